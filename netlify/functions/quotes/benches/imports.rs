@@ -0,0 +1,60 @@
+//! Benchmarks for parsing large import bodies. Compare `cargo bench --bench imports` with
+//! `cargo bench --bench imports --features simd`; with the feature, the simd-json and
+//! serde_json parsers are also measured side by side.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use quotes_api::imports;
+use serde_json::{json, Value};
+
+/// About 5 MB of quotes as a JSON array and as NDJSON.
+fn bodies() -> (Vec<u8>, Vec<u8>) {
+    let quotes: Vec<Value> = (0..25_000)
+        .map(|i| {
+            json!({
+                "quote": format!("Make it so, number {}. Tea, Earl Grey, hot. Engage!", i),
+                "characters": ["Picard", "Riker"],
+                "stardate": format!("{}.{}", 41000 + i % 1000, i % 10),
+                "episode": i % 178,
+                "lang": "en",
+            })
+        })
+        .collect();
+    let array = serde_json::to_vec(&quotes).unwrap();
+    let ndjson = quotes
+        .iter()
+        .map(|quote| quote.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .into_bytes();
+    (array, ndjson)
+}
+
+fn parsing(c: &mut Criterion) {
+    let (array, ndjson) = bodies();
+
+    let mut group = c.benchmark_group("import_parsing");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(array.len() as u64));
+    group.bench_function("parse_json", |b| {
+        b.iter(|| imports::parse_json(black_box(&array)).unwrap())
+    });
+    group.bench_function("parse_ndjson", |b| {
+        b.iter(|| imports::parse_ndjson(black_box(&ndjson)).unwrap())
+    });
+    #[cfg(feature = "simd")]
+    {
+        group.bench_function("serde_json", |b| {
+            b.iter(|| serde_json::from_slice::<Vec<Value>>(black_box(&array)).unwrap())
+        });
+        group.bench_function("simd_json", |b| {
+            b.iter(|| {
+                let mut body = array.clone();
+                simd_json::serde::from_slice::<Vec<Value>>(black_box(&mut body)).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+//! Benchmarks for the per-quote work of the list path: JSON (de)serialization, row mapping
+//! and statement building, plus the stardate conversion of imports. Run with `cargo bench`.
+//!
+//! Row mapping needs real rows, so it only runs when `BENCH_DATABASE_URL` is set. The rows
+//! are generated by the query itself; no table is read.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use quotes_api::quotes::{self, AsOf, Line, NaturalKey, Quote};
+use quotes_api::stardate;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use uuid::Uuid;
+
+const PAGE: usize = 100;
+
+fn quote(i: usize) -> Quote {
+    Quote {
+        rowid: Some(780_000_000_000_000_000 + i as i64),
+        uuid: Some(Uuid::from_u128(i as u128)),
+        slug: Some(format!("make-it-so-{}", i)),
+        quote: Some(format!("Make it so, number {}. Engage!", i)),
+        characters: Some(vec!["Picard".to_string(), "Riker".to_string()]),
+        stardate: Some(Decimal::new(411_537 + i as i64, 1)),
+        episode: Some(i as i64),
+        lang: Some("en".to_string()),
+        created_by: Some("auth0|5f1c0ffee".to_string()),
+        lines: (i % 10 == 0).then(|| {
+            vec![
+                Line {
+                    speaker: Some("Picard".to_string()),
+                    text: "Make it so.".to_string(),
+                },
+                Line {
+                    speaker: Some("Riker".to_string()),
+                    text: "Aye, sir.".to_string(),
+                },
+            ]
+        }),
+        highlight: None,
+    }
+}
+
+fn serialization(c: &mut Criterion) {
+    let page: Vec<Quote> = (0..PAGE).map(quote).collect();
+    let json = serde_json::to_string(&page).unwrap();
+
+    let mut group = c.benchmark_group("serialization");
+    group.throughput(Throughput::Elements(PAGE as u64));
+    group.bench_function("serialize_page", |b| {
+        b.iter(|| serde_json::to_string(black_box(&page)).unwrap())
+    });
+    group.bench_function("serialize_page_to_value", |b| {
+        b.iter(|| serde_json::to_value(black_box(&page)).unwrap())
+    });
+    group.bench_function("deserialize_page", |b| {
+        b.iter(|| serde_json::from_str::<Vec<Quote>>(black_box(&json)).unwrap())
+    });
+    group.finish();
+}
+
+fn stardates(c: &mut Criterion) {
+    let values: Vec<f64> = (0..PAGE).map(|i| 41_153.7 + i as f64 * 0.1).collect();
+    let import = serde_json::to_string(
+        &(0..PAGE)
+            .map(|i| serde_json::json!({ "quote": "Make it so.", "stardate": values[i] }))
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("stardates");
+    group.throughput(Throughput::Elements(PAGE as u64));
+    group.bench_function("from_f64", |b| {
+        b.iter(|| {
+            black_box(&values)
+                .iter()
+                .map(|&value| stardate::from_f64(value))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("from_f64_via_text", |b| {
+        b.iter(|| {
+            black_box(&values)
+                .iter()
+                .map(|value| Decimal::from_str(&value.to_string()).ok())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("deserialize_import", |b| {
+        b.iter(|| serde_json::from_str::<Vec<Quote>>(black_box(&import)).unwrap())
+    });
+    group.finish();
+}
+
+fn statements(c: &mut Criterion) {
+    let as_of = AsOf::parse("1656430740123456789.0000000001").unwrap();
+    let changes = quote(1);
+    let key = NaturalKey {
+        episode: Some(42),
+        character: Some("Picard".to_string()),
+        stardate: Some(Decimal::new(411_537, 1)),
+    };
+
+    let mut group = c.benchmark_group("statements");
+    group.bench_function("columns", |b| {
+        b.iter(|| quotes::columns(black_box(Some("q"))))
+    });
+    group.bench_function("list_quotes_sql", |b| {
+        b.iter(|| quotes::list_quotes_sql(black_box(Some(&as_of))))
+    });
+    group.bench_function("update_quote_sql", |b| {
+        b.iter(|| {
+            quotes::update_quote_sql(black_box(&1), black_box(&changes)).map(|update| update.0)
+        })
+    });
+    group.bench_function("lookup_quotes_sql", |b| {
+        b.iter(|| quotes::lookup_quotes_sql(black_box(&key)).0)
+    });
+    group.finish();
+}
+
+fn row_mapping(c: &mut Criterion) {
+    let url = match std::env::var("BENCH_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    std::env::set_var("DATABASE_URL", url);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let rows = runtime.block_on(async {
+        let client = quotes_api::db::get_db_client().await.unwrap();
+        client
+            .query(
+                "SELECT i AS rowid, gen_random_uuid() AS uuid, 'make-it-so-' || i::STRING AS slug, 'Make it so, number ' || i::STRING || '. Engage!' AS quote, ARRAY['Picard', 'Riker'] AS characters, (41153.7 + i)::DECIMAL AS stardate, i AS episode, 'en' AS lang, 'auth0|5f1c0ffee' AS created_by FROM generate_series(1, $1) AS i;",
+                &[&(PAGE as i64)],
+            )
+            .await
+            .unwrap()
+    });
+
+    let mut group = c.benchmark_group("row_mapping");
+    group.throughput(Throughput::Elements(rows.len() as u64));
+    group.bench_function("quote_from_row", |b| {
+        b.iter(|| {
+            black_box(&rows)
+                .iter()
+                .map(|row| quotes::quote_from_row(row, "bench").unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialization, stardates, statements, row_mapping);
+criterion_main!(benches);
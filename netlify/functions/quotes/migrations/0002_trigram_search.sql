@@ -0,0 +1,3 @@
+-- Trigram inverted indexes backing fuzzy search with `?q=`.
+CREATE INDEX IF NOT EXISTS quotes_quote_trgm_idx ON quotes USING GIN (quote gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS quotes_characters_trgm_idx ON quotes USING GIN (characters gin_trgm_ops);
@@ -0,0 +1,6 @@
+-- Quotes picked by the `quotes-qotd` publisher, one per day.
+CREATE TABLE IF NOT EXISTS qotd (
+    day DATE PRIMARY KEY,
+    quote_rowid INT8 NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- Records when each quote was added, used by the Atom feed. Existing quotes get the time of the migration.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+CREATE INDEX IF NOT EXISTS quotes_created_at_idx ON quotes (created_at DESC);
@@ -0,0 +1,10 @@
+-- Stores the speakers of a quote as an array so a dialogue can credit several characters.
+-- `characters_text` keeps every speaker in one string for the trigram search index.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS characters_list STRING[];
+UPDATE quotes SET characters_list = ARRAY[characters] WHERE characters IS NOT NULL;
+DROP INDEX IF EXISTS quotes_characters_trgm_idx;
+ALTER TABLE quotes DROP COLUMN characters;
+ALTER TABLE quotes RENAME COLUMN characters_list TO characters;
+ALTER TABLE quotes ADD COLUMN characters_text STRING AS (array_to_string(characters, ', ')) STORED;
+CREATE INDEX IF NOT EXISTS quotes_characters_text_trgm_idx ON quotes USING GIN (characters_text gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS quotes_characters_idx ON quotes USING GIN (characters);
@@ -0,0 +1,8 @@
+-- Optional dialogue lines of a quote, in the order they are spoken.
+CREATE TABLE IF NOT EXISTS quote_lines (
+    quote_rowid INT8 NOT NULL,
+    position INT8 NOT NULL,
+    speaker STRING,
+    text STRING NOT NULL,
+    PRIMARY KEY (quote_rowid, position)
+);
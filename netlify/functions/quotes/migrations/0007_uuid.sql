@@ -0,0 +1,3 @@
+-- Public identifier of each quote, used in URLs and payloads instead of the rowid when `UUID_IDS=true`.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS uuid UUID NOT NULL DEFAULT gen_random_uuid();
+CREATE UNIQUE INDEX IF NOT EXISTS quotes_uuid_idx ON quotes (uuid);
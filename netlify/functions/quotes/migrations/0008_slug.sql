@@ -0,0 +1,5 @@
+-- Human-friendly identifier generated from the quote text, used in `/quotes/{slug}` URLs.
+-- Existing quotes get a random suffix so their slugs are unique without exposing the rowid.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS slug STRING;
+UPDATE quotes SET slug = concat(trim(both '-' from left(regexp_replace(lower(quote), '[^a-z0-9]+', '-', 'g'), 60)), '-', substr(gen_random_uuid()::STRING, 1, 8)) WHERE slug IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS quotes_slug_idx ON quotes (slug);
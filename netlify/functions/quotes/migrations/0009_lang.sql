@@ -0,0 +1,3 @@
+-- Language of each quote's text as an ISO 639-1 code where one exists, detected on insert.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS lang STRING;
+CREATE INDEX IF NOT EXISTS quotes_lang_idx ON quotes (lang);
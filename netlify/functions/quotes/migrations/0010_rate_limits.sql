@@ -0,0 +1,5 @@
+-- Time of the last request per client key, used to throttle anonymous submissions.
+CREATE TABLE IF NOT EXISTS rate_limits (
+    key STRING PRIMARY KEY,
+    last_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- JWT subject of whoever submitted each quote; NULL for quotes added without one.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS created_by STRING;
+CREATE INDEX IF NOT EXISTS quotes_created_by_idx ON quotes (created_by, created_at DESC);
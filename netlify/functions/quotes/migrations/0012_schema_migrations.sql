@@ -0,0 +1,9 @@
+-- Records which migrations have been applied, checked by the self-check against the binary's
+-- schema version. Every later migration ends by inserting its own number.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INT8 PRIMARY KEY,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+-- Version 1 was the regional-by-row migration, now optional, so it is not recorded.
+INSERT INTO schema_migrations (version)
+    SELECT generate_series(2, 12) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,20 @@
+-- Progress of each `POST /quotes/import`, so an import cut short by the Lambda timeout
+-- resumes from its last committed chunk. `payload` holds the submitted quotes until the
+-- import is done.
+CREATE TABLE IF NOT EXISTS import_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_by STRING,
+    idempotency_key STRING,
+    status STRING NOT NULL DEFAULT 'running',
+    payload JSONB NOT NULL,
+    rows_total INT8 NOT NULL,
+    rows_processed INT8 NOT NULL DEFAULT 0,
+    bytes_total INT8 NOT NULL,
+    bytes_processed INT8 NOT NULL DEFAULT 0,
+    last_key INT8,
+    failures JSONB NOT NULL DEFAULT '[]',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (created_by, idempotency_key)
+);
+INSERT INTO schema_migrations (version) VALUES (13) ON CONFLICT (version) DO NOTHING;
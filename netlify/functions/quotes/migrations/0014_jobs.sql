@@ -0,0 +1,31 @@
+-- Long-running work split into steps across invocations: imports and backups. Imports move
+-- here from import_jobs and keep their ids, so /imports/<id> URLs stay valid.
+CREATE TABLE IF NOT EXISTS jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind STRING NOT NULL,
+    status STRING NOT NULL DEFAULT 'queued',
+    created_by STRING,
+    idempotency_key STRING,
+    input JSONB NOT NULL DEFAULT '{}',
+    progress JSONB NOT NULL DEFAULT '{}',
+    error STRING,
+    steps INT8 NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (kind, created_by, idempotency_key)
+);
+INSERT INTO jobs (id, kind, status, created_by, idempotency_key, input, progress, created_at, updated_at)
+    SELECT id, 'import', status, created_by, idempotency_key, payload,
+        jsonb_build_object(
+            'rows_total', rows_total,
+            'rows_processed', rows_processed,
+            'bytes_total', bytes_total,
+            'bytes_processed', bytes_processed,
+            'last_key', last_key::STRING,
+            'failures', failures
+        ),
+        created_at, updated_at
+    FROM import_jobs
+    ON CONFLICT (id) DO NOTHING;
+DROP TABLE IF EXISTS import_jobs;
+INSERT INTO schema_migrations (version) VALUES (14) ON CONFLICT (version) DO NOTHING;
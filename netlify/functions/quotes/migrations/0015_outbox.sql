@@ -0,0 +1,14 @@
+-- Events recorded with the changes they describe, delivered by the quotes-outbox Lambda.
+CREATE TABLE IF NOT EXISTS outbox (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event STRING NOT NULL,
+    dedupe_key STRING UNIQUE,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INT8 NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    last_error STRING,
+    INDEX outbox_due_idx (next_attempt_at) WHERE delivered_at IS NULL
+);
+INSERT INTO schema_migrations (version) VALUES (15) ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,8 @@
+-- Indexes for the episode and stardate filters and orderings. Listing by episode reads
+-- every column, so its index stores them and pages are served without a join back to the
+-- primary index. Characters and trigram search already have theirs, from 0002 and 0005.
+CREATE INDEX IF NOT EXISTS quotes_episode_idx ON quotes (episode, rowid)
+    STORING (uuid, slug, quote, characters, stardate, lang, created_by);
+CREATE INDEX IF NOT EXISTS quotes_episode_stardate_idx ON quotes (episode, stardate, rowid);
+CREATE INDEX IF NOT EXISTS quotes_stardate_idx ON quotes (stardate, rowid) WHERE stardate IS NOT NULL;
+INSERT INTO schema_migrations (version) VALUES (16) ON CONFLICT (version) DO NOTHING;
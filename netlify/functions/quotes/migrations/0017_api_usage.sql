@@ -0,0 +1,12 @@
+-- Requests, quotes read and quotes written per caller and UTC day, upserted in batches by
+-- each function instance when USAGE_TRACKING=true.
+CREATE TABLE IF NOT EXISTS api_usage (
+    caller STRING NOT NULL,
+    day DATE NOT NULL,
+    requests INT8 NOT NULL DEFAULT 0,
+    rows_read INT8 NOT NULL DEFAULT 0,
+    rows_written INT8 NOT NULL DEFAULT 0,
+    PRIMARY KEY (caller, day),
+    INDEX api_usage_day_idx (day)
+);
+INSERT INTO schema_migrations (version) VALUES (17) ON CONFLICT (version) DO NOTHING;
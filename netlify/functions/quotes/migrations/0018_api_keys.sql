@@ -0,0 +1,12 @@
+-- Quotas per caller, named as in api_usage, such as 'key:<digest>' for an X-Api-Key. A NULL
+-- quota is unlimited, and callers without a row have no quotas.
+CREATE TABLE IF NOT EXISTS api_keys (
+    caller STRING PRIMARY KEY,
+    name STRING,
+    daily_requests INT8,
+    monthly_requests INT8,
+    daily_writes INT8,
+    monthly_writes INT8,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+INSERT INTO schema_migrations (version) VALUES (18) ON CONFLICT (version) DO NOTHING;
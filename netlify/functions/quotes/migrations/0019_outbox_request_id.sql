@@ -0,0 +1,3 @@
+-- The id of the request that made each change, delivered with its event.
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS request_id STRING;
+INSERT INTO schema_migrations (version) VALUES (19) ON CONFLICT (version) DO NOTHING;
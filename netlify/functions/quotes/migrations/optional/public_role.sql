@@ -0,0 +1,7 @@
+-- Optional, not part of the numbered migrations. A SQL role that may only read the tables
+-- behind the public routes, for DATABASE_PUBLIC_URL. Unauthenticated GET requests connect
+-- as this role, so a flaw on the public path cannot write. Set a password of your own and
+-- put it in the URL, such as postgresql://quotes_public:<password>@<host>:26257/defaultdb.
+CREATE USER IF NOT EXISTS quotes_public WITH PASSWORD NULL;
+GRANT SELECT ON TABLE quotes, quote_lines, qotd, jobs TO quotes_public;
+-- ALTER USER quotes_public WITH PASSWORD '<password>';
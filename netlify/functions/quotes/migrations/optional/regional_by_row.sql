@@ -0,0 +1,9 @@
+-- Optional, not part of the numbered migrations. Makes the quotes table REGIONAL BY ROW so
+-- each quote is homed in the region it was written from, for REGIONAL_BY_ROW=true. Needs a
+-- multi-region cluster; replace the database name and regions with the ones configured for
+-- your cluster.
+ALTER DATABASE defaultdb SET PRIMARY REGION "aws-us-east-1";
+ALTER DATABASE defaultdb ADD REGION "aws-us-west-2";
+ALTER DATABASE defaultdb ADD REGION "aws-eu-west-1";
+
+ALTER TABLE quotes SET LOCALITY REGIONAL BY ROW;
@@ -0,0 +1,5 @@
+-- Optional, not part of the numbered migrations. Keys quotes by their random uuid, so
+-- inserts are spread over the whole table. The rowid primary index is kept as a unique
+-- secondary index, so lookups by rowid take an extra index join. Pairs well with
+-- UUID_IDS=true. Compare before and after with `loadgen --keys`.
+ALTER TABLE quotes ALTER PRIMARY KEY USING COLUMNS (uuid);
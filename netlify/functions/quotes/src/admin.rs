@@ -0,0 +1,729 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::db::{DbError, StatementContext};
+use crate::jobs::{self, Job, Kind, Step};
+use crate::router::Params;
+use crate::{db, quotes, response, schema, types};
+
+// Tables owned by this service, reported by the schema endpoint.
+const TABLES: &[&str] = &["quotes"];
+
+#[derive(Serialize)]
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+}
+
+#[derive(Serialize)]
+struct Column {
+    name: String,
+    data_type: String,
+    nullable: bool,
+    default: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Index {
+    name: String,
+    unique: bool,
+    columns: Vec<IndexColumn>,
+}
+
+#[derive(Serialize)]
+struct IndexColumn {
+    name: String,
+    direction: Option<String>,
+    storing: bool,
+}
+
+/// Runs `EXPLAIN ANALYZE` for the query behind `?route=` and returns the plan text.
+pub async fn explain(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let params = &event.query_string_parameters;
+    let rows = match params.first("route") {
+        Some("list") => {
+            let sql = format!("EXPLAIN ANALYZE {}", quotes::list_quotes_sql(None));
+            client
+                .query(
+                    sql.as_str(),
+                    &[&quotes::page_size(None), &0i64, &params.first("lang")],
+                )
+                .await
+                .statement("explain")?
+        }
+        Some("search") => match params.first("q") {
+            Some(q) => {
+                let sql = format!("EXPLAIN ANALYZE {}", quotes::search_quotes_sql(None));
+                client
+                    .query(
+                        sql.as_str(),
+                        &[&q, &quotes::page_size(None), &0i64, &params.first("lang")],
+                    )
+                    .await
+                    .statement("explain")?
+            }
+            None => return Ok(response::text(400, "q is required")),
+        },
+        Some("get") => match params.first("rowid") {
+            Some(rowid) => {
+                let rowid: i64 = match rowid.parse() {
+                    Ok(rowid) => rowid,
+                    Err(_) => {
+                        return Ok(response::problem(
+                            400,
+                            "Bad Request",
+                            "rowid must be an integer.",
+                        ))
+                    }
+                };
+                let sql = format!("EXPLAIN ANALYZE {}", quotes::get_quote_sql());
+                client
+                    .query(sql.as_str(), &[&rowid])
+                    .await
+                    .statement("explain")?
+            }
+            None => return Ok(response::rowid_required()),
+        },
+        _ => {
+            return Ok(response::text(
+                400,
+                "route must be one of: list, search, get",
+            ))
+        }
+    };
+
+    let plan: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    Ok(response::text(200, plan.join("\n")))
+}
+
+/// Reports the columns and indexes of the service's tables from `information_schema`.
+pub async fn schema(client: &Client) -> Result<ApiGatewayProxyResponse, Error> {
+    let mut tables: Vec<Table> = TABLES
+        .iter()
+        .map(|name| Table {
+            name: name.to_string(),
+            columns: Vec::new(),
+            indexes: Vec::new(),
+        })
+        .collect();
+
+    let columns = client
+        .query(
+            "SELECT table_name, column_name, data_type, is_nullable, column_default FROM information_schema.columns WHERE table_schema = 'public' AND table_name = ANY($1) ORDER BY table_name, ordinal_position;",
+            &[&TABLES],
+        )
+        .await
+        .statement("schema_columns")?;
+    for row in columns {
+        let table_name: String = row.get(0);
+        if let Some(table) = tables.iter_mut().find(|t| t.name == table_name) {
+            let nullable: String = row.get(3);
+            table.columns.push(Column {
+                name: row.get(1),
+                data_type: row.get(2),
+                nullable: nullable == "YES",
+                default: row.get(4),
+            });
+        }
+    }
+
+    let indexes = client
+        .query(
+            "SELECT table_name, index_name, non_unique::STRING, column_name, direction, storing::STRING FROM information_schema.statistics WHERE table_schema = 'public' AND table_name = ANY($1) ORDER BY table_name, index_name, seq_in_index;",
+            &[&TABLES],
+        )
+        .await
+        .statement("schema_indexes")?;
+    for row in indexes {
+        let table_name: String = row.get(0);
+        let index_name: String = row.get(1);
+        let non_unique: String = row.get(2);
+        let storing: String = row.get(5);
+        let column = IndexColumn {
+            name: row.get(3),
+            direction: row.get(4),
+            storing: storing == "YES" || storing == "true",
+        };
+
+        if let Some(table) = tables.iter_mut().find(|t| t.name == table_name) {
+            match table.indexes.iter_mut().find(|i| i.name == index_name) {
+                Some(index) => index.columns.push(column),
+                None => table.indexes.push(Index {
+                    name: index_name,
+                    unique: non_unique == "NO" || non_unique == "false",
+                    columns: vec![column],
+                }),
+            }
+        }
+    }
+
+    Ok(response::json(200, serde_json::to_string(&tables)?))
+}
+
+/// `?name=` as an integer, or `default` when it is not given. Anything else gets a `400`.
+fn integer_param<T: FromStr>(
+    event: &ApiGatewayProxyRequest,
+    name: &str,
+    default: T,
+) -> Result<T, ApiGatewayProxyResponse> {
+    match event.query_string_parameters.first(name) {
+        Some(value) => value.parse().map_err(|_| {
+            response::problem(400, "Bad Request", &format!("{} must be an integer.", name))
+        }),
+        None => Ok(default),
+    }
+}
+
+/// Progress of a repair run; pass `next_after` back as `?after=` to continue.
+#[derive(Serialize)]
+struct RepairReport {
+    fixes: Vec<String>,
+    batches: u32,
+    scanned: i64,
+    updated: u64,
+    next_after: Option<i64>,
+    done: bool,
+}
+
+const REPAIR_FIXES: &[&str] = &["trim", "title_case", "stardate_precision"];
+
+/// Normalizes quotes in rowid order, one `UPDATE` per batch so each batch commits on its own.
+///
+/// `?fixes=` picks from `trim` (strip and collapse whitespace), `title_case` (character names)
+/// and `stardate_precision` (round to `stardate_scale` places, default 1). A run stops after
+/// `max_batches` batches and reports where to resume.
+pub async fn repair(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let params = &event.query_string_parameters;
+    let fixes: Vec<String> = params
+        .first("fixes")
+        .unwrap_or("trim,title_case,stardate_precision")
+        .split(',')
+        .map(|fix| fix.trim().to_string())
+        .filter(|fix| !fix.is_empty())
+        .collect();
+    if let Some(unknown) = fixes
+        .iter()
+        .find(|fix| !REPAIR_FIXES.contains(&fix.as_str()))
+    {
+        return Ok(response::text(
+            400,
+            format!(
+                "unknown fix {}; expected {}",
+                unknown,
+                REPAIR_FIXES.join(", ")
+            ),
+        ));
+    }
+    let (batch_size, max_batches, stardate_scale, mut after) = match (
+        integer_param(event, "batch_size", 500i64),
+        integer_param(event, "max_batches", 20u32),
+        integer_param(event, "stardate_scale", 1i32),
+        integer_param(event, "after", i64::MIN),
+    ) {
+        (Ok(batch_size), Ok(max_batches), Ok(stardate_scale), Ok(after)) => (
+            batch_size.clamp(1, 10_000),
+            max_batches.max(1),
+            stardate_scale.clamp(0, 10),
+            after,
+        ),
+        (Err(resp), ..) | (_, Err(resp), ..) | (_, _, Err(resp), _) | (.., Err(resp)) => {
+            return Ok(resp)
+        }
+    };
+
+    let has = |fix: &str| fixes.iter().any(|f| f == fix);
+    let mut quote = String::from("quote");
+    let mut characters = String::from("name");
+    let mut stardate = String::from("stardate");
+    if has("trim") {
+        quote = format!("regexp_replace(trim({}), '\\s+', ' ', 'g')", quote);
+        characters = format!("regexp_replace(trim({}), '\\s+', ' ', 'g')", characters);
+    }
+    if has("title_case") {
+        characters = format!("initcap({})", characters);
+    }
+    if has("stardate_precision") {
+        stardate = format!("round({}, {})", stardate, stardate_scale);
+    }
+    // Fixes apply to each speaker of a dialogue, keeping their order.
+    let characters = format!(
+        "CASE WHEN characters IS NULL THEN NULL ELSE ARRAY(SELECT {} FROM unnest(characters) WITH ORDINALITY AS names (name, n) ORDER BY n) END",
+        characters
+    );
+    let update = format!(
+        "UPDATE quotes SET quote = {q}, characters = {c}, stardate = {s} WHERE rowid > $1 AND rowid <= $2 AND (quote IS DISTINCT FROM {q} OR characters IS DISTINCT FROM {c} OR stardate IS DISTINCT FROM {s});",
+        q = quote,
+        c = characters,
+        s = stardate,
+    );
+
+    let mut report = RepairReport {
+        fixes,
+        batches: 0,
+        scanned: 0,
+        updated: 0,
+        next_after: None,
+        done: false,
+    };
+
+    while report.batches < max_batches {
+        let row = client
+            .query_one(
+                "SELECT max(rowid), count(*) FROM (SELECT rowid FROM quotes WHERE rowid > $1 ORDER BY rowid LIMIT $2);",
+                &[&after, &batch_size],
+            )
+            .await
+            .statement("repair_scan")?;
+        let (last, scanned): (Option<i64>, i64) = (row.get(0), row.get(1));
+        let last = match last {
+            Some(last) => last,
+            None => {
+                report.done = true;
+                break;
+            }
+        };
+
+        let updated = client
+            .execute(update.as_str(), &[&after, &last])
+            .await
+            .statement("repair_update")?;
+        report.batches += 1;
+        report.scanned += scanned;
+        report.updated += updated;
+        after = last;
+        log::info!(
+            "repair batch {}: scanned {}, updated {}, through rowid {}",
+            report.batches,
+            scanned,
+            updated,
+            last
+        );
+
+        if scanned < batch_size {
+            report.done = true;
+            break;
+        }
+    }
+    if !report.done {
+        report.next_after = Some(after);
+    }
+
+    Ok(response::json(200, serde_json::to_string(&report)?))
+}
+
+// Tables included in a cluster backup.
+const BACKUP_TABLES: &str = "quotes, quote_lines, qotd";
+
+/// The `BACKUP_URI` destination with the `BACKUP_AWS_*` credentials appended, or `AUTH=implicit`
+/// when none are configured.
+fn backup_uri() -> Option<String> {
+    let uri = std::env::var("BACKUP_URI")
+        .ok()
+        .filter(|uri| !uri.is_empty())?;
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    match (
+        std::env::var("BACKUP_AWS_ACCESS_KEY_ID"),
+        std::env::var("BACKUP_AWS_SECRET_ACCESS_KEY"),
+    ) {
+        (Ok(key_id), Ok(secret)) => {
+            query.append_pair("AWS_ACCESS_KEY_ID", &key_id);
+            query.append_pair("AWS_SECRET_ACCESS_KEY", &secret);
+        }
+        _ => {
+            query.append_pair("AUTH", "implicit");
+        }
+    }
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    Some(format!("{}{}{}", uri, separator, query.finish()))
+}
+
+/// Starts a `backup` job, which runs a detached `BACKUP` of the service's tables into
+/// `BACKUP_URI` and follows it until CockroachDB reports it finished.
+pub async fn backup(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    if backup_uri().is_none() {
+        return Ok(response::problem(
+            501,
+            "Not Implemented",
+            "Backups are not configured.",
+        ));
+    }
+    let key = event
+        .headers
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok());
+    let job = jobs::create(
+        client,
+        Kind::Backup,
+        principal.subject.as_deref(),
+        key,
+        serde_json::json!({}),
+        serde_json::json!({}),
+    )
+    .await?;
+    let id = job.id;
+    let job = jobs::work(client, id).await?.unwrap_or(job);
+    Ok(jobs::response(event, &job))
+}
+
+/// The `progress` of a backup job.
+#[serde_as]
+#[derive(Default, Serialize, Deserialize)]
+struct BackupProgress {
+    /// The id of the CockroachDB `BACKUP` job, once started.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    backup_job_id: Option<i64>,
+    backup_status: Option<String>,
+    fraction_completed: Option<f64>,
+}
+
+/// Starts the `BACKUP` on the first step, then polls `SHOW JOBS` until it has finished.
+pub async fn backup_step(client: &Client, job: &Job) -> Result<Step, DbError> {
+    let mut progress: BackupProgress =
+        serde_json::from_value(job.progress.clone()).unwrap_or_default();
+
+    let backup_job_id = match progress.backup_job_id {
+        Some(backup_job_id) => backup_job_id,
+        None => {
+            let uri = match backup_uri() {
+                Some(uri) => uri,
+                None => {
+                    return Ok(Step::Failed(
+                        job.progress.clone(),
+                        String::from("Backups are not configured."),
+                    ))
+                }
+            };
+            let row = client
+                .query_one(
+                    format!("BACKUP TABLE {} INTO $1 WITH detached;", BACKUP_TABLES).as_str(),
+                    &[&uri],
+                )
+                .await
+                .statement("backup")?;
+            let backup_job_id: i64 = row
+                .try_get("job_id")
+                .map_err(|e| DbError::mapping("backup", e))?;
+            log::info!("started backup job {}", backup_job_id);
+            progress.backup_job_id = Some(backup_job_id);
+            progress.backup_status = Some(String::from("running"));
+            return Ok(Step::Wait(
+                serde_json::to_value(progress).unwrap_or_default(),
+            ));
+        }
+    };
+
+    let row = client
+        .query_opt(
+            "SELECT status, fraction_completed, error FROM [SHOW JOBS] WHERE job_id = $1;",
+            &[&backup_job_id],
+        )
+        .await
+        .statement("backup_status")?;
+    let row = match row {
+        Some(row) => row,
+        None => {
+            return Ok(Step::Failed(
+                job.progress.clone(),
+                format!("backup job {} no longer exists", backup_job_id),
+            ))
+        }
+    };
+    let status: String = row.get(0);
+    let error: Option<String> = row.get(2);
+    progress.fraction_completed = row.get(1);
+    progress.backup_status = Some(status.clone());
+    let progress = serde_json::to_value(progress).unwrap_or_default();
+
+    Ok(match status.as_str() {
+        "succeeded" => Step::Done(progress),
+        "failed" | "canceled" => Step::Failed(
+            progress,
+            error
+                .filter(|error| !error.is_empty())
+                .unwrap_or_else(|| format!("backup {}", status)),
+        ),
+        _ => Step::Wait(progress),
+    })
+}
+
+/// Reports the status of the job in the `{job}` segment from `SHOW JOBS`.
+pub async fn backup_status(
+    params: &Params,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let job_id: i64 = match params.get("job").map(str::parse) {
+        Some(Ok(job_id)) => job_id,
+        _ => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "job must be a job id.",
+            ))
+        }
+    };
+    let row = client
+        .query_opt(
+            "SELECT job_type, status, fraction_completed, error, created::STRING, finished::STRING FROM [SHOW JOBS] WHERE job_id = $1;",
+            &[&job_id],
+        )
+        .await
+        .statement("backup_status")?;
+    let row = match row {
+        Some(row) => row,
+        None => {
+            return Ok(response::not_found(&format!(
+                "Job {} does not exist.",
+                job_id
+            )))
+        }
+    };
+    let job_type: String = row.get(0);
+    if job_type != "BACKUP" {
+        return Ok(response::not_found(&format!(
+            "Job {} is not a backup.",
+            job_id
+        )));
+    }
+
+    let error: Option<String> = row.get(3);
+    let body = serde_json::json!({
+        "data": {
+            "job_id": job_id.to_string(),
+            "status": row.get::<_, String>(1),
+            "fraction_completed": row.get::<_, Option<f64>>(2),
+            "error": error.filter(|error| !error.is_empty()),
+            "created": row.get::<_, Option<String>>(4),
+            "finished": row.get::<_, Option<String>>(5),
+        }
+    });
+    Ok(response::json(200, body.to_string()))
+}
+
+#[derive(Default, Serialize)]
+struct ClusterStatus {
+    nodes: Option<Vec<Node>>,
+    /// Unfinished jobs by status, such as `running` or `paused`.
+    jobs: Option<HashMap<String, i64>>,
+    quotes_ranges: Option<i64>,
+    /// Why a section is missing, for instance because the SQL user may not read it.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    errors: HashMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    node_id: i64,
+    address: String,
+    is_live: bool,
+}
+
+/// Reports node liveness, unfinished jobs and the range count of `quotes`. Each part is read
+/// on its own, so a part the connection may not see is reported under `errors`.
+pub async fn cluster(client: &Client) -> Result<ApiGatewayProxyResponse, Error> {
+    let mut status = ClusterStatus::default();
+
+    match client
+        .query(
+            "SELECT node_id::INT8, address, is_live FROM crdb_internal.gossip_nodes ORDER BY node_id;",
+            &[],
+        )
+        .await
+    {
+        Ok(rows) => {
+            status.nodes = Some(
+                rows.iter()
+                    .map(|row| Node {
+                        node_id: row.get(0),
+                        address: row.get(1),
+                        is_live: row.get(2),
+                    })
+                    .collect(),
+            )
+        }
+        Err(e) => {
+            status.errors.insert("nodes", e.to_string());
+        }
+    }
+
+    match client
+        .query(
+            "SELECT status, count(*) FROM [SHOW JOBS] WHERE finished IS NULL GROUP BY status;",
+            &[],
+        )
+        .await
+    {
+        Ok(rows) => status.jobs = Some(rows.iter().map(|row| (row.get(0), row.get(1))).collect()),
+        Err(e) => {
+            status.errors.insert("jobs", e.to_string());
+        }
+    }
+
+    match client
+        .query_one("SELECT count(*) FROM [SHOW RANGES FROM TABLE quotes];", &[])
+        .await
+    {
+        Ok(row) => status.quotes_ranges = Some(row.get(0)),
+        Err(e) => {
+            status.errors.insert("quotes_ranges", e.to_string());
+        }
+    }
+
+    Ok(response::json(200, serde_json::to_string(&status)?))
+}
+
+/// Compares the database schema with the one this binary expects; `503` when they differ.
+pub async fn selfcheck(client: &Client) -> Result<ApiGatewayProxyResponse, Error> {
+    let report = schema::check(client).await?;
+    let status = if report.ok { 200 } else { 503 };
+    Ok(response::json(status, serde_json::to_string(&report)?))
+}
+
+/// What `GET /admin/indexes` found. Parts the SQL user may not read, or that the cluster
+/// version lacks, are listed under `errors` instead.
+#[derive(Default, Serialize)]
+struct IndexAdvice {
+    /// CockroachDB's index recommendations for the statements it has seen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recommendations: Option<Vec<Recommendation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unused: Option<Vec<UnusedIndex>>,
+    /// Indexes from `migrations/` that the database does not have.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    errors: HashMap<&'static str, String>,
+}
+
+#[derive(Serialize)]
+struct Recommendation {
+    /// Such as `creation : CREATE INDEX ON quotes (episode) STORING (quote);`.
+    recommendation: String,
+    /// How many statement fingerprints it would help.
+    statements: i64,
+}
+
+#[derive(Serialize)]
+struct UnusedIndex {
+    table: String,
+    index: String,
+    total_reads: i64,
+    last_read: Option<DateTime<Utc>>,
+}
+
+/// Reports CockroachDB's index recommendations, secondary indexes of the service's tables
+/// not read within `?unused_days=` (default 30), and expected indexes that are missing.
+pub async fn indexes(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let unused_days: i64 = match integer_param(event, "unused_days", 30) {
+        Ok(days) => days,
+        Err(resp) => return Ok(resp),
+    };
+    let tables: Vec<&str> = schema::INDEXES.iter().map(|(table, _)| *table).collect();
+    let mut advice = IndexAdvice::default();
+
+    match client
+        .query(
+            "SELECT recommendation, count(*) FROM (SELECT DISTINCT fingerprint_id, unnest(index_recommendations) AS recommendation FROM crdb_internal.statement_statistics) GROUP BY recommendation ORDER BY count(*) DESC, recommendation;",
+            &[],
+        )
+        .await
+    {
+        Ok(rows) => {
+            advice.recommendations = Some(
+                rows.iter()
+                    .map(|row| Recommendation {
+                        recommendation: row.get(0),
+                        statements: row.get(1),
+                    })
+                    .collect(),
+            )
+        }
+        Err(e) => {
+            advice.errors.insert("recommendations", e.to_string());
+        }
+    }
+
+    // Indexes never read have no usage row at all.
+    match client
+        .query(
+            "SELECT ti.descriptor_name, ti.index_name, COALESCE(us.total_reads, 0)::INT8, us.last_read FROM crdb_internal.table_indexes AS ti LEFT JOIN crdb_internal.index_usage_statistics AS us ON us.table_id = ti.descriptor_id AND us.index_id = ti.index_id WHERE ti.index_type = 'secondary' AND ti.descriptor_name = ANY($1) AND (us.last_read IS NULL OR us.last_read < now() - $2 * INTERVAL '1 day') ORDER BY ti.descriptor_name, ti.index_name;",
+            &[&tables, &unused_days],
+        )
+        .await
+    {
+        Ok(rows) => {
+            advice.unused = Some(
+                rows.iter()
+                    .map(|row| UnusedIndex {
+                        table: row.get(0),
+                        index: row.get(1),
+                        total_reads: row.get(2),
+                        last_read: row.get(3),
+                    })
+                    .collect(),
+            )
+        }
+        Err(e) => {
+            advice.errors.insert("unused", e.to_string());
+        }
+    }
+
+    match client
+        .query(
+            "SELECT table_name, index_name FROM information_schema.statistics WHERE table_schema = 'public' AND table_name = ANY($1);",
+            &[&tables],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let found: Vec<(String, String)> =
+                rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+            advice.missing = Some(
+                schema::INDEXES
+                    .iter()
+                    .filter(|(table, index)| !found.iter().any(|(t, i)| t == table && i == index))
+                    .map(|(table, index)| format!("{}.{}", table, index))
+                    .collect(),
+            )
+        }
+        Err(e) => {
+            advice.errors.insert("missing", e.to_string());
+        }
+    }
+
+    Ok(response::json(200, serde_json::to_string(&advice)?))
+}
+
+/// JSON Schema for the API's request and response bodies; see [`types`].
+pub fn types() -> Result<ApiGatewayProxyResponse, Error> {
+    Ok(response::json(200, types::schema().to_string()))
+}
+
+/// Reports the state of the cached connection for each connection profile.
+pub fn pool() -> Result<ApiGatewayProxyResponse, Error> {
+    Ok(response::json(
+        200,
+        serde_json::to_string(&db::pool_stats())?,
+    ))
+}
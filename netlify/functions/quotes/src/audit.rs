@@ -0,0 +1,49 @@
+//! Audit log of mutating requests, logged as one structured line when the request finishes.
+//!
+//! Like the timings, the current request's caller lives in a static rather than being
+//! threaded through every handler.
+
+use std::sync::Mutex;
+
+use serde_json::json;
+
+use crate::auth::Principal;
+
+static CURRENT: Mutex<Option<Caller>> = Mutex::new(None);
+
+struct Caller {
+    actor: Option<String>,
+    subject: Option<String>,
+}
+
+/// Remembers who the current request acts as, once the caller is authenticated.
+pub fn begin(principal: &Principal) {
+    *CURRENT.lock().unwrap() = Some(Caller {
+        actor: principal.actor.clone(),
+        subject: principal.subject.clone(),
+    });
+}
+
+/// Logs the audit line for requests that may change data. `actor` is set only when an admin
+/// acted on behalf of `subject`.
+pub fn finish(request_id: &str, method: &str, path: &str, status: i64) {
+    let caller = match CURRENT.lock().unwrap().take() {
+        Some(caller) => caller,
+        None => return,
+    };
+    if method == "GET" || method == "HEAD" {
+        return;
+    }
+
+    let line = json!({
+        "audit": true,
+        "request_id": request_id,
+        "method": method,
+        "path": path,
+        "status": status,
+        "actor": caller.actor.as_ref().or(caller.subject.as_ref()),
+        "subject": caller.subject,
+        "on_behalf_of": caller.actor.is_some(),
+    });
+    log::info!("{}", line);
+}
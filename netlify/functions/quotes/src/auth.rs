@@ -0,0 +1,378 @@
+//! Who is calling and which scopes they hold.
+//!
+//! Callers authenticate with the `ADMIN_TOKEN` bearer token, an HS256 JWT signed with
+//! `JWT_SECRET`, a request signed with one of the `SIGNING_SECRETS`, or an `X-Api-Key`
+//! listed in `API_KEYS`. Everyone else gets
+//! `ANONYMOUS_SCOPES`. Admin callers may act as another user with `X-On-Behalf-Of`.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use http::header::{HeaderMap, AUTHORIZATION};
+use openssl::error::ErrorStack;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{config, db, response};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    QuotesRead,
+    QuotesWrite,
+    /// Implies every other scope.
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::QuotesRead => "quotes:read",
+            Scope::QuotesWrite => "quotes:write",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "quotes:read" => Some(Scope::QuotesRead),
+            "quotes:write" => Some(Scope::QuotesWrite),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Principal {
+    /// The JWT `sub` claim, or the user named in `X-On-Behalf-Of`; `None` for other kinds
+    /// of caller.
+    pub subject: Option<String>,
+    /// Who actually sent the request when an admin acts on behalf of `subject`.
+    pub actor: Option<String>,
+    pub scopes: Vec<Scope>,
+    pub authenticated: bool,
+    /// The credentials the request came with, as usage is counted: `admin`, `user:<sub>`,
+    /// `signing:<key id>` or `key:<digest of the API key>`. `None` for anonymous callers.
+    pub caller: Option<String>,
+}
+
+impl Principal {
+    pub fn has(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.has(Scope::Admin)
+    }
+
+    /// Who is recorded as the owner of what the caller creates: the subject, otherwise the
+    /// API key or signing key the request came with. `None` for anonymous and admin callers.
+    pub fn owner(&self) -> Option<String> {
+        match (&self.subject, self.is_admin()) {
+            (Some(subject), _) => Some(subject.clone()),
+            (None, false) => self.caller.clone(),
+            (None, true) => None,
+        }
+    }
+
+    /// Whether the caller may change something created by `owner`. Only admins may change
+    /// what has no owner.
+    pub fn may_modify(&self, owner: Option<&str>) -> bool {
+        match owner {
+            Some(owner) => self.is_admin() || self.owner().as_deref() == Some(owner),
+            None => self.is_admin(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    exp: Option<u64>,
+    /// Space-separated, as in OAuth 2.0.
+    scope: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// A shared secret from `SIGNING_SECRETS` and the scopes of requests signed with it.
+#[derive(Deserialize)]
+struct SigningKey {
+    secret: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+/// Identifies the caller, or explains why the credentials they sent were refused.
+pub fn principal(event: &ApiGatewayProxyRequest) -> Result<Principal, &'static str> {
+    let headers = &event.headers;
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(token) = header(AUTHORIZATION.as_str()).and_then(|v| v.strip_prefix("Bearer ")) {
+        if is_admin_token(token) {
+            return Ok(Principal {
+                subject: None,
+                actor: None,
+                scopes: vec![Scope::Admin],
+                authenticated: true,
+                caller: Some(String::from("admin")),
+            });
+        }
+        let secret = match std::env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret,
+            _ => return Err("The bearer token is not valid."),
+        };
+        let claims = verify_jwt(token, &secret)?;
+        let scopes = claims
+            .scope
+            .iter()
+            .flat_map(|scope| scope.split(' '))
+            .chain(claims.scopes.iter().map(String::as_str))
+            .filter_map(Scope::parse)
+            .collect();
+        return Ok(Principal {
+            caller: claims.sub.as_ref().map(|sub| format!("user:{}", sub)),
+            subject: claims.sub,
+            actor: None,
+            scopes,
+            authenticated: true,
+        });
+    }
+
+    if let Some(signature) = header("x-signature") {
+        let scopes = verify_signature(event, signature, header("x-signature-timestamp"))?;
+        let key_id = signature.split_once(':').map_or("", |(key_id, _)| key_id);
+        return Ok(Principal {
+            subject: None,
+            actor: None,
+            scopes: scopes
+                .iter()
+                .filter_map(|scope| Scope::parse(scope))
+                .collect(),
+            authenticated: true,
+            caller: Some(format!("signing:{}", key_id)),
+        });
+    }
+
+    if let Some(key) = header("x-api-key") {
+        let keys: HashMap<String, Vec<String>> = std::env::var("API_KEYS")
+            .ok()
+            .and_then(|keys| serde_json::from_str(&keys).ok())
+            .unwrap_or_default();
+        let scopes = keys
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), key.as_bytes()))
+            .map(|(_, scopes)| scopes)
+            .ok_or("The API key is not valid.")?;
+        return Ok(Principal {
+            subject: None,
+            actor: None,
+            scopes: scopes
+                .iter()
+                .filter_map(|scope| Scope::parse(scope))
+                .collect(),
+            authenticated: true,
+            caller: Some(api_key_id(key)),
+        });
+    }
+
+    let scopes = config::var_or("ANONYMOUS_SCOPES", String::from("quotes:read quotes:write"));
+    let mut scopes: Vec<Scope> = scopes.split_whitespace().filter_map(Scope::parse).collect();
+    // Anonymous callers connect as the read-only role in public mode, so they may only read.
+    if db::public_mode() {
+        scopes.retain(|scope| *scope == Scope::QuotesRead);
+    }
+    Ok(Principal {
+        subject: None,
+        actor: None,
+        scopes,
+        authenticated: false,
+        caller: None,
+    })
+}
+
+/// Names an API key by the start of its SHA-256 digest, so the key itself is never stored.
+pub fn api_key_id(key: &str) -> String {
+    format!("key:{}", &hex(&openssl::sha::sha256(key.as_bytes()))[..16])
+}
+
+/// Switches an admin caller to the user named in `X-On-Behalf-Of`, keeping the admin as the
+/// actor. Anyone else sending the header gets a `403`.
+pub fn on_behalf_of(
+    principal: Principal,
+    headers: &HeaderMap,
+) -> Result<Principal, ApiGatewayProxyResponse> {
+    let user = match headers
+        .get("x-on-behalf-of")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+    {
+        Some(user) if !user.is_empty() => user.to_string(),
+        _ => return Ok(principal),
+    };
+    if !principal.is_admin() {
+        return Err(response::problem(
+            403,
+            "Forbidden",
+            "Only admin callers may act on behalf of another user.",
+        ));
+    }
+    Ok(Principal {
+        actor: Some(principal.subject.unwrap_or_else(|| String::from("admin"))),
+        subject: Some(user),
+        ..principal
+    })
+}
+
+/// `401` for anonymous callers and `403` naming the missing scope for everyone else.
+pub fn missing_scope(principal: &Principal, scope: Scope) -> ApiGatewayProxyResponse {
+    if !principal.authenticated {
+        return response::problem(
+            401,
+            "Unauthorized",
+            &format!(
+                "Credentials with the {} scope are required.",
+                scope.as_str()
+            ),
+        );
+    }
+    response::problem_with(
+        403,
+        "Forbidden",
+        &format!("This route requires the {} scope.", scope.as_str()),
+        serde_json::json!({ "missing_scope": scope.as_str() }),
+    )
+}
+
+/// `403` for a change to someone else's quote.
+pub fn not_owner() -> ApiGatewayProxyResponse {
+    response::problem(
+        403,
+        "Forbidden",
+        "Only the caller who submitted this quote or an admin may change it.",
+    )
+}
+
+/// Whether `token` is the one configured in `ADMIN_TOKEN`, which is ignored while unset.
+fn is_admin_token(token: &str) -> bool {
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(admin) if !admin.is_empty() => constant_time_eq(token.as_bytes(), admin.as_bytes()),
+        _ => false,
+    }
+}
+
+fn verify_jwt(token: &str, secret: &str) -> Result<Claims, &'static str> {
+    const INVALID: &str = "The bearer token is not valid.";
+
+    let parts: Vec<&str> = token.split('.').collect();
+    let (header, payload, signature) = match parts.as_slice() {
+        [header, payload, signature] => (*header, *payload, *signature),
+        _ => return Err(INVALID),
+    };
+    let alg: Value = serde_json::from_slice(&base64url(header)?).map_err(|_| INVALID)?;
+    if alg["alg"] != "HS256" {
+        return Err("Only HS256 tokens are accepted.");
+    }
+
+    let expected =
+        hmac_sha256(secret, format!("{}.{}", header, payload).as_bytes()).map_err(|_| INVALID)?;
+    if !constant_time_eq(&base64url(signature)?, &expected) {
+        return Err(INVALID);
+    }
+
+    let claims: Claims = serde_json::from_slice(&base64url(payload)?).map_err(|_| INVALID)?;
+    if claims.exp.map_or(false, |exp| exp <= now()) {
+        return Err("The bearer token has expired.");
+    }
+    Ok(claims)
+}
+
+/// Checks an `X-Signature: <key id>:<hex>` header: an HMAC-SHA256, keyed by that entry of
+/// `SIGNING_SECRETS`, of the timestamp, method, path, [`canonical_query`] and hex SHA-256 of
+/// the body, one per line. Requests signed more than `SIGNATURE_WINDOW_SECS` away from now are
+/// refused as replays.
+fn verify_signature(
+    event: &ApiGatewayProxyRequest,
+    signature: &str,
+    timestamp: Option<&str>,
+) -> Result<Vec<String>, &'static str> {
+    const INVALID: &str = "The request signature is not valid.";
+
+    let (key_id, signature) = signature.split_once(':').ok_or(INVALID)?;
+    let mut keys: HashMap<String, SigningKey> = std::env::var("SIGNING_SECRETS")
+        .ok()
+        .and_then(|keys| serde_json::from_str(&keys).ok())
+        .unwrap_or_default();
+    let key = keys.remove(key_id).ok_or(INVALID)?;
+
+    let timestamp = timestamp.ok_or("X-Signature-Timestamp is required with X-Signature.")?;
+    let sent: u64 = timestamp.parse().map_err(|_| INVALID)?;
+    if now().abs_diff(sent) > config::var_or("SIGNATURE_WINDOW_SECS", 300) {
+        return Err("The request signature is outside the replay window.");
+    }
+
+    let body = match (
+        event.body.as_deref(),
+        event.is_base64_encoded.unwrap_or(false),
+    ) {
+        (Some(body), true) => openssl::base64::decode_block(body).map_err(|_| INVALID)?,
+        (Some(body), false) => body.as_bytes().to_vec(),
+        (None, _) => Vec::new(),
+    };
+    let signed = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        timestamp,
+        event.http_method,
+        event.path.as_deref().unwrap_or("/"),
+        canonical_query(event),
+        hex(&openssl::sha::sha256(&body)),
+    );
+    let expected = hmac_sha256(&key.secret, signed.as_bytes()).map_err(|_| INVALID)?;
+    if !constant_time_eq(signature.as_bytes(), hex(&expected).as_bytes()) {
+        return Err(INVALID);
+    }
+    Ok(key.scopes)
+}
+
+/// The query parameters of a signed request as they are signed: every name and value pair
+/// sorted by name, then value, and form-urlencoded. Signing them keeps a captured request
+/// from being replayed with other parameters, such as `dry_run` or `mode`.
+pub fn canonical_query(event: &ApiGatewayProxyRequest) -> String {
+    let mut pairs: Vec<(&str, &str)> = event.query_string_parameters.iter().collect();
+    pairs.sort_unstable();
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+pub(crate) fn hmac_sha256(secret: &str, data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    signer.sign_to_vec()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+fn base64url(segment: &str) -> Result<Vec<u8>, &'static str> {
+    let mut standard = segment.replace('-', "+").replace('_', "/");
+    while standard.len() % 4 != 0 {
+        standard.push('=');
+    }
+    openssl::base64::decode_block(&standard).map_err(|_| "The bearer token is not valid.")
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,375 @@
+//! Batch insert and bulk update with per-item multi-status results.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use serde_with::{serde_as, DisplayFromStr};
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::db::{self, DbError, StatementContext};
+use crate::moderation::{self, Verdict};
+use crate::quotes::{self, Lock, Quote};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Every item is applied on its own; failures do not affect other items.
+    BestEffort,
+    /// All items are applied in one transaction that is rolled back on the first failure.
+    Transactional,
+    /// Items are applied in chunks, each under its own savepoint, so a failure only rolls
+    /// back its chunk. See [`run_chunked`].
+    Chunked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Insert,
+    Update,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ItemResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "rowid_is_hidden")]
+    #[schemars(with = "Option<String>")]
+    pub rowid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Results give the rowid only while it is a public id, as quotes do.
+fn rowid_is_hidden(rowid: &Option<i64>) -> bool {
+    rowid.is_none() || quotes::uuid_ids()
+}
+
+/// Whether a chunk of a [`Mode::Chunked`] batch was kept.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ChunkResult {
+    pub index: usize,
+    /// Index of the chunk's first item.
+    pub first: usize,
+    pub items: usize,
+    pub committed: bool,
+}
+
+impl Mode {
+    pub fn from_param(param: Option<&str>) -> Option<Mode> {
+        match param {
+            None | Some("best_effort") => Some(Mode::BestEffort),
+            Some("transactional") => Some(Mode::Transactional),
+            Some("chunked") => Some(Mode::Chunked),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Applies `operation` to every item and reports each outcome.
+///
+/// `nested` runs the batch inside a transaction the caller already opened, such as a dry run,
+/// using savepoints where it would otherwise begin and commit its own transaction.
+pub async fn run(
+    client: &Client,
+    principal: &Principal,
+    operation: Operation,
+    mode: Mode,
+    items: Vec<Value>,
+    nested: bool,
+) -> Result<Vec<ItemResult>, DbError> {
+    if mode != Mode::Transactional {
+        return apply_items(client, principal, operation, mode, items, nested).await;
+    }
+    begin(client, nested).await?;
+    match apply_items(client, principal, operation, mode, items, nested).await {
+        Ok(results) => Ok(results),
+        Err(e) => Err(abandon(client, nested, e).await),
+    }
+}
+
+/// The body of [`run`], which ends a transactional batch's transaction itself unless an
+/// error cuts it short.
+async fn apply_items(
+    client: &Client,
+    principal: &Principal,
+    operation: Operation,
+    mode: Mode,
+    items: Vec<Value>,
+    nested: bool,
+) -> Result<Vec<ItemResult>, DbError> {
+    let mut results = Vec::with_capacity(items.len());
+    let mut failed = false;
+    for (index, item) in items.into_iter().enumerate() {
+        if failed {
+            results.push(ItemResult {
+                index,
+                status: 424,
+                rowid: None,
+                uuid: None,
+                error: Some(String::from("not attempted after an earlier item failed")),
+            });
+            continue;
+        }
+
+        let result = match mode {
+            // An error aborts the surrounding transaction, so each item gets its own savepoint.
+            Mode::BestEffort if nested => {
+                client
+                    .batch_execute("SAVEPOINT batch_item;")
+                    .await
+                    .statement("batch")?;
+                let result =
+                    apply(client, principal, operation, Lock::ForUpdate, index, item).await;
+                if result.error.is_some() {
+                    client
+                        .batch_execute("ROLLBACK TO SAVEPOINT batch_item;")
+                        .await
+                        .statement("batch")?;
+                }
+                client
+                    .batch_execute("RELEASE SAVEPOINT batch_item;")
+                    .await
+                    .statement("batch")?;
+                result
+            }
+            Mode::BestEffort => apply(client, principal, operation, Lock::None, index, item).await,
+            _ => apply(client, principal, operation, Lock::ForUpdate, index, item).await,
+        };
+        failed = mode == Mode::Transactional && result.error.is_some();
+        results.push(result);
+    }
+
+    if mode == Mode::Transactional {
+        if failed {
+            rollback(client, nested).await?;
+            for result in results.iter_mut().filter(|r| r.error.is_none()) {
+                result.status = 424;
+                result.rowid = None;
+                result.uuid = None;
+                result.error = Some(String::from("rolled back after another item failed"));
+            }
+        } else {
+            commit(client, nested).await?;
+        }
+    }
+
+    Ok(results)
+}
+
+/// Applies `operation` to `items` in chunks of `chunk_size`, all in one transaction with a
+/// savepoint per chunk. An item that fails rolls back only its own chunk, and the report
+/// says which chunks were committed so a client can resubmit just the others.
+pub async fn run_chunked(
+    client: &Client,
+    principal: &Principal,
+    operation: Operation,
+    items: Vec<Value>,
+    chunk_size: usize,
+    nested: bool,
+) -> Result<(Vec<ItemResult>, Vec<ChunkResult>), DbError> {
+    begin(client, nested).await?;
+    match apply_chunks(client, principal, operation, items, chunk_size, nested).await {
+        Ok(outcome) => Ok(outcome),
+        Err(e) => Err(abandon(client, nested, e).await),
+    }
+}
+
+/// The body of [`run_chunked`] once its transaction has begun.
+async fn apply_chunks(
+    client: &Client,
+    principal: &Principal,
+    operation: Operation,
+    items: Vec<Value>,
+    chunk_size: usize,
+    nested: bool,
+) -> Result<(Vec<ItemResult>, Vec<ChunkResult>), DbError> {
+    let mut results = Vec::with_capacity(items.len());
+    let mut chunks = Vec::new();
+    let mut items = items.into_iter().enumerate().peekable();
+
+    while items.peek().is_some() {
+        let first = results.len();
+        client
+            .batch_execute("SAVEPOINT batch_chunk;")
+            .await
+            .statement("batch")?;
+
+        let mut failed = false;
+        for (index, item) in items.by_ref().take(chunk_size) {
+            // The failed statement aborted the transaction up to the savepoint.
+            if failed {
+                results.push(ItemResult {
+                    index,
+                    status: 424,
+                    rowid: None,
+                    uuid: None,
+                    error: Some(String::from(
+                        "not attempted after an earlier item in its chunk failed",
+                    )),
+                });
+                continue;
+            }
+            let result = apply(client, principal, operation, Lock::ForUpdate, index, item).await;
+            failed = result.error.is_some();
+            results.push(result);
+        }
+
+        if failed {
+            client
+                .batch_execute("ROLLBACK TO SAVEPOINT batch_chunk;")
+                .await
+                .statement("batch")?;
+            for result in results[first..].iter_mut().filter(|r| r.error.is_none()) {
+                result.status = 424;
+                result.rowid = None;
+                result.uuid = None;
+                result.error = Some(String::from("rolled back with its chunk"));
+            }
+        }
+        client
+            .batch_execute("RELEASE SAVEPOINT batch_chunk;")
+            .await
+            .statement("batch")?;
+        chunks.push(ChunkResult {
+            index: chunks.len(),
+            first,
+            items: results.len() - first,
+            committed: !failed,
+        });
+    }
+    commit(client, nested).await?;
+
+    Ok((results, chunks))
+}
+
+/// Rolls back a batch that failed with `error` partway through, so the cached client is not
+/// left inside its transaction for the next request. CockroachDB refuses some rollbacks, such
+/// as to a savepoint after a serialization failure; the client is then dropped instead.
+/// A nested batch leaves the rest to the caller, who rolls back the enclosing transaction.
+async fn abandon(client: &Client, nested: bool, error: DbError) -> DbError {
+    if let Err(rollback_error) = rollback(client, nested).await {
+        log::warn!("failed to roll back batch: {}", rollback_error);
+        if !nested {
+            db::discard_clients();
+        }
+    }
+    error
+}
+
+async fn begin(client: &Client, nested: bool) -> Result<(), DbError> {
+    let statement = match nested {
+        true => "SAVEPOINT batch;",
+        false => "BEGIN;",
+    };
+    client.batch_execute(statement).await.statement("batch")
+}
+
+async fn commit(client: &Client, nested: bool) -> Result<(), DbError> {
+    let statement = match nested {
+        true => "RELEASE SAVEPOINT batch;",
+        false => "COMMIT;",
+    };
+    client.batch_execute(statement).await.statement("batch")
+}
+
+async fn rollback(client: &Client, nested: bool) -> Result<(), DbError> {
+    let statement = match nested {
+        true => "ROLLBACK TO SAVEPOINT batch; RELEASE SAVEPOINT batch;",
+        false => "ROLLBACK;",
+    };
+    client.batch_execute(statement).await.statement("batch")
+}
+
+/// Applies one item. `lock` is how an update locks the quote while checking its owner,
+/// which only helps when the item runs inside a transaction.
+async fn apply(
+    client: &Client,
+    principal: &Principal,
+    operation: Operation,
+    lock: Lock,
+    index: usize,
+    item: Value,
+) -> ItemResult {
+    let failure = |status: u16, error: String| ItemResult {
+        index,
+        status,
+        rowid: None,
+        uuid: None,
+        error: Some(error),
+    };
+
+    let mut quote: Quote = match serde_json::from_value(item) {
+        Ok(quote) => quote,
+        Err(e) => return failure(422, e.to_string()),
+    };
+    if let Verdict::Reject(reason) = moderation::check(&quote) {
+        return failure(422, reason);
+    }
+
+    let outcome = match operation {
+        Operation::Insert => {
+            quote.created_by = principal.owner();
+            quotes::insert_quote(client, quote)
+                .await
+                .map(|quote| Some((201, quote.rowid, quote.uuid)))
+        }
+        // Items name the quote they update by its public id, like a URL does.
+        Operation::Update => match item_id(&quote) {
+            Some(id) => match quotes::resolve_id(client, &id).await {
+                Ok(Some(rowid)) => match quotes::quote_owner(client, rowid, lock).await {
+                    Ok(owner) if !principal.may_modify(owner.as_deref()) => {
+                        return failure(403, String::from("quote belongs to another caller"))
+                    }
+                    Ok(_) => quotes::update_quote(client, rowid, quote)
+                        .await
+                        .map(|updated| updated.map(|quote| (200, Some(rowid), quote.uuid))),
+                    Err(e) => Err(e),
+                },
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            },
+            None => return failure(422, String::from(id_required())),
+        },
+    };
+
+    match outcome {
+        Ok(Some((status, rowid, uuid))) => ItemResult {
+            index,
+            status,
+            rowid,
+            uuid,
+            error: None,
+        },
+        Ok(None) => failure(404, String::from("quote does not exist")),
+        Err(e) => {
+            let status = match e {
+                DbError::Constraint { .. } => 409,
+                _ => 500,
+            };
+            failure(status, e.to_string())
+        }
+    }
+}
+
+/// The id an update item names its quote by: its `uuid`, or its `rowid` unless `UUID_IDS`
+/// keeps rowids internal.
+fn item_id(quote: &Quote) -> Option<String> {
+    match (quote.uuid, quote.rowid) {
+        (Some(uuid), _) => Some(uuid.to_string()),
+        (None, Some(rowid)) if !quotes::uuid_ids() => Some(rowid.to_string()),
+        (None, _) => None,
+    }
+}
+
+fn id_required() -> &'static str {
+    match quotes::uuid_ids() {
+        true => "uuid is required",
+        false => "rowid or uuid is required",
+    }
+}
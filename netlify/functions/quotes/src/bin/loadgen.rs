@@ -0,0 +1,571 @@
+//! Fires concurrent synthetic requests and reports latency percentiles and how often the
+//! database asked for a retry, so that performance changes can be measured.
+//!
+//! With `--url` it sends HTTP requests to a deployed site or `netlify dev`. Without it, it
+//! calls the query functions in-process against `DATABASE_URL`, which leaves out routing and
+//! serialization but isolates the database path.
+//!
+//! With `--keys` it instead compares insert throughput across primary key layouts, each on
+//! a scratch table it creates and drops again, against `DATABASE_URL`.
+//!
+//! ```text
+//! loadgen [--url https://<site>/api] [--requests 1000] [--concurrency 16]
+//!         [--mix list:6,get:3,search:1,write:0] [--token <bearer token>]
+//! loadgen --keys rowid,hash,uuid [--requests 1000] [--concurrency 16]
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+use log::LevelFilter;
+use serde_json::Value;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client;
+
+use quotes_api::db::{self, DbError};
+use quotes_api::quotes::{self, Quote};
+use quotes_api::{logging, metrics};
+
+const USAGE: &str = "usage: loadgen [--url URL] [--requests N] [--concurrency N] [--mix list:6,get:3,search:1,write:0] [--token TOKEN] [--keys rowid,hash,uuid]";
+
+const SEARCHES: &[&str] = &[
+    "make it so",
+    "engage",
+    "Picard",
+    "resistance is futile",
+    "Data",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scenario {
+    List,
+    Get,
+    Search,
+    /// Creates a quote and deletes it again.
+    Write,
+}
+
+impl Scenario {
+    const ALL: [Scenario; 4] = [
+        Scenario::List,
+        Scenario::Get,
+        Scenario::Search,
+        Scenario::Write,
+    ];
+
+    fn parse(name: &str) -> Option<Scenario> {
+        Scenario::ALL
+            .into_iter()
+            .find(|scenario| scenario.as_str() == name)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Scenario::List => "list",
+            Scenario::Get => "get",
+            Scenario::Search => "search",
+            Scenario::Write => "write",
+        }
+    }
+}
+
+/// A primary key layout compared by `--keys`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Keys {
+    /// `unique_rowid()`, ordered by time like the rowids of `quotes`.
+    Rowid,
+    /// The same, hash-sharded as by `migrations/optional/hash_sharded_rowid.sql`.
+    Hash,
+    /// Random UUIDs, as by `migrations/optional/uuid_primary_key.sql`.
+    Uuid,
+}
+
+impl Keys {
+    const ALL: [Keys; 3] = [Keys::Rowid, Keys::Hash, Keys::Uuid];
+
+    fn parse(name: &str) -> Option<Keys> {
+        Keys::ALL.into_iter().find(|keys| keys.as_str() == name)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Keys::Rowid => "rowid",
+            Keys::Hash => "hash",
+            Keys::Uuid => "uuid",
+        }
+    }
+
+    fn table(self) -> String {
+        format!("loadgen_keys_{}", self.as_str())
+    }
+
+    fn create_sql(self) -> String {
+        let columns = match self {
+            Keys::Rowid => "id INT8 NOT NULL DEFAULT unique_rowid() PRIMARY KEY",
+            Keys::Hash => "id INT8 NOT NULL DEFAULT unique_rowid(), PRIMARY KEY (id) USING HASH WITH (bucket_count = 16)",
+            Keys::Uuid => "id UUID NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY",
+        };
+        format!(
+            "CREATE TABLE {} ({}, quote STRING NOT NULL);",
+            self.table(),
+            columns
+        )
+    }
+}
+
+struct Options {
+    url: Option<String>,
+    requests: usize,
+    concurrency: usize,
+    /// Each scenario with its weight.
+    mix: Vec<(Scenario, usize)>,
+    token: Option<String>,
+    keys: Vec<Keys>,
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options {
+        url: None,
+        requests: 1000,
+        concurrency: 16,
+        mix: vec![
+            (Scenario::List, 6),
+            (Scenario::Get, 3),
+            (Scenario::Search, 1),
+        ],
+        token: std::env::var("LOADGEN_TOKEN").ok(),
+        keys: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--url" => options.url = Some(value()?.trim_end_matches('/').to_string()),
+            "--requests" => options.requests = value()?.parse().map_err(|_| "bad --requests")?,
+            "--concurrency" => {
+                options.concurrency = value()?.parse().map_err(|_| "bad --concurrency")?
+            }
+            "--token" => options.token = Some(value()?),
+            "--mix" => {
+                options.mix = value()?
+                    .split(',')
+                    .map(|entry| {
+                        let (name, weight) = entry.split_once(':').unwrap_or((entry, "1"));
+                        match (Scenario::parse(name.trim()), weight.trim().parse()) {
+                            (Some(scenario), Ok(weight)) => Ok((scenario, weight)),
+                            _ => Err(format!("bad --mix entry {}", entry)),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            "--keys" => {
+                options.keys = value()?
+                    .split(',')
+                    .map(|name| {
+                        Keys::parse(name.trim()).ok_or(format!("bad --keys entry {}", name))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if !options.keys.is_empty() && options.url.is_some() {
+        return Err(String::from("--keys runs in-process; leave out --url"));
+    }
+    if options.concurrency == 0 || options.mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err(USAGE.to_string());
+    }
+    Ok(options)
+}
+
+/// Where the requests go.
+enum Target {
+    Http {
+        http: reqwest::Client,
+        base: String,
+        token: Option<String>,
+    },
+    InProcess(Arc<Client>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    /// The database asked for the request to be retried: a serialization conflict or a lost
+    /// connection, which the API reports as `503` with `Retry-After`.
+    Retry,
+    Failed,
+}
+
+struct Sample {
+    scenario: Scenario,
+    elapsed: Duration,
+    outcome: Outcome,
+}
+
+#[tokio::main]
+async fn main() {
+    logging::init(LevelFilter::Warn);
+
+    let options = match options() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    let target = match &options.url {
+        Some(url) => Target::Http {
+            http: reqwest::Client::new(),
+            base: url.clone(),
+            token: options.token.clone(),
+        },
+        None => match db::get_db_client().await {
+            Ok(client) => Target::InProcess(client),
+            Err(e) => {
+                eprintln!("cannot connect to DATABASE_URL: {}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Target::InProcess(client) = &target {
+        if !options.keys.is_empty() {
+            compare_keys(client, &options).await;
+            return;
+        }
+    }
+    let ids = target.ids().await;
+    if ids.is_empty()
+        && options
+            .mix
+            .iter()
+            .any(|&(s, w)| s == Scenario::Get && w > 0)
+    {
+        eprintln!("there are no quotes to get; add some or leave get out of --mix");
+        std::process::exit(1);
+    }
+
+    // A deterministic interleaving of the scenarios in proportion to their weights.
+    let schedule: Vec<Scenario> = options
+        .mix
+        .iter()
+        .flat_map(|&(scenario, weight)| std::iter::repeat(scenario).take(weight))
+        .collect();
+    let reconnects = metrics::RECONNECTS.get();
+    let started = Instant::now();
+    let samples: Vec<Sample> = stream::iter(0..options.requests)
+        .map(|i| {
+            let scenario = schedule[i % schedule.len()];
+            let target = &target;
+            let ids = &ids;
+            async move {
+                let at = Instant::now();
+                let outcome = target.send(scenario, i, ids).await;
+                Sample {
+                    scenario,
+                    elapsed: at.elapsed(),
+                    outcome,
+                }
+            }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+
+    report(&samples, started.elapsed());
+    if let Target::InProcess(_) = target {
+        println!(
+            "reconnects: {}",
+            metrics::RECONNECTS.get().saturating_sub(reconnects)
+        );
+    }
+}
+
+impl Target {
+    /// Ids of existing quotes for the `get` scenario.
+    async fn ids(&self) -> Vec<String> {
+        match self {
+            Target::Http { http, base, token } => {
+                let mut request = http.get(format!("{}/quotes?limit=100", base));
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                let body: Value = match request.send().await {
+                    Ok(resp) => resp.json().await.unwrap_or_default(),
+                    Err(_) => Value::Null,
+                };
+                body["data"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|quote| quote["rowid"].as_str().or(quote["uuid"].as_str()))
+                    .map(str::to_string)
+                    .collect()
+            }
+            Target::InProcess(client) => {
+                match quotes::get_quotes(client, 1, 100, None, None, None).await {
+                    Ok(page) => page
+                        .quotes
+                        .iter()
+                        .filter_map(|quote| quote.rowid)
+                        .map(|rowid| rowid.to_string())
+                        .collect(),
+                    Err(_) => Vec::new(),
+                }
+            }
+        }
+    }
+
+    async fn send(&self, scenario: Scenario, i: usize, ids: &[String]) -> Outcome {
+        let id = ids
+            .get(i % ids.len().max(1))
+            .map(String::as_str)
+            .unwrap_or("1");
+        let search = SEARCHES[i % SEARCHES.len()];
+        let page = (i % 5 + 1) as i64;
+        match self {
+            Target::Http { http, base, token } => {
+                let send = |request: reqwest::RequestBuilder| {
+                    let request = match token {
+                        Some(token) => request.bearer_auth(token),
+                        None => request,
+                    };
+                    async move {
+                        match request.send().await {
+                            Ok(resp) => http_outcome(&resp),
+                            Err(_) => Outcome::Failed,
+                        }
+                    }
+                };
+                match scenario {
+                    Scenario::List => {
+                        send(http.get(format!("{}/quotes?page={}", base, page))).await
+                    }
+                    Scenario::Get => send(http.get(format!("{}/quotes/{}", base, id))).await,
+                    Scenario::Search => {
+                        send(http.get(format!("{}/quotes", base)).query(&[("q", search)])).await
+                    }
+                    Scenario::Write => {
+                        let mut request = http
+                            .post(format!("{}/quotes", base))
+                            .json(&synthetic_quote(i));
+                        if let Some(token) = token {
+                            request = request.bearer_auth(token);
+                        }
+                        let resp = match request.send().await {
+                            Ok(resp) => resp,
+                            Err(_) => return Outcome::Failed,
+                        };
+                        let outcome = http_outcome(&resp);
+                        let location = resp
+                            .headers()
+                            .get(reqwest::header::LOCATION)
+                            .and_then(|value| value.to_str().ok());
+                        match (outcome, location) {
+                            (Outcome::Ok, Some(location)) => {
+                                let location = reqwest::Url::parse(base)
+                                    .and_then(|base| base.join(location))
+                                    .map(String::from)
+                                    .unwrap_or_else(|_| location.to_string());
+                                send(http.delete(location)).await
+                            }
+                            (outcome, _) => outcome,
+                        }
+                    }
+                }
+            }
+            Target::InProcess(client) => {
+                let result = match scenario {
+                    Scenario::List => quotes::get_quotes(client, page, 20, None, None, None)
+                        .await
+                        .map(|_| ()),
+                    Scenario::Search => {
+                        quotes::search_quotes(client, search, 1, 20, None, None, None)
+                            .await
+                            .map(|_| ())
+                    }
+                    Scenario::Get => quotes::get_quote(client, id.parse().unwrap_or(1))
+                        .await
+                        .map(|_| ()),
+                    Scenario::Write => {
+                        match quotes::insert_quote(client, synthetic_quote(i)).await {
+                            Ok(quote) => match quote.rowid {
+                                Some(rowid) => {
+                                    quotes::delete_quote(client, rowid).await.map(|_| ())
+                                }
+                                None => Ok(()),
+                            },
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                match result {
+                    Ok(()) => Outcome::Ok,
+                    Err(DbError::Serialization { .. } | DbError::Connect { .. }) => Outcome::Retry,
+                    Err(e) => {
+                        log::warn!("{} failed: {}", scenario.as_str(), e);
+                        Outcome::Failed
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Inserts `--requests` rows into a fresh table for each key layout and reports how fast
+/// they went in. The tables are dropped afterwards.
+async fn compare_keys(client: &Client, options: &Options) {
+    println!(
+        "{:<8} {:>8} {:>7} {:>8} {:>9} {:>9} {:>9} {:>10}",
+        "keys", "inserts", "failed", "retries", "p50 ms", "p99 ms", "max ms", "inserts/s"
+    );
+    for keys in &options.keys {
+        let table = keys.table();
+        let create = format!("DROP TABLE IF EXISTS {}; {}", table, keys.create_sql());
+        if let Err(e) = client.batch_execute(&create).await {
+            eprintln!("cannot create {}: {}", table, e);
+            std::process::exit(1);
+        }
+        let insert = format!("INSERT INTO {} (quote) VALUES ($1);", table);
+
+        let started = Instant::now();
+        let samples: Vec<Sample> = stream::iter(0..options.requests)
+            .map(|i| {
+                let insert = &insert;
+                async move {
+                    let at = Instant::now();
+                    let outcome = match client
+                        .execute(insert.as_str(), &[&synthetic_quote(i).quote])
+                        .await
+                    {
+                        Ok(_) => Outcome::Ok,
+                        Err(e) if e.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE) => {
+                            Outcome::Retry
+                        }
+                        Err(e) => {
+                            log::warn!("insert failed: {}", e);
+                            Outcome::Failed
+                        }
+                    };
+                    Sample {
+                        scenario: Scenario::Write,
+                        elapsed: at.elapsed(),
+                        outcome,
+                    }
+                }
+            })
+            .buffer_unordered(options.concurrency)
+            .collect()
+            .await;
+        let elapsed = started.elapsed();
+
+        let mut latencies: Vec<Duration> = samples.iter().map(|s| s.elapsed).collect();
+        latencies.sort();
+        let count = |outcome| samples.iter().filter(|s| s.outcome == outcome).count();
+        println!(
+            "{:<8} {:>8} {:>7} {:>8} {:>9.1} {:>9.1} {:>9.1} {:>10.1}",
+            keys.as_str(),
+            samples.len(),
+            count(Outcome::Failed),
+            count(Outcome::Retry),
+            millis(percentile(&latencies, 0.50)),
+            millis(percentile(&latencies, 0.99)),
+            millis(latencies.last().copied().unwrap_or_default()),
+            count(Outcome::Ok) as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        );
+        if let Err(e) = client
+            .batch_execute(&format!("DROP TABLE {};", table))
+            .await
+        {
+            eprintln!("cannot drop {}: {}", table, e);
+        }
+    }
+}
+
+fn http_outcome(resp: &reqwest::Response) -> Outcome {
+    let status = resp.status();
+    if status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+        && resp.headers().contains_key(reqwest::header::RETRY_AFTER)
+    {
+        Outcome::Retry
+    } else if status.is_success() {
+        Outcome::Ok
+    } else {
+        Outcome::Failed
+    }
+}
+
+fn synthetic_quote(i: usize) -> Quote {
+    Quote {
+        rowid: None,
+        uuid: None,
+        slug: None,
+        quote: Some(format!(
+            "Load test quote {} from process {}",
+            i,
+            std::process::id()
+        )),
+        characters: Some(vec!["Loadgen".to_string()]),
+        stardate: None,
+        episode: None,
+        lang: Some("en".to_string()),
+        created_by: None,
+        lines: None,
+        highlight: None,
+    }
+}
+
+fn report(samples: &[Sample], elapsed: Duration) {
+    println!(
+        "{:<8} {:>8} {:>7} {:>8} {:>9} {:>9} {:>9}",
+        "scenario", "requests", "failed", "retries", "p50 ms", "p99 ms", "max ms"
+    );
+    for scenario in Scenario::ALL {
+        let samples: Vec<&Sample> = samples.iter().filter(|s| s.scenario == scenario).collect();
+        if !samples.is_empty() {
+            row(scenario.as_str(), &samples);
+        }
+    }
+    row("all", &samples.iter().collect::<Vec<_>>());
+    let retries = samples
+        .iter()
+        .filter(|s| s.outcome == Outcome::Retry)
+        .count();
+    println!(
+        "{} requests in {:.1} s ({:.1} req/s), database retry rate {:.2}%",
+        samples.len(),
+        elapsed.as_secs_f64(),
+        samples.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        100.0 * retries as f64 / samples.len().max(1) as f64
+    );
+}
+
+fn row(name: &str, samples: &[&Sample]) {
+    let mut latencies: Vec<Duration> = samples.iter().map(|s| s.elapsed).collect();
+    latencies.sort();
+    let count = |outcome| samples.iter().filter(|s| s.outcome == outcome).count();
+    println!(
+        "{:<8} {:>8} {:>7} {:>8} {:>9.1} {:>9.1} {:>9.1}",
+        name,
+        samples.len(),
+        count(Outcome::Failed),
+        count(Outcome::Retry),
+        millis(percentile(&latencies, 0.50)),
+        millis(percentile(&latencies, 0.99)),
+        millis(latencies.last().copied().unwrap_or_default()),
+    );
+}
+
+/// The nearest-rank percentile of sorted `latencies`.
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,49 @@
+use aws_lambda_events::event::sqs::SqsEvent;
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use log::LevelFilter;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use quotes_api::{db, deadline, guard, jobs, logging};
+
+#[derive(Deserialize)]
+struct Message {
+    job_id: Uuid,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    logging::init(LevelFilter::Info);
+
+    let processor = service_fn(handler);
+    lambda_runtime::run(processor).await?;
+    Ok(())
+}
+
+/// Carries on with each queued job until it finishes, waits, or this invocation runs short
+/// of time; `jobs::work` queues it again in the last two cases.
+async fn handler(event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
+    deadline::start_budget(deadline::from_context(&event.context));
+    let client = db::get_db_client().await?;
+    if !guard::writes_allowed(&client).await? {
+        return Err("the database is not the expected cluster".into());
+    }
+
+    for record in event.payload.records {
+        let message_id = record.message_id.unwrap_or_default();
+        let id = match record.body.as_deref().map(serde_json::from_str::<Message>) {
+            Some(Ok(message)) => message.job_id,
+            _ => {
+                log::error!("dropping message {}: body is not a job", message_id);
+                continue;
+            }
+        };
+
+        // Transient failures fail the invocation so SQS redelivers the message.
+        match jobs::work(&client, id).await? {
+            Some(job) => log::info!("job {} is {} after {} steps", job.id, job.status, job.steps),
+            None => log::warn!("dropping message {}: job {} does not exist", message_id, id),
+        }
+    }
+    Ok(())
+}
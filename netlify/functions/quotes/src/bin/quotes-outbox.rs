@@ -0,0 +1,32 @@
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use log::LevelFilter;
+use serde_json::Value;
+
+use quotes_api::{db, deadline, guard, logging, outbox};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    logging::init(LevelFilter::Info);
+
+    let processor = service_fn(handler);
+    lambda_runtime::run(processor).await?;
+    Ok(())
+}
+
+/// Runs on an EventBridge schedule and delivers due outbox events until none are left or
+/// this invocation runs short of time.
+async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    deadline::start_budget(deadline::from_context(&event.context));
+    let client = db::get_db_client().await?;
+    if !guard::writes_allowed(&client).await? {
+        return Err("the database is not the expected cluster".into());
+    }
+
+    let report = outbox::dispatch(&client).await?;
+    log::info!(
+        "delivered {} events, {} failed",
+        report.delivered,
+        report.failed
+    );
+    Ok(serde_json::to_value(report)?)
+}
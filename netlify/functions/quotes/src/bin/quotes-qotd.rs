@@ -0,0 +1,65 @@
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use log::LevelFilter;
+use serde_json::{json, Value};
+
+use quotes_api::quotes::{self, Quote};
+use quotes_api::{db, guard, logging, outbox, share};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    logging::init(LevelFilter::Info);
+
+    let processor = service_fn(handler);
+    lambda_runtime::run(processor).await?;
+    Ok(())
+}
+
+/// Runs on an EventBridge schedule; the event itself carries nothing we need.
+async fn handler(_event: LambdaEvent<Value>) -> Result<Value, Error> {
+    let client = db::get_db_client().await?;
+    if !guard::writes_allowed(&client).await? {
+        return Err("the database is not the expected cluster".into());
+    }
+    let (day, quote) = match quotes::quote_of_the_day(&client).await? {
+        Some(picked) => picked,
+        None => {
+            log::warn!("no quotes to publish");
+            return Ok(Value::Null);
+        }
+    };
+    log::info!(
+        "quote of the day for {} is {}",
+        day,
+        quote.rowid.unwrap_or_default()
+    );
+
+    // With the outbox on, the quotes-outbox Lambda delivers the quote, retrying until the
+    // webhook takes it; running again on the same day records nothing new.
+    if outbox::enabled() {
+        let payload = json!({ "text": share::text(&quote), "day": day, "quote": quote });
+        outbox::record(
+            &client,
+            "qotd.published",
+            &format!("qotd:{}", day),
+            &payload,
+        )
+        .await?;
+    } else if let Ok(url) = std::env::var("QOTD_WEBHOOK_URL") {
+        publish(&url, &day, &quote).await?;
+    }
+
+    Ok(json!({ "day": day, "quote": quote }))
+}
+
+/// Posts the quote to a webhook. The `text` field makes the payload a valid Slack message.
+async fn publish(url: &str, day: &str, quote: &Quote) -> Result<(), Error> {
+    let payload = json!({ "text": share::text(quote), "day": day, "quote": quote });
+
+    reqwest::Client::new()
+        .post(url)
+        .json(&payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
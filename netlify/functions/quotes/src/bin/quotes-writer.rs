@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use aws_lambda_events::event::sqs::SqsEvent;
+use lambda_runtime::{service_fn, Error, LambdaEvent};
+use log::LevelFilter;
+
+use quotes_api::db::{self, DbError};
+use quotes_api::quotes::{self, Quote};
+use quotes_api::{cache, config, guard, logging};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    logging::init(LevelFilter::Info);
+
+    let processor = service_fn(handler);
+    lambda_runtime::run(processor).await?;
+    Ok(())
+}
+
+/// Inserts every queued quote, failing the invocation if any of them could not be written
+/// so that SQS redelivers the batch or moves it to the dead-letter queue.
+async fn handler(event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
+    let mut failed = 0;
+    let mut inserted = 0;
+
+    for record in event.payload.records {
+        let tracking_id = record.message_id.unwrap_or_default();
+        let quote: Quote = match record.body.as_deref().map(serde_json::from_str) {
+            Some(Ok(quote)) => quote,
+            _ => {
+                log::error!("dropping message {}: body is not a quote", tracking_id);
+                continue;
+            }
+        };
+
+        match insert_with_retries(quote).await {
+            Ok(quote) => {
+                log::info!(
+                    "message {} inserted as quote {}",
+                    tracking_id,
+                    quote.rowid.unwrap_or_default()
+                );
+                inserted += 1;
+            }
+            Err(e) => {
+                log::error!("message {} failed: {}", tracking_id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if inserted > 0 {
+        cache::invalidate().await;
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{} quotes could not be written", n).into()),
+    }
+}
+
+/// Retries transient failures `WRITE_RETRIES` times, doubling the delay from `WRITE_RETRY_DELAY_MS`.
+async fn insert_with_retries(quote: Quote) -> Result<Quote, Error> {
+    let retries: u32 = config::var_or("WRITE_RETRIES", 3);
+    let mut delay = Duration::from_millis(config::var_or("WRITE_RETRY_DELAY_MS", 200));
+    let mut attempt = 0;
+
+    loop {
+        let result = match db::get_db_client().await {
+            Ok(client) => match guard::writes_allowed(&client).await {
+                Ok(true) => quotes::insert_quote(&client, quote.clone()).await,
+                Ok(false) => return Err("the database is not the expected cluster".into()),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(quote) => return Ok(quote),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                log::warn!("insert attempt {} failed: {}", attempt, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn is_transient(error: &DbError) -> bool {
+    matches!(
+        error,
+        DbError::Connect { .. } | DbError::Timeout { .. } | DbError::Serialization { .. }
+    )
+}
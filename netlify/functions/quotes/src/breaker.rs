@@ -0,0 +1,117 @@
+//! Process-wide circuit breaker around database connections.
+//!
+//! Lambda keeps the process alive between warm invocations, so the breaker
+//! state lives in a static and is shared by every request the container serves.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+enum State {
+    Closed,
+    Open(Instant),
+    HalfOpen,
+}
+
+struct Breaker {
+    state: State,
+    window_start: Option<Instant>,
+    requests: u32,
+    failures: u32,
+}
+
+static BREAKER: Mutex<Breaker> = Mutex::new(Breaker {
+    state: State::Closed,
+    window_start: None,
+    requests: 0,
+    failures: 0,
+});
+
+fn window() -> Duration {
+    Duration::from_secs(config::var_or("BREAKER_WINDOW_SECS", 60))
+}
+
+fn open_for() -> Duration {
+    Duration::from_secs(config::var_or("BREAKER_OPEN_SECS", 30))
+}
+
+fn min_requests() -> u32 {
+    config::var_or("BREAKER_MIN_REQUESTS", 5)
+}
+
+fn failure_rate() -> f64 {
+    config::var_or("BREAKER_FAILURE_RATE", 0.5)
+}
+
+/// Returns `Err` with the time left until the next trial when the breaker is open.
+pub fn check() -> Result<(), Duration> {
+    let mut breaker = BREAKER.lock().unwrap();
+    if let State::Open(until) = breaker.state {
+        let now = Instant::now();
+        if now < until {
+            return Err(until - now);
+        }
+        breaker.state = State::HalfOpen;
+    }
+    Ok(())
+}
+
+/// How long a client turned away by a database failure should wait: the rest of the open
+/// period when the breaker is open, otherwise `RETRY_AFTER_SECS`.
+pub fn retry_after() -> Duration {
+    let breaker = BREAKER.lock().unwrap();
+    match breaker.state {
+        State::Open(until) => until.saturating_duration_since(Instant::now()),
+        _ => Duration::from_secs(config::var_or("RETRY_AFTER_SECS", 1)),
+    }
+}
+
+pub fn record_success() {
+    let mut breaker = BREAKER.lock().unwrap();
+    if let State::HalfOpen = breaker.state {
+        breaker.state = State::Closed;
+        breaker.window_start = None;
+    }
+    breaker.record(false);
+}
+
+pub fn record_failure() {
+    let mut breaker = BREAKER.lock().unwrap();
+    if let State::HalfOpen = breaker.state {
+        breaker.trip();
+        return;
+    }
+    breaker.record(true);
+    if breaker.requests >= min_requests()
+        && f64::from(breaker.failures) / f64::from(breaker.requests) >= failure_rate()
+    {
+        breaker.trip();
+    }
+}
+
+impl Breaker {
+    fn record(&mut self, failed: bool) {
+        let now = Instant::now();
+        match self.window_start {
+            Some(start) if now.duration_since(start) < window() => {}
+            _ => {
+                self.window_start = Some(now);
+                self.requests = 0;
+                self.failures = 0;
+            }
+        }
+        self.requests += 1;
+        if failed {
+            self.failures += 1;
+        }
+    }
+
+    fn trip(&mut self) {
+        log::warn!("database circuit breaker opened");
+        self.state = State::Open(Instant::now() + open_for());
+        self.window_start = None;
+        self.requests = 0;
+        self.failures = 0;
+    }
+}
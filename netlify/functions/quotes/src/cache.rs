@@ -0,0 +1,343 @@
+//! An optional cache of read responses, consulted before CockroachDB for the hot public
+//! routes and emptied whenever a request changes quotes.
+//!
+//! `RESPONSE_CACHE` picks the [`Store`]: `memory` keeps responses in the container, so
+//! other containers only see a write once their entries expire; `dynamodb` (builds with the
+//! `dynamodb` feature) shares one table between all containers. Entries are tagged with a
+//! generation that every invalidation bumps, so emptying the cache is a single write.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use futures::future::BoxFuture;
+use http::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT};
+use serde::{Deserialize, Serialize};
+
+use crate::router::Endpoint;
+use crate::{config, links, response};
+
+/// A cached response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    status_code: i64,
+    headers: Vec<(String, String)>,
+    body: String,
+    /// Unix time after which the entry is ignored.
+    expires_at: u64,
+}
+
+/// What a [`Store`] holds under a key, and the generation a response read now should be
+/// stored at.
+pub struct Lookup {
+    pub entry: Option<Entry>,
+    pub generation: i64,
+}
+
+/// Somewhere to keep cached responses. Failures are reported as text and treated as misses.
+pub trait Store: Send + Sync {
+    /// The entry under `key` when it is from the current generation.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Lookup, String>>;
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        generation: i64,
+        entry: Entry,
+    ) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Makes every stored entry stale.
+    fn invalidate(&self) -> BoxFuture<'_, Result<(), String>>;
+}
+
+static STORE: Mutex<Option<Arc<dyn Store>>> = Mutex::new(None);
+
+async fn store() -> Option<Arc<dyn Store>> {
+    if let Some(store) = STORE.lock().unwrap().as_ref() {
+        return Some(store.clone());
+    }
+    let store: Arc<dyn Store> = match config::var_or("RESPONSE_CACHE", String::from("off")).as_str()
+    {
+        "memory" => Arc::new(Memory::default()),
+        #[cfg(feature = "dynamodb")]
+        "dynamodb" => Arc::new(dynamodb::DynamoDb::new().await),
+        _ => return None,
+    };
+    *STORE.lock().unwrap() = Some(store.clone());
+    Some(store)
+}
+
+/// The cache key of a request, or `None` when its response must not be cached: anything but
+/// a `GET` of a public read route.
+pub fn key(
+    endpoint: Endpoint,
+    method: &http::Method,
+    event: &ApiGatewayProxyRequest,
+) -> Option<String> {
+    if method != http::Method::GET {
+        return None;
+    }
+    if !matches!(
+        endpoint,
+        Endpoint::Quotes
+            | Endpoint::RelatedQuotes
+            | Endpoint::Share
+            | Endpoint::Timeline
+            | Endpoint::Lookup
+            | Endpoint::Feed
+            | Endpoint::Sitemap
+            | Endpoint::CharacterNames
+    ) {
+        return None;
+    }
+    let mut query: Vec<(&str, &str)> = event.query_string_parameters.iter().collect();
+    query.sort_unstable();
+    let query: Vec<String> = query
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    let accept: Vec<&str> = event
+        .headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    // Cached bodies and links hold absolute URLs, so each origin gets its own entries.
+    Some(format!(
+        "{}{}?{} {}",
+        links::origin(event).unwrap_or_default(),
+        event.path.as_deref().unwrap_or("/"),
+        query.join("&"),
+        accept.join(",")
+    ))
+}
+
+/// Looks `key` up. `None` when caching is off or the store could not be read.
+pub async fn lookup(key: &str) -> Option<Lookup> {
+    let store = store().await?;
+    match store.get(key).await {
+        Ok(lookup) => Some(lookup),
+        Err(e) => {
+            log::warn!("response cache read failed: {}", e);
+            None
+        }
+    }
+}
+
+/// A cached response, marked with `X-Cache: HIT`.
+pub fn response(entry: Entry) -> ApiGatewayProxyResponse {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &entry.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    headers.insert("x-cache", HeaderValue::from_static("HIT"));
+    response::new(entry.status_code, headers, Body::Text(entry.body))
+}
+
+/// Caches a successful text response under `key` for `RESPONSE_CACHE_TTL_SECS`.
+pub async fn put(key: &str, generation: i64, resp: &ApiGatewayProxyResponse) {
+    let body = match (&resp.body, resp.status_code) {
+        (Some(Body::Text(body)), 200) => body.clone(),
+        _ => return,
+    };
+    let store = match store().await {
+        Some(store) => store,
+        None => return,
+    };
+    let entry = Entry {
+        status_code: resp.status_code,
+        headers: resp
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body,
+        expires_at: now() + config::var_or("RESPONSE_CACHE_TTL_SECS", 60),
+    };
+    if let Err(e) = store.put(key, generation, entry).await {
+        log::warn!("response cache write failed: {}", e);
+    }
+}
+
+/// Empties the cache after quotes changed. A failure is logged; entries then expire on
+/// their own.
+pub async fn invalidate() {
+    if let Some(store) = store().await {
+        if let Err(e) = store.invalidate().await {
+            log::warn!("response cache invalidation failed: {}", e);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// Responses kept in the container, up to `RESPONSE_CACHE_ENTRIES`.
+#[derive(Default)]
+struct Memory {
+    inner: Mutex<MemoryInner>,
+}
+
+#[derive(Default)]
+struct MemoryInner {
+    generation: i64,
+    entries: HashMap<String, (i64, Entry)>,
+}
+
+impl Store for Memory {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Lookup, String>> {
+        let inner = self.inner.lock().unwrap();
+        let entry = inner
+            .entries
+            .get(key)
+            .filter(|(generation, entry)| {
+                *generation == inner.generation && entry.expires_at > now()
+            })
+            .map(|(_, entry)| entry.clone());
+        let lookup = Lookup {
+            entry,
+            generation: inner.generation,
+        };
+        Box::pin(async move { Ok(lookup) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        generation: i64,
+        entry: Entry,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= config::var_or("RESPONSE_CACHE_ENTRIES", 1000) {
+            inner.entries.clear();
+        }
+        inner.entries.insert(key.to_string(), (generation, entry));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, Result<(), String>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(feature = "dynamodb")]
+mod dynamodb {
+    use aws_sdk_dynamodb::model::AttributeValue;
+    use futures::future::BoxFuture;
+
+    use super::{now, Entry, Lookup, Store};
+
+    // The item holding the current generation, next to the cached responses.
+    const GENERATION_KEY: &str = "#generation";
+
+    /// Responses in the DynamoDB table named by `RESPONSE_CACHE_TABLE`, with a string
+    /// partition key `key`. Enable TTL on its `expires_at` attribute to have DynamoDB delete
+    /// expired entries.
+    pub struct DynamoDb {
+        client: aws_sdk_dynamodb::Client,
+        table: String,
+    }
+
+    impl DynamoDb {
+        pub async fn new() -> DynamoDb {
+            let config = aws_config::load_from_env().await;
+            DynamoDb {
+                client: aws_sdk_dynamodb::Client::new(&config),
+                table: std::env::var("RESPONSE_CACHE_TABLE").unwrap_or_default(),
+            }
+        }
+    }
+
+    fn number(item: Option<&std::collections::HashMap<String, AttributeValue>>, name: &str) -> i64 {
+        item.and_then(|item| item.get(name))
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    impl Store for DynamoDb {
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Lookup, String>> {
+            Box::pin(async move {
+                let generation = self
+                    .client
+                    .get_item()
+                    .table_name(&self.table)
+                    .key("key", AttributeValue::S(GENERATION_KEY.to_string()))
+                    .consistent_read(true)
+                    .send();
+                let entry = self
+                    .client
+                    .get_item()
+                    .table_name(&self.table)
+                    .key("key", AttributeValue::S(key.to_string()))
+                    .send();
+                let (generation, entry) = futures::join!(generation, entry);
+                let generation =
+                    number(generation.map_err(|e| e.to_string())?.item(), "generation");
+                let entry = entry.map_err(|e| e.to_string())?;
+                let entry = entry
+                    .item()
+                    .filter(|item| number(Some(item), "generation") == generation)
+                    .and_then(|item| item.get("entry"))
+                    .and_then(|value| value.as_s().ok())
+                    .and_then(|entry| serde_json::from_str::<Entry>(entry).ok())
+                    .filter(|entry| entry.expires_at > now());
+                Ok(Lookup { entry, generation })
+            })
+        }
+
+        fn put<'a>(
+            &'a self,
+            key: &'a str,
+            generation: i64,
+            entry: Entry,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            Box::pin(async move {
+                let json = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+                self.client
+                    .put_item()
+                    .table_name(&self.table)
+                    .item("key", AttributeValue::S(key.to_string()))
+                    .item("generation", AttributeValue::N(generation.to_string()))
+                    .item("entry", AttributeValue::S(json))
+                    .item(
+                        "expires_at",
+                        AttributeValue::N(entry.expires_at.to_string()),
+                    )
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+        }
+
+        fn invalidate(&self) -> BoxFuture<'_, Result<(), String>> {
+            Box::pin(async move {
+                self.client
+                    .update_item()
+                    .table_name(&self.table)
+                    .key("key", AttributeValue::S(GENERATION_KEY.to_string()))
+                    .update_expression("ADD generation :one")
+                    .expression_attribute_values(":one", AttributeValue::N(String::from("1")))
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            })
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Compressed request bodies, sent with `Content-Encoding: gzip` or `deflate`.
+//!
+//! Bodies are inflated before any handler sees them, and never beyond
+//! `MAX_DECOMPRESSED_BYTES`, so a small body that inflates to gigabytes is refused instead of
+//! exhausting the Lambda's memory.
+
+use std::io::Read;
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use http::header::CONTENT_ENCODING;
+
+use crate::{config, response};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+/// The largest body inflation may produce, from `MAX_DECOMPRESSED_BYTES`.
+pub fn max_decompressed_bytes() -> u64 {
+    config::var_or("MAX_DECOMPRESSED_BYTES", 10 * 1024 * 1024)
+}
+
+/// Replaces a compressed body with its decompressed text and drops `Content-Encoding`.
+/// Requests without a body or without `Content-Encoding` are left as they are.
+pub fn decompress(
+    mut event: ApiGatewayProxyRequest,
+) -> Result<ApiGatewayProxyRequest, ApiGatewayProxyResponse> {
+    let encoding = match event.headers.get(CONTENT_ENCODING).map(|value| {
+        value
+            .to_str()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    }) {
+        None => return Ok(event),
+        Some(encoding) => match encoding.as_str() {
+            "" | "identity" => None,
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => {
+                return Err(response::problem(
+                    415,
+                    "Unsupported Media Type",
+                    "Content-Encoding must be gzip or deflate.",
+                ))
+            }
+        },
+    };
+    event.headers.remove(CONTENT_ENCODING);
+    let (encoding, body) = match (encoding, event.body.take()) {
+        (Some(encoding), Some(body)) => (encoding, body),
+        (_, body) => {
+            event.body = body;
+            return Ok(event);
+        }
+    };
+
+    let compressed = match event.is_base64_encoded.unwrap_or(false) {
+        true => openssl::base64::decode_block(&body).map_err(|_| corrupt())?,
+        false => body.into_bytes(),
+    };
+    let text = inflate(encoding, &compressed)?;
+    event.body = Some(text);
+    event.is_base64_encoded = Some(false);
+    Ok(event)
+}
+
+fn inflate(encoding: Encoding, compressed: &[u8]) -> Result<String, ApiGatewayProxyResponse> {
+    let limit = max_decompressed_bytes();
+    let read = |reader: &mut dyn Read| {
+        let mut out = Vec::new();
+        reader.take(limit + 1).read_to_end(&mut out).map(|_| out)
+    };
+    let inflated = match encoding {
+        Encoding::Gzip => read(&mut GzDecoder::new(compressed)),
+        // `deflate` is meant to be zlib-wrapped, but some clients send a raw stream.
+        Encoding::Deflate => read(&mut ZlibDecoder::new(compressed))
+            .or_else(|_| read(&mut DeflateDecoder::new(compressed))),
+    }
+    .map_err(|_| corrupt())?;
+
+    if inflated.len() as u64 > limit {
+        return Err(response::problem(
+            413,
+            "Payload Too Large",
+            &format!(
+                "The request body decompresses to more than {} bytes.",
+                limit
+            ),
+        ));
+    }
+    String::from_utf8(inflated).map_err(|_| {
+        response::problem(
+            400,
+            "Bad Request",
+            "The decompressed request body is not UTF-8 text.",
+        )
+    })
+}
+
+fn corrupt() -> ApiGatewayProxyResponse {
+    response::problem(
+        400,
+        "Bad Request",
+        "The request body is not valid for its Content-Encoding.",
+    )
+}
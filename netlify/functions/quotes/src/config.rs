@@ -0,0 +1,23 @@
+use std::str::FromStr;
+
+pub fn var_or<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// The CockroachDB region rows are homed in when `REGIONAL_BY_ROW` is enabled.
+///
+/// Uses `CRDB_REGION` when set, otherwise derives the Cockroach Cloud region name
+/// from the Lambda's `AWS_REGION`.
+pub fn crdb_region() -> Option<String> {
+    if !var_or("REGIONAL_BY_ROW", false) {
+        return None;
+    }
+    std::env::var("CRDB_REGION").ok().or_else(|| {
+        std::env::var("AWS_REGION")
+            .ok()
+            .map(|r| format!("aws-{}", r))
+    })
+}
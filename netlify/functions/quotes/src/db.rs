@@ -0,0 +1,761 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lambda_runtime::Error;
+use openssl::ssl::{SslConnector, SslMethod};
+use postgres_openssl::MakeTlsConnector;
+use serde::Serialize;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client;
+
+use crate::config;
+use crate::metrics::{self, Counter};
+use crate::{redact, xray};
+
+/// Errors from the database layer, classified so callers can map them to precise HTTP
+/// statuses. Statement-level variants carry the name of the repository statement that failed.
+#[derive(Debug)]
+pub enum DbError {
+    /// No connection could be established, or an open one was lost.
+    Connect {
+        host: Option<String>,
+        source: Option<tokio_postgres::Error>,
+    },
+    /// The TLS configuration could not be loaded.
+    Tls(Box<dyn std::error::Error + Send + Sync>),
+    /// The statement was cancelled, by the deadline handler or a statement timeout.
+    Timeout {
+        statement: &'static str,
+        source: tokio_postgres::Error,
+    },
+    /// An integrity constraint (SQLSTATE class 23) rejected the statement.
+    Constraint {
+        statement: &'static str,
+        source: tokio_postgres::Error,
+    },
+    /// CockroachDB aborted the transaction with a retryable serialization failure (40001).
+    Serialization {
+        statement: &'static str,
+        source: tokio_postgres::Error,
+    },
+    /// A returned row could not be read into the expected Rust types.
+    Mapping {
+        statement: &'static str,
+        source: tokio_postgres::Error,
+    },
+    /// Any other error reported for a statement.
+    Query {
+        statement: &'static str,
+        source: tokio_postgres::Error,
+    },
+    /// A statement that should always return a row, such as `INSERT ... RETURNING`, returned none.
+    NoRowReturned { statement: &'static str },
+}
+
+impl DbError {
+    /// Classifies an error returned while running `statement`.
+    pub fn from_query(statement: &'static str, source: tokio_postgres::Error) -> DbError {
+        let code = source.code().map(|code| code.code().to_string());
+        match code.as_deref() {
+            Some("40001") => DbError::Serialization { statement, source },
+            Some("57014") => DbError::Timeout { statement, source },
+            Some(code) if code.starts_with("23") => DbError::Constraint { statement, source },
+            Some(code) if code.starts_with("08") => DbError::Connect {
+                host: None,
+                source: Some(source),
+            },
+            None if source.is_closed() => DbError::Connect {
+                host: None,
+                source: Some(source),
+            },
+            _ => DbError::Query { statement, source },
+        }
+    }
+
+    pub fn mapping(statement: &'static str, source: tokio_postgres::Error) -> DbError {
+        DbError::Mapping { statement, source }
+    }
+
+    fn source_error(&self) -> Option<&tokio_postgres::Error> {
+        match self {
+            DbError::Connect { source, .. } => source.as_ref(),
+            DbError::Timeout { source, .. }
+            | DbError::Constraint { source, .. }
+            | DbError::Serialization { source, .. }
+            | DbError::Mapping { source, .. }
+            | DbError::Query { source, .. } => Some(source),
+            DbError::Tls(_) | DbError::NoRowReturned { .. } => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<&SqlState> {
+        self.source_error().and_then(|e| e.code())
+    }
+
+    /// The name of the statement that failed, for errors raised by a statement.
+    pub fn statement(&self) -> Option<&'static str> {
+        match self {
+            DbError::Timeout { statement, .. }
+            | DbError::Constraint { statement, .. }
+            | DbError::Serialization { statement, .. }
+            | DbError::Mapping { statement, .. }
+            | DbError::Query { statement, .. }
+            | DbError::NoRowReturned { statement } => Some(statement),
+            DbError::Connect { .. } | DbError::Tls(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    /// Driver messages may quote the connection string, so the text is always redacted.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact::redact(&Unredacted(self).to_string()))
+    }
+}
+
+struct Unredacted<'a>(&'a DbError);
+
+impl fmt::Display for Unredacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            DbError::Connect { host, source } => {
+                write!(f, "could not connect")?;
+                if let Some(host) = host {
+                    write!(f, " to {}", host)?;
+                }
+                match source {
+                    Some(source) => write!(f, ": {}", source),
+                    None => Ok(()),
+                }
+            }
+            DbError::Tls(source) => write!(f, "could not configure TLS: {}", source),
+            DbError::NoRowReturned { statement } => write!(f, "{} returned no row", statement),
+            DbError::Mapping { statement, source } => {
+                write!(f, "could not read the result of {}: {}", statement, source)
+            }
+            error => {
+                let statement = error.statement().unwrap_or_default();
+                match error.code() {
+                    Some(code) => write!(f, "{} failed [{}]", statement, code.code())?,
+                    None => write!(f, "{} failed", statement)?,
+                }
+                match error.source_error() {
+                    Some(source) => write!(f, ": {}", source),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Tls(source) => Some(source.as_ref()),
+            error => error
+                .source_error()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+        }
+    }
+}
+
+/// Attaches the statement name to errors from `tokio_postgres` calls.
+pub trait StatementContext<T> {
+    fn statement(self, statement: &'static str) -> Result<T, DbError>;
+}
+
+impl<T> StatementContext<T> for Result<T, tokio_postgres::Error> {
+    fn statement(self, statement: &'static str) -> Result<T, DbError> {
+        self.map_err(|e| DbError::from_query(statement, e))
+    }
+}
+
+// Hosts that recently refused a connection, keyed by `host[:port]`.
+static UNHEALTHY: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+// Clients kept alive between warm invocations, one per connection profile.
+static PRIMARY: Profile = Profile::new("primary");
+static READ: Profile = Profile::new("read");
+static PUBLIC: Profile = Profile::new("public");
+
+struct Profile {
+    name: &'static str,
+    slot: Mutex<Option<Cached>>,
+    acquisitions: Counter,
+    failed_acquisitions: Counter,
+    wait_micros: Counter,
+}
+
+struct Cached {
+    client: Arc<Client>,
+    connected_at: Instant,
+    last_used: Instant,
+}
+
+#[derive(Serialize)]
+pub struct PoolStats {
+    profile: &'static str,
+    size: usize,
+    available: usize,
+    acquisitions: u64,
+    failed_acquisitions: u64,
+    avg_wait_ms: f64,
+    connection_age_secs: Option<u64>,
+}
+
+impl Profile {
+    const fn new(name: &'static str) -> Profile {
+        Profile {
+            name,
+            slot: Mutex::new(None),
+            acquisitions: Counter::new(),
+            failed_acquisitions: Counter::new(),
+            wait_micros: Counter::new(),
+        }
+    }
+
+    fn stats(&self) -> PoolStats {
+        let slot = self.slot.lock().unwrap();
+        let acquisitions = self.acquisitions.get();
+        PoolStats {
+            profile: self.name,
+            size: slot.iter().count(),
+            available: slot
+                .iter()
+                .filter(|cached| Arc::strong_count(&cached.client) == 1)
+                .count(),
+            acquisitions,
+            failed_acquisitions: self.failed_acquisitions.get(),
+            avg_wait_ms: match acquisitions {
+                0 => 0.0,
+                n => self.wait_micros.get() as f64 / n as f64 / 1000.0,
+            },
+            connection_age_secs: slot
+                .as_ref()
+                .map(|cached| cached.connected_at.elapsed().as_secs()),
+        }
+    }
+}
+
+pub fn pool_stats() -> Vec<PoolStats> {
+    vec![PRIMARY.stats(), READ.stats(), PUBLIC.stats()]
+}
+
+/// Drops the cached clients so the next request starts from fresh connections.
+pub fn discard_clients() {
+    for profile in [&PRIMARY, &READ, &PUBLIC] {
+        if let Ok(mut slot) = profile.slot.lock() {
+            slot.take();
+        }
+    }
+}
+
+/// A connection string split around its host list so each host can be tried on its own.
+struct DatabaseUrl {
+    prefix: String,
+    hosts: Vec<String>,
+    suffix: String,
+}
+
+impl DatabaseUrl {
+    fn parse(url: &str) -> DatabaseUrl {
+        let authority_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+        let authority_end = url[authority_start..]
+            .find(|c| c == '/' || c == '?')
+            .map(|i| i + authority_start)
+            .unwrap_or(url.len());
+        let hosts_start = url[authority_start..authority_end]
+            .rfind('@')
+            .map(|i| i + authority_start + 1)
+            .unwrap_or(authority_start);
+
+        DatabaseUrl {
+            prefix: url[..hosts_start].to_string(),
+            hosts: url[hosts_start..authority_end]
+                .split(',')
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            suffix: url[authority_end..].to_string(),
+        }
+    }
+
+    fn for_host(&self, host: &str) -> String {
+        format!("{}{}{}", self.prefix, host, self.suffix)
+    }
+
+    /// The same URL with `password` in place of the one in its userinfo, if any.
+    fn with_password(&self, password: &str) -> DatabaseUrl {
+        let authority_start = self.prefix.find("://").map(|i| i + 3).unwrap_or(0);
+        let userinfo = self.prefix[authority_start..].trim_end_matches('@');
+        let user = userinfo.split(':').next().unwrap_or_default();
+        let password: String = password
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{:02X}", b),
+            })
+            .collect();
+
+        DatabaseUrl {
+            prefix: format!("{}{}:{}@", &self.prefix[..authority_start], user, password),
+            hosts: self.hosts.clone(),
+            suffix: self.suffix.clone(),
+        }
+    }
+}
+
+/// Orders hosts so that healthy ones are tried first, keeping the configured order otherwise.
+fn ordered_hosts(hosts: &[String]) -> Vec<String> {
+    let retry_after = Duration::from_secs(config::var_or("HOST_RETRY_SECS", 30));
+    let mut unhealthy = UNHEALTHY.lock().unwrap();
+    let unhealthy = unhealthy.get_or_insert_with(HashMap::new);
+    unhealthy.retain(|_, failed_at| failed_at.elapsed() < retry_after);
+
+    let (mut healthy, down): (Vec<String>, Vec<String>) = hosts
+        .iter()
+        .cloned()
+        .partition(|host| !unhealthy.contains_key(host));
+    healthy.extend(down);
+    healthy
+}
+
+fn mark_host(host: &str, healthy: bool) {
+    let mut unhealthy = UNHEALTHY.lock().unwrap();
+    let unhealthy = unhealthy.get_or_insert_with(HashMap::new);
+    if healthy {
+        unhealthy.remove(host);
+    } else {
+        unhealthy.insert(host.to_string(), Instant::now());
+    }
+}
+
+/// The TLS connector and the CA it trusts, built on the first connection and rebuilt only
+/// when the CA is refreshed.
+static TLS: Mutex<Option<(Vec<u8>, MakeTlsConnector)>> = Mutex::new(None);
+
+pub async fn tls_connector() -> Result<MakeTlsConnector, DbError> {
+    let tls = async {
+        let pem = ca_pem().await?;
+        if let Some((trusted, connector)) = TLS.lock().unwrap().as_ref() {
+            if *trusted == pem {
+                return Ok(connector.clone());
+            }
+        }
+        let cert = openssl::x509::X509::from_pem(&pem)?;
+        let mut ctx = SslConnector::builder(SslMethod::tls())?;
+        ctx.cert_store_mut().add_cert(cert)?;
+        if let Some((cert, key)) = client_identity().await? {
+            ctx.set_certificate(&openssl::x509::X509::from_pem(&cert)?)?;
+            ctx.set_private_key(&openssl::pkey::PKey::private_key_from_pem(&key)?)?;
+            ctx.check_private_key()?;
+        }
+        let connector = MakeTlsConnector::new(ctx.build());
+        *TLS.lock().unwrap() = Some((pem, connector.clone()));
+        Ok::<_, Error>(connector)
+    };
+    tls.await.map_err(DbError::Tls)
+}
+
+/// The client certificate and key, loaded once per container.
+static CLIENT_IDENTITY: Mutex<Option<(Vec<u8>, Vec<u8>)>> = Mutex::new(None);
+
+/// The PEM certificate and key a certificate-authenticated SQL user presents, when
+/// `DATABASE_AUTH=cert`. Password authentication through `DATABASE_URL` needs neither.
+async fn client_identity() -> Result<Option<(Vec<u8>, Vec<u8>)>, Error> {
+    if config::var_or("DATABASE_AUTH", String::from("password")) != "cert" {
+        return Ok(None);
+    }
+    if let Some(identity) = CLIENT_IDENTITY.lock().unwrap().clone() {
+        return Ok(Some(identity));
+    }
+    let identity = (
+        client_material("DATABASE_CLIENT_CERT").await?,
+        client_material("DATABASE_CLIENT_KEY").await?,
+    );
+    *CLIENT_IDENTITY.lock().unwrap() = Some(identity.clone());
+    Ok(Some(identity))
+}
+
+/// Reads PEM material from the `name` variable itself, the file named by `{name}_FILE`, or
+/// the Secrets Manager secret named by `{name}_SECRET_ID`, in that order.
+async fn client_material(name: &str) -> Result<Vec<u8>, Error> {
+    let var = |suffix: &str| {
+        std::env::var(format!("{}{}", name, suffix))
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+    if let Some(pem) = var("") {
+        return Ok(pem.into_bytes());
+    }
+    if let Some(path) = var("_FILE") {
+        return Ok(std::fs::read(path)?);
+    }
+    if let Some(secret_id) = var("_SECRET_ID") {
+        return Ok(secret_string(&secret_id, None).await?.into_bytes());
+    }
+    Err(format!(
+        "DATABASE_AUTH=cert requires {} or {}_FILE or {}_SECRET_ID",
+        name, name, name
+    )
+    .into())
+}
+
+/// A string secret from Secrets Manager, at `stage` or the current version.
+async fn secret_string(secret_id: &str, stage: Option<&str>) -> Result<String, Error> {
+    let _subsegment = xray::remote("secrets_manager");
+    let config = aws_config::load_from_env().await;
+    let output = aws_sdk_secretsmanager::Client::new(&config)
+        .get_secret_value()
+        .secret_id(secret_id)
+        .set_version_stage(stage.map(String::from))
+        .send()
+        .await?;
+    output
+        .secret_string()
+        .map(String::from)
+        .ok_or_else(|| format!("{} is not a string secret", secret_id).into())
+}
+
+/// The password read from `DATABASE_PASSWORD_SECRET_ID`, kept until the server rejects it.
+static DATABASE_PASSWORD: Mutex<Option<String>> = Mutex::new(None);
+
+/// The password read from `DATABASE_PASSWORD_SECRET_ID`, for [`redact`] to mask.
+pub(crate) fn cached_password() -> Option<String> {
+    DATABASE_PASSWORD.lock().unwrap().clone()
+}
+
+/// The SQL password from `DATABASE_PASSWORD_SECRET_ID`, overriding the one in the connection
+/// string. The secret is either the password itself or JSON with a `password` field, as
+/// written by the Secrets Manager rotation functions. `stage` re-reads that version stage
+/// instead of using the cached password.
+async fn database_password(stage: Option<&str>) -> Result<Option<String>, DbError> {
+    let secret_id = match std::env::var("DATABASE_PASSWORD_SECRET_ID") {
+        Ok(secret_id) if !secret_id.is_empty() => secret_id,
+        _ => return Ok(None),
+    };
+    if stage.is_none() {
+        if let Some(password) = DATABASE_PASSWORD.lock().unwrap().clone() {
+            return Ok(Some(password));
+        }
+    }
+
+    let secret = secret_string(&secret_id, stage).await.map_err(|e| {
+        log::error!(
+            "could not read DATABASE_PASSWORD_SECRET_ID: {}",
+            redact::redact(&e.to_string())
+        );
+        DbError::Connect {
+            host: None,
+            source: None,
+        }
+    })?;
+    let password = match serde_json::from_str::<serde_json::Value>(&secret) {
+        Ok(serde_json::Value::Object(fields)) => fields
+            .get("password")
+            .and_then(serde_json::Value::as_str)
+            .map(String::from)
+            .unwrap_or_default(),
+        _ => secret,
+    };
+    *DATABASE_PASSWORD.lock().unwrap() = Some(password.clone());
+    Ok(Some(password))
+}
+
+/// The cluster CA fetched from Cockroach Cloud, with when it was fetched.
+static CLOUD_CA: Mutex<Option<(Vec<u8>, Instant)>> = Mutex::new(None);
+
+/// The cluster CA as PEM. With `COCKROACH_CLUSTER_ID` it is downloaded from Cockroach Cloud
+/// and cached in memory and in `CA_CACHE_PATH` for `CA_CACHE_SECS`, so a rotated CA is
+/// picked up without a redeploy. Otherwise the bundled `cc-ca.crt` is used.
+async fn ca_pem() -> Result<Vec<u8>, Error> {
+    let cluster_id = match std::env::var("COCKROACH_CLUSTER_ID") {
+        Ok(id) if !id.is_empty() => id,
+        _ => return Ok(std::fs::read("../cc-ca.crt")?),
+    };
+    let max_age = Duration::from_secs(config::var_or("CA_CACHE_SECS", 24 * 60 * 60));
+    if let Some((pem, fetched)) = CLOUD_CA.lock().unwrap().as_ref() {
+        if fetched.elapsed() < max_age {
+            return Ok(pem.clone());
+        }
+    }
+
+    let path = config::var_or("CA_CACHE_PATH", String::from("/tmp/cc-ca.crt"));
+    let on_disk = std::fs::metadata(&path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .filter(|age| *age < max_age)
+        .and_then(|_| std::fs::read(&path).ok());
+    let pem = match on_disk {
+        Some(pem) => pem,
+        None => match fetch_ca(&cluster_id).await {
+            Ok(pem) => {
+                if let Err(e) = std::fs::write(&path, &pem) {
+                    log::warn!("failed to cache the cluster CA in {}: {}", path, e);
+                }
+                pem
+            }
+            // A stale copy still verifies the cluster until the CA actually rotates.
+            Err(e) => match std::fs::read(&path) {
+                Ok(pem) => {
+                    log::warn!(
+                        "failed to download the cluster CA, using the cached copy: {}",
+                        redact::redact(&e.to_string())
+                    );
+                    pem
+                }
+                Err(_) => return Err(e),
+            },
+        },
+    };
+    *CLOUD_CA.lock().unwrap() = Some((pem.clone(), Instant::now()));
+    Ok(pem)
+}
+
+async fn fetch_ca(cluster_id: &str) -> Result<Vec<u8>, Error> {
+    let _subsegment = xray::remote("ca_download");
+    let base = config::var_or(
+        "COCKROACH_CLOUD_URL",
+        String::from("https://cockroachlabs.cloud"),
+    );
+    let mut request = reqwest::Client::new().get(format!("{}/clusters/{}/cert", base, cluster_id));
+    if let Ok(token) = std::env::var("COCKROACH_API_TOKEN") {
+        request = request.bearer_auth(token);
+    }
+    let pem = request
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec();
+    openssl::x509::X509::from_pem(&pem)?;
+    Ok(pem)
+}
+
+pub async fn get_db_client() -> Result<Arc<Client>, DbError> {
+    cached(&PRIMARY, connect_primary).await
+}
+
+/// Returns the client for the read profile used by GET routes.
+///
+/// `DATABASE_READ_URL` points reads at a separate connection string, and
+/// `FOLLOWER_READS=true` serves them from the nearest replica at a slightly stale timestamp.
+/// Without either, reads share the primary client.
+pub async fn get_read_client() -> Result<Arc<Client>, DbError> {
+    if std::env::var("DATABASE_READ_URL").is_err() && !config::var_or("FOLLOWER_READS", false) {
+        return get_db_client().await;
+    }
+    cached(&READ, connect_read).await
+}
+
+/// Whether `DATABASE_PUBLIC_URL` gives unauthenticated callers their own read-only role.
+pub fn public_mode() -> bool {
+    std::env::var("DATABASE_PUBLIC_URL").is_ok()
+}
+
+/// Returns the client for `GET` requests from unauthenticated callers. It connects with
+/// `DATABASE_PUBLIC_URL`, meant for a SQL role that may only read, so abuse of the public
+/// routes cannot change data. Without it, they share the read profile.
+pub async fn get_public_client() -> Result<Arc<Client>, DbError> {
+    if !public_mode() {
+        return get_read_client().await;
+    }
+    cached(&PUBLIC, connect_public).await
+}
+
+/// Reuses the profile's cached client if it is still alive, reconnecting otherwise.
+async fn cached<F, Fut>(profile: &Profile, connect: F) -> Result<Arc<Client>, DbError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Client, DbError>>,
+{
+    let started = Instant::now();
+    let previous = profile.slot.lock().unwrap().take();
+    let reconnecting = previous.is_some();
+
+    let (client, connected_at) = match previous {
+        Some(cached) if is_alive(&cached).await => (cached.client, cached.connected_at),
+        _ => match connect().await {
+            Ok(client) => {
+                if reconnecting {
+                    let total = metrics::RECONNECTS.incr();
+                    metrics::emit("Reconnects", 1.0, "Count");
+                    log::info!("reconnected to the database ({} reconnects)", total);
+                }
+                (Arc::new(client), Instant::now())
+            }
+            Err(e) => {
+                profile.failed_acquisitions.incr();
+                metrics::emit("FailedAcquisitions", 1.0, "Count");
+                return Err(e);
+            }
+        },
+    };
+
+    let waited = started.elapsed();
+    profile.acquisitions.incr();
+    profile.wait_micros.add(waited.as_micros() as u64);
+    metrics::emit("AcquireTime", waited.as_secs_f64() * 1000.0, "Milliseconds");
+
+    *profile.slot.lock().unwrap() = Some(Cached {
+        client: client.clone(),
+        connected_at,
+        last_used: Instant::now(),
+    });
+    Ok(client)
+}
+
+/// Checks a cached client, pinging the server when it has been idle for `LIVENESS_IDLE_SECS`.
+async fn is_alive(cached: &Cached) -> bool {
+    if cached.client.is_closed() {
+        return false;
+    }
+
+    let idle_limit = Duration::from_secs(config::var_or("LIVENESS_IDLE_SECS", 30));
+    if cached.last_used.elapsed() < idle_limit {
+        return true;
+    }
+
+    let ping = cached.client.simple_query("SELECT 1");
+    matches!(
+        tokio::time::timeout(Duration::from_secs(2), ping).await,
+        Ok(Ok(_))
+    )
+}
+
+async fn connect_primary() -> Result<Client, DbError> {
+    let database_url = std::env::var("DATABASE_URL").expect("Must have a DATABASE_URL set");
+    let mut url = DatabaseUrl::parse(&database_url);
+    if let Ok(hosts) = std::env::var("DATABASE_HOSTS") {
+        url.hosts = hosts
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect();
+    }
+
+    connect(&url).await
+}
+
+async fn connect_read() -> Result<Client, DbError> {
+    let client = match std::env::var("DATABASE_READ_URL") {
+        Ok(read_url) => connect(&DatabaseUrl::parse(&read_url)).await?,
+        Err(_) => connect_primary().await?,
+    };
+
+    if config::var_or("FOLLOWER_READS", false) {
+        client
+            .batch_execute("SET default_transaction_use_follower_reads = on;")
+            .await
+            .statement("follower_reads")?;
+    }
+
+    Ok(client)
+}
+
+// The public role authenticates with the password in its own URL, since
+// `DATABASE_PASSWORD_SECRET_ID` holds the password of the read-write role.
+async fn connect_public() -> Result<Client, DbError> {
+    let public_url = std::env::var("DATABASE_PUBLIC_URL").unwrap_or_default();
+    let client = connect_hosts(&DatabaseUrl::parse(&public_url)).await?;
+
+    let mut settings = String::from("SET default_transaction_read_only = on;");
+    if config::var_or("FOLLOWER_READS", false) {
+        settings.push_str(" SET default_transaction_use_follower_reads = on;");
+    }
+    client
+        .batch_execute(&settings)
+        .await
+        .statement("public_session")?;
+    Ok(client)
+}
+
+/// Connects with the password from `DATABASE_PASSWORD_SECRET_ID` when one is configured.
+///
+/// When the server rejects it, the secret was probably rotated: the current version is read
+/// again, then the pending one in case rotation has changed the password but not yet
+/// promoted it. The cached clients are dropped so they reconnect with the new password.
+async fn connect(url: &DatabaseUrl) -> Result<Client, DbError> {
+    let password = match database_password(None).await? {
+        Some(password) => password,
+        None => return connect_hosts(url).await,
+    };
+
+    let mut result = connect_hosts(&url.with_password(&password)).await;
+    for stage in ["AWSCURRENT", "AWSPENDING"] {
+        match &result {
+            Err(e) if is_auth_failure(e) => {
+                log::warn!("the database rejected the password, reading {}", stage);
+                let password = match database_password(Some(stage)).await {
+                    Ok(Some(password)) => password,
+                    _ => continue,
+                };
+                discard_clients();
+                result = connect_hosts(&url.with_password(&password)).await;
+                if result.is_ok() {
+                    metrics::emit("PasswordRefreshes", 1.0, "Count");
+                }
+            }
+            _ => break,
+        }
+    }
+    result
+}
+
+/// Whether the server refused the credentials (SQLSTATE class 28).
+fn is_auth_failure(error: &DbError) -> bool {
+    matches!(error.code(), Some(code) if code.code().starts_with("28"))
+}
+
+async fn connect_hosts(url: &DatabaseUrl) -> Result<Client, DbError> {
+    let connector = {
+        let _subsegment = xray::remote("tls");
+        tls_connector().await?
+    };
+    let mut last_error = None;
+
+    for host in ordered_hosts(&url.hosts) {
+        let _subsegment = xray::remote("connect");
+        match tokio_postgres::connect(&url.for_host(&host), connector.clone()).await {
+            Ok((client, connection)) => {
+                mark_host(&host, true);
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        eprintln!("connection error: {}", redact::redact(&e.to_string()));
+                    }
+                });
+                return Ok(client);
+            }
+            Err(e) => {
+                log::warn!(
+                    "failed to connect to {}: {}",
+                    host,
+                    redact::redact(&e.to_string())
+                );
+                mark_host(&host, false);
+                last_error = Some((host, e));
+            }
+        }
+    }
+
+    match last_error {
+        Some((host, e)) => Err(DbError::Connect {
+            host: Some(host),
+            source: Some(e),
+        }),
+        None => {
+            log::error!("DATABASE_URL does not contain any hosts");
+            Err(DbError::Connect {
+                host: None,
+                source: None,
+            })
+        }
+    }
+}
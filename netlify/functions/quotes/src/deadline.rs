@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lambda_runtime::{Context, Error};
+use tokio::time::Instant;
+use tokio_postgres::CancelToken;
+
+use crate::{config, db, redact};
+
+// When list queries stop reading rows and answer with what they have. Lambda hands a
+// process one request at a time, so it lives in a static like the request timings.
+static SOFT_DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// When in-flight work for this invocation has to be abandoned.
+///
+/// Leaves `DEADLINE_MARGIN_MS` before the Lambda timeout to cancel queries and
+/// respond. Returns `None` when the runtime did not report a deadline.
+pub fn from_context(context: &Context) -> Option<Instant> {
+    if context.deadline == 0 {
+        return None;
+    }
+
+    let deadline = UNIX_EPOCH + Duration::from_millis(context.deadline);
+    let remaining = deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    let margin = Duration::from_millis(config::var_or("DEADLINE_MARGIN_MS", 500));
+    Some(Instant::now() + remaining.saturating_sub(margin))
+}
+
+/// Starts the budget for partial results: `PARTIAL_RESULTS_MS` before `deadline`, leaving
+/// time to serialize a partial page. `0` disables partial results.
+pub fn start_budget(deadline: Option<Instant>) {
+    let reserve = Duration::from_millis(config::var_or("PARTIAL_RESULTS_MS", 1000));
+    *SOFT_DEADLINE.lock().unwrap() = match reserve.is_zero() {
+        true => None,
+        false => deadline.and_then(|deadline| deadline.checked_sub(reserve)),
+    };
+}
+
+/// When list queries should give up on the rest of their rows, if partial results are on.
+pub fn soft_deadline() -> Option<Instant> {
+    *SOFT_DEADLINE.lock().unwrap()
+}
+
+/// Whether the budget for this invocation has already run out.
+pub fn budget_spent() -> bool {
+    soft_deadline().map_or(false, |soft| Instant::now() >= soft)
+}
+
+/// Asks the server to cancel whatever statement the token's connection is running.
+pub async fn cancel(token: CancelToken) {
+    let result = match db::tls_connector().await {
+        Ok(tls) => token.cancel_query(tls).await.map_err(Error::from),
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        log::warn!("failed to cancel query: {}", redact::redact(&e.to_string()));
+    }
+}
@@ -0,0 +1,90 @@
+//! Migration warnings on deprecated routes: a `Deprecation` header (RFC 9745), a `Sunset`
+//! header (RFC 8594) once a removal date is set, a `successor-version` link, and the same
+//! under `meta.deprecation` in JSON envelopes.
+
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use http::header::{HeaderValue, CONTENT_TYPE, LINK};
+use serde_json::{json, Value};
+
+use crate::router::Deprecation;
+
+fn time(unix: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(unix, 0).single()
+}
+
+/// Announces `deprecation` on `resp`. `successor` is the path of the route replacing it.
+pub fn apply(
+    mut resp: ApiGatewayProxyResponse,
+    deprecation: &Deprecation,
+    successor: Option<&str>,
+) -> ApiGatewayProxyResponse {
+    if let Ok(value) = HeaderValue::from_str(&format!("@{}", deprecation.since)) {
+        resp.headers.insert("deprecation", value);
+    }
+    let sunset = deprecation.sunset.and_then(time);
+    if let Some(sunset) = sunset {
+        let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(value) = HeaderValue::from_str(&date) {
+            resp.headers.insert("sunset", value);
+        }
+    }
+    if let Some(successor) = successor {
+        // Keeps the links already set, such as pagination.
+        let link = format!("<{}>; rel=\"successor-version\"", successor);
+        let link = match resp.headers.get(LINK).and_then(|value| value.to_str().ok()) {
+            Some(existing) => format!("{}, {}", existing, link),
+            None => link,
+        };
+        if let Ok(value) = HeaderValue::from_str(&link) {
+            resp.headers.insert(LINK, value);
+        }
+    }
+
+    let notice = json!({
+        "deprecated_at": time(deprecation.since)
+            .map(|since| since.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "sunset": sunset.map(|sunset| sunset.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "successor": successor,
+    });
+    annotate(&mut resp, notice);
+    resp
+}
+
+// Adds `meta.deprecation` to JSON and JSON:API envelopes. Problem documents and other
+// bodies are left alone.
+fn annotate(resp: &mut ApiGatewayProxyResponse, notice: Value) {
+    let is_envelope = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| {
+            value.starts_with("application/json") || value.starts_with("application/vnd.api+json")
+        });
+    if !is_envelope {
+        return;
+    }
+    let mut body: Value = match &resp.body {
+        Some(Body::Text(body)) => match serde_json::from_str(body) {
+            Ok(body) => body,
+            Err(_) => return,
+        },
+        _ => return,
+    };
+    let envelope = match body.as_object_mut() {
+        Some(envelope) => envelope,
+        None => return,
+    };
+    match envelope
+        .entry("meta")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+    {
+        Some(meta) => {
+            meta.insert(String::from("deprecation"), notice);
+        }
+        None => return,
+    }
+    resp.body = Some(Body::Text(body.to_string()));
+}
@@ -0,0 +1,149 @@
+//! The API Gateway REST (payload format 1.0) request the handlers work with, from whichever
+//! kind of event invoked the function.
+//!
+//! Netlify and REST APIs send format 1.0 as it is. HTTP APIs send payload format 2.0 and
+//! Application Load Balancers send their own shape, so both are rewritten into 1.0 before
+//! deserializing.
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use http::header::CONTENT_ENCODING;
+use serde_json::{json, Map, Value};
+
+/// Deserializes the invocation payload. A base64 body that decodes to UTF-8 text is decoded
+/// here, so handlers only see base64 for binary and compressed bodies.
+pub fn request(payload: Value) -> Result<ApiGatewayProxyRequest, serde_json::Error> {
+    let payload = if payload["version"] == "2.0" {
+        from_http_api(payload)
+    } else if payload["requestContext"]["elb"].is_object() {
+        from_load_balancer(payload)
+    } else {
+        payload
+    };
+    let mut event: ApiGatewayProxyRequest = serde_json::from_value(payload)?;
+    decode_text_body(&mut event);
+    Ok(event)
+}
+
+fn from_http_api(mut payload: Value) -> Value {
+    let context = payload["requestContext"].take();
+    let http = &context["http"];
+
+    let mut headers = match payload["headers"].take() {
+        Value::Object(headers) => headers,
+        _ => Map::new(),
+    };
+    // Format 2.0 moves cookies out of the headers.
+    if let Some(cookies) = payload["cookies"].as_array() {
+        let cookies: Vec<&str> = cookies.iter().filter_map(Value::as_str).collect();
+        if !cookies.is_empty() {
+            headers.insert("cookie".to_string(), json!(cookies.join("; ")));
+        }
+    }
+    let (query, multi_query) = query_maps(
+        form_urlencoded::parse(payload["rawQueryString"].as_str().unwrap_or("").as_bytes())
+            .map(|(name, value)| (name.into_owned(), value.into_owned())),
+    );
+
+    json!({
+        "resource": context["routeKey"],
+        "path": payload["rawPath"],
+        "httpMethod": http["method"],
+        "headers": headers,
+        "queryStringParameters": query,
+        "multiValueQueryStringParameters": multi_query,
+        "pathParameters": payload["pathParameters"],
+        "stageVariables": payload["stageVariables"],
+        "requestContext": {
+            "accountId": context["accountId"],
+            "apiId": context["apiId"],
+            "domainName": context["domainName"],
+            "domainPrefix": context["domainPrefix"],
+            "requestId": context["requestId"],
+            "stage": context["stage"],
+            "protocol": http["protocol"],
+            "httpMethod": http["method"],
+            "requestTime": context["time"],
+            "requestTimeEpoch": context["timeEpoch"].as_i64().unwrap_or_default(),
+            "identity": {
+                "sourceIp": http["sourceIp"],
+                "userAgent": http["userAgent"],
+            },
+            "authorizer": match &context["authorizer"] {
+                Value::Object(authorizer) => Value::Object(authorizer.clone()),
+                _ => json!({}),
+            },
+        },
+        "body": payload["body"],
+        "isBase64Encoded": payload["isBase64Encoded"].as_bool().unwrap_or(false),
+    })
+}
+
+fn from_load_balancer(mut payload: Value) -> Value {
+    // Load balancers pass query strings on still percent-encoded.
+    let pairs = match (
+        &payload["multiValueQueryStringParameters"],
+        &payload["queryStringParameters"],
+    ) {
+        (Value::Object(params), _) => params
+            .iter()
+            .flat_map(|(name, values)| {
+                values
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(move |value| (name.as_str(), value))
+            })
+            .map(|(name, value)| (decode(name), decode(value)))
+            .collect::<Vec<_>>(),
+        (_, Value::Object(params)) => params
+            .iter()
+            .filter_map(|(name, value)| Some((decode(name), decode(value.as_str()?))))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let (query, multi_query) = query_maps(pairs.into_iter());
+    payload["queryStringParameters"] = query;
+    payload["multiValueQueryStringParameters"] = multi_query;
+    payload["requestContext"] = json!({ "httpMethod": payload["httpMethod"] });
+    payload
+}
+
+/// The single- and multi-value query maps of format 1.0; the single-value map keeps the
+/// last value of a repeated parameter, as API Gateway does.
+fn query_maps(pairs: impl Iterator<Item = (String, String)>) -> (Value, Value) {
+    let mut query = Map::new();
+    let mut multi_query = Map::new();
+    for (name, value) in pairs {
+        query.insert(name.clone(), json!(value));
+        match multi_query.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(json!(value)),
+            _ => {
+                multi_query.insert(name, json!([value]));
+            }
+        }
+    }
+    (Value::Object(query), Value::Object(multi_query))
+}
+
+fn decode(text: &str) -> String {
+    form_urlencoded::parse(format!("x={}", text).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+fn decode_text_body(event: &mut ApiGatewayProxyRequest) {
+    if !event.is_base64_encoded.unwrap_or(false) || event.headers.contains_key(CONTENT_ENCODING) {
+        return;
+    }
+    let text = event
+        .body
+        .as_deref()
+        .and_then(|body| openssl::base64::decode_block(body).ok())
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    if let Some(text) = text {
+        event.body = Some(text);
+        event.is_base64_encoded = Some(false);
+    }
+}
@@ -0,0 +1,85 @@
+//! Atom feed of the most recently added quotes.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use http::header::{HeaderValue, CACHE_CONTROL};
+use lambda_runtime::Error;
+use tokio_postgres::Client;
+
+use crate::quotes::{self, Quote};
+use crate::{links, response};
+
+const FEED_SIZE: i64 = 50;
+
+pub async fn handle(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let entries = quotes::recent_quotes(client, FEED_SIZE).await?;
+    let feed = atom(event, &entries);
+
+    let mut resp = response::body(200, "application/atom+xml; charset=utf-8", feed);
+    resp.headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=300"),
+    );
+    Ok(resp)
+}
+
+fn atom(event: &ApiGatewayProxyRequest, entries: &[(Quote, DateTime<Utc>)]) -> String {
+    let feed_url = links::request_url(event);
+    let updated = entries
+        .iter()
+        .map(|(_, created_at)| *created_at)
+        .max()
+        .unwrap_or_else(Utc::now);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("  <title>Star Trek quotes</title>\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape(&feed_url)));
+    xml.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}\"/>\n",
+        escape(&feed_url)
+    ));
+    xml.push_str(&format!("  <updated>{}</updated>\n", timestamp(&updated)));
+
+    for (quote, created_at) in entries {
+        let url = links::for_quote(event, quote.public_id()).self_link;
+        let characters = quote
+            .characters_text()
+            .unwrap_or_else(|| String::from("Unknown"));
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", escape(&url)));
+        xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&url)));
+        xml.push_str(&format!("    <title>{}</title>\n", escape(&characters)));
+        xml.push_str(&format!(
+            "    <author><name>{}</name></author>\n",
+            escape(&characters)
+        ));
+        xml.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            timestamp(created_at)
+        ));
+        xml.push_str(&format!(
+            "    <content type=\"text\">{}</content>\n",
+            escape(quote.quote.as_deref().unwrap_or_default())
+        ));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+pub(crate) fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use async_graphql::{Context, EmptySubscription, InputObject, Object, Schema, ID};
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use lambda_runtime::Error;
+use rust_decimal::Decimal;
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::quotes::{self, Quote};
+use crate::response;
+
+pub struct QueryRoot;
+
+pub struct MutationRoot;
+
+#[derive(InputObject)]
+struct QuoteInput {
+    quote: Option<String>,
+    characters: Option<Vec<String>>,
+    stardate: Option<Decimal>,
+    episode: Option<i64>,
+}
+
+impl From<QuoteInput> for Quote {
+    fn from(input: QuoteInput) -> Quote {
+        Quote {
+            rowid: None,
+            uuid: None,
+            slug: None,
+            quote: input.quote,
+            characters: input.characters,
+            stardate: input.stardate,
+            episode: input.episode,
+            lang: None,
+            created_by: None,
+            lines: None,
+            highlight: None,
+        }
+    }
+}
+
+#[Object(name = "Quote")]
+impl Quote {
+    async fn rowid(&self) -> Option<ID> {
+        match quotes::uuid_ids() {
+            true => None,
+            false => self.rowid.map(|rowid| ID(rowid.to_string())),
+        }
+    }
+
+    async fn uuid(&self) -> Option<ID> {
+        self.uuid.map(|uuid| ID(uuid.to_string()))
+    }
+
+    async fn slug(&self) -> Option<&str> {
+        self.slug.as_deref()
+    }
+
+    async fn quote(&self) -> Option<&str> {
+        self.quote.as_deref()
+    }
+
+    /// All speakers joined with commas.
+    async fn characters(&self) -> Option<String> {
+        self.characters_text()
+    }
+
+    async fn character_list(&self) -> Option<&[String]> {
+        self.characters.as_deref()
+    }
+
+    async fn stardate(&self) -> Option<Decimal> {
+        self.stardate
+    }
+
+    async fn episode(&self) -> Option<i64> {
+        self.episode
+    }
+}
+
+#[Object]
+impl QueryRoot {
+    async fn quotes(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1)] page: i64,
+        limit: Option<i64>,
+    ) -> async_graphql::Result<Vec<Quote>> {
+        let client = ctx.data::<Arc<Client>>()?;
+        let page =
+            quotes::get_quotes(client, page, quotes::page_size(limit), None, None, None).await?;
+        Ok(page.quotes)
+    }
+
+    /// Looks a quote up by rowid, uuid or slug.
+    async fn quote(&self, ctx: &Context<'_>, rowid: ID) -> async_graphql::Result<Option<Quote>> {
+        let client = ctx.data::<Arc<Client>>()?;
+        match quotes::resolve_id(client, &rowid).await? {
+            Some(rowid) => Ok(quotes::get_quote(client, rowid).await?),
+            None => Ok(None),
+        }
+    }
+}
+
+#[Object]
+impl MutationRoot {
+    async fn create_quote(
+        &self,
+        ctx: &Context<'_>,
+        input: QuoteInput,
+    ) -> async_graphql::Result<Quote> {
+        let client = ctx.data::<Arc<Client>>()?;
+        let mut quote: Quote = input.into();
+        quote.created_by = ctx.data::<Principal>()?.owner();
+        Ok(quotes::insert_quote(client, quote).await?)
+    }
+
+    async fn update_quote(
+        &self,
+        ctx: &Context<'_>,
+        rowid: ID,
+        input: QuoteInput,
+    ) -> async_graphql::Result<Option<Quote>> {
+        let client = ctx.data::<Arc<Client>>()?;
+        match quotes::resolve_id(client, &rowid).await? {
+            Some(rowid) => {
+                check_owner(ctx, client, rowid).await?;
+                Ok(quotes::update_quote(client, rowid, input.into()).await?)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_quote(&self, ctx: &Context<'_>, rowid: ID) -> async_graphql::Result<bool> {
+        let client = ctx.data::<Arc<Client>>()?;
+        match quotes::resolve_id(client, &rowid).await? {
+            Some(rowid) => {
+                check_owner(ctx, client, rowid).await?;
+                Ok(quotes::delete_quote(client, rowid).await? > 0)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+async fn check_owner(ctx: &Context<'_>, client: &Client, rowid: i64) -> async_graphql::Result<()> {
+    let owner = quotes::quote_owner(client, rowid, quotes::Lock::None).await?;
+    match ctx.data::<Principal>()?.may_modify(owner.as_deref()) {
+        true => Ok(()),
+        false => Err("Only the caller who submitted this quote or an admin may change it.".into()),
+    }
+}
+
+/// Executes the GraphQL request in the event body against the quotes schema.
+pub async fn handle(
+    event: &ApiGatewayProxyRequest,
+    client: Arc<Client>,
+    principal: Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let request: async_graphql::Request = match event.body.as_deref() {
+        Some(body) => serde_json::from_str(body)?,
+        None => return Ok(response::text(400, "request body is required")),
+    };
+
+    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish();
+    let resp = schema.execute(request.data(client).data(principal)).await;
+
+    Ok(response::json(200, serde_json::to_string(&resp)?))
+}
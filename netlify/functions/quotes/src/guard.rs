@@ -0,0 +1,46 @@
+//! Refuses writes when the database is not the cluster this deployment expects, so that a
+//! staging build pointed at the production `DATABASE_URL` cannot change production data.
+
+use std::sync::Mutex;
+
+use tokio_postgres::Client;
+
+use crate::db::{DbError, StatementContext};
+use crate::xray;
+
+// The cluster a process is connected to does not change while it is warm.
+static CLUSTER_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether writes may go to `client`'s cluster: always when `EXPECTED_CLUSTER_ID` is unset,
+/// otherwise only when it matches `crdb_internal.cluster_id()`.
+pub async fn writes_allowed(client: &Client) -> Result<bool, DbError> {
+    let expected = match std::env::var("EXPECTED_CLUSTER_ID") {
+        Ok(expected) if !expected.trim().is_empty() => expected,
+        _ => return Ok(true),
+    };
+
+    let cached = CLUSTER_ID.lock().unwrap().clone();
+    let found = match cached {
+        Some(found) => found,
+        None => {
+            let _subsegment = xray::sql("cluster_id");
+            let row = client
+                .query_one("SELECT crdb_internal.cluster_id()::STRING;", &[])
+                .await
+                .statement("cluster_id")?;
+            let found: String = row.get(0);
+            *CLUSTER_ID.lock().unwrap() = Some(found.clone());
+            found
+        }
+    };
+
+    let allowed = found.eq_ignore_ascii_case(expected.trim());
+    if !allowed {
+        log::error!(
+            "refusing writes: connected to cluster {} but EXPECTED_CLUSTER_ID is {}",
+            found,
+            expected.trim()
+        );
+    }
+    Ok(allowed)
+}
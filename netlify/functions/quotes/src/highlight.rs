@@ -0,0 +1,57 @@
+//! Marks the words of a search in quote text, so UIs can show why a quote matched.
+
+use crate::config;
+
+/// Wraps every case-insensitive occurrence of a word of `q` in `text` with the
+/// `HIGHLIGHT_PRE` and `HIGHLIGHT_POST` markers, `<em>` and `</em>` by default.
+///
+/// Words shorter than two characters are ignored, and overlapping matches are merged
+/// into one marked span.
+pub fn highlight(text: &str, q: &str) -> String {
+    let pre = config::var_or("HIGHLIGHT_PRE", String::from("<em>"));
+    let post = config::var_or("HIGHLIGHT_POST", String::from("</em>"));
+
+    let mut spans = Vec::new();
+    for term in q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() > 1)
+    {
+        spans.extend(find_all(text, term));
+    }
+    spans.sort_unstable();
+
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut at = 0;
+    for (start, end) in merged {
+        out.push_str(&text[at..start]);
+        out.push_str(&pre);
+        out.push_str(&text[start..end]);
+        out.push_str(&post);
+        at = end;
+    }
+    out.push_str(&text[at..]);
+    out
+}
+
+/// Byte ranges of `term` in `text`, ignoring ASCII case.
+fn find_all(text: &str, term: &str) -> Vec<(usize, usize)> {
+    let (haystack, needle) = (text.as_bytes(), term.as_bytes());
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return Vec::new();
+    }
+    (0..=haystack.len() - needle.len())
+        .filter(|&start| {
+            text.is_char_boundary(start) && text.is_char_boundary(start + needle.len())
+        })
+        .filter(|&start| haystack[start..start + needle.len()].eq_ignore_ascii_case(needle))
+        .map(|start| (start, start + needle.len()))
+        .collect()
+}
@@ -0,0 +1,294 @@
+//! Resumable bulk imports for `POST /quotes/import`, run as `import` jobs.
+//!
+//! The submitted quotes are the job's input and are inserted in chunks of
+//! `IMPORT_CHUNK_SIZE`, one chunk per job step, so each chunk commits together with the
+//! import's progress.
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio_postgres::Client;
+
+use crate::db::{DbError, StatementContext};
+use crate::jobs::{self, Job, Kind, Step};
+use crate::moderation::{self, Verdict};
+use crate::quotes::{self, Quote};
+use crate::{config, multipart, xray};
+
+/// The `progress` of an import job.
+#[serde_as]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Progress {
+    rows_total: i64,
+    rows_processed: i64,
+    bytes_total: i64,
+    bytes_processed: i64,
+    /// Rowid of the last quote inserted.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    last_key: Option<i64>,
+    /// `{index, error}` for every row that could not be inserted.
+    failures: Vec<Value>,
+}
+
+/// How an uploaded file is read, from the optional `options` part of an upload.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+struct UploadOptions {
+    /// `csv`, `json` or `ndjson`; guessed from the file's content type or name when not given.
+    format: Option<String>,
+    delimiter: char,
+    /// Separates the speakers in the `characters` column of a CSV file.
+    characters_separator: String,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            format: None,
+            delimiter: ',',
+            characters_separator: String::from(";"),
+        }
+    }
+}
+
+/// Parses a JSON array of quotes. Builds with the `simd` feature parse with simd-json, which
+/// is several times faster on multi-megabyte bodies.
+pub fn parse_json(body: &[u8]) -> Result<Vec<Value>, String> {
+    const INVALID: &str = "The request body must be a JSON array of quotes.";
+    #[cfg(feature = "simd")]
+    {
+        // simd-json parses in place.
+        let mut body = body.to_vec();
+        simd_json::serde::from_slice(&mut body).map_err(|_| String::from(INVALID))
+    }
+    #[cfg(not(feature = "simd"))]
+    {
+        serde_json::from_slice(body).map_err(|_| String::from(INVALID))
+    }
+}
+
+/// Parses newline-delimited JSON, one quote per line. Blank lines are skipped.
+pub fn parse_ndjson(body: &[u8]) -> Result<Vec<Value>, String> {
+    body.split(|&b| b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(i, line)| {
+            let invalid = || format!("Line {} is not a JSON object.", i + 1);
+            #[cfg(feature = "simd")]
+            let item: Value =
+                simd_json::serde::from_slice(&mut line.to_vec()).map_err(|_| invalid())?;
+            #[cfg(not(feature = "simd"))]
+            let item: Value = serde_json::from_slice(line).map_err(|_| invalid())?;
+            match item.is_object() {
+                true => Ok(item),
+                false => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// The quotes in a `multipart/form-data` upload: its `file` part holds a CSV file with a
+/// header row naming quote fields, or a JSON array, and an optional `options` part holds
+/// [`UploadOptions`] as JSON. `None` when the request is not an upload; the error explains
+/// what is wrong with one.
+pub fn from_upload(event: &ApiGatewayProxyRequest) -> Option<Result<Vec<Value>, String>> {
+    let boundary = multipart::boundary(event)?;
+    Some(read_upload(event, &boundary))
+}
+
+fn read_upload(event: &ApiGatewayProxyRequest, boundary: &str) -> Result<Vec<Value>, String> {
+    let parts = multipart::parts(event, boundary)?;
+    let options = match parts
+        .iter()
+        .find(|part| part.name.as_deref() == Some("options"))
+    {
+        Some(part) => serde_json::from_slice(&part.body)
+            .map_err(|e| format!("The options part is not valid: {}", e))?,
+        None => UploadOptions::default(),
+    };
+    let file = parts
+        .iter()
+        .find(|part| part.name.as_deref() == Some("file"))
+        .ok_or("The upload has no file part.")?;
+
+    let format = options.format.clone().unwrap_or_else(|| {
+        let content_type = file.content_type.as_deref().unwrap_or_default();
+        let filename = file.filename.as_deref().unwrap_or_default();
+        let format = if content_type.contains("ndjson")
+            || filename.ends_with(".ndjson")
+            || filename.ends_with(".jsonl")
+        {
+            "ndjson"
+        } else if content_type.contains("json") || filename.ends_with(".json") {
+            "json"
+        } else {
+            "csv"
+        };
+        String::from(format)
+    });
+    match format.as_str() {
+        "json" => parse_json(&file.body)
+            .map_err(|_| String::from("The file must be a JSON array of quotes.")),
+        "ndjson" => parse_ndjson(&file.body),
+        #[cfg(feature = "csv")]
+        "csv" => from_csv(&file.body, &options),
+        #[cfg(not(feature = "csv"))]
+        "csv" => Err(String::from("CSV files need a build with the csv feature.")),
+        _ => Err(String::from("format must be csv, json or ndjson.")),
+    }
+}
+
+/// Turns each CSV record into a quote object keyed by the header row. Empty cells are left
+/// out, `episode` is read as a number and `characters` is split into its speakers; anything
+/// that is still wrong with a row is reported when the row is imported.
+#[cfg(feature = "csv")]
+fn from_csv(file: &[u8], options: &UploadOptions) -> Result<Vec<Value>, String> {
+    if !options.delimiter.is_ascii() {
+        return Err(String::from("delimiter must be a single ASCII character."));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter as u8)
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("The CSV header row is not valid: {}", e))?
+        .clone();
+
+    let mut items = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("CSV record {} is not valid: {}", index, e))?;
+        let mut item = Map::new();
+        for (column, cell) in headers.iter().zip(record.iter()) {
+            if cell.is_empty() {
+                continue;
+            }
+            let value = match column {
+                "episode" => cell
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .unwrap_or_else(|_| Value::from(cell)),
+                "characters" => Value::from(
+                    cell.split(options.characters_separator.as_str())
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => Value::from(cell),
+            };
+            item.insert(column.to_string(), value);
+        }
+        items.push(Value::Object(item));
+    }
+    Ok(items)
+}
+
+/// Starts importing `items` for `created_by`, or resumes the caller's earlier import with the
+/// same `idempotency_key`, and works on it until it is done or time runs short.
+pub async fn start(
+    client: &Client,
+    created_by: Option<&str>,
+    idempotency_key: Option<&str>,
+    items: Vec<Value>,
+) -> Result<Job, DbError> {
+    let progress = Progress {
+        rows_total: items.len() as i64,
+        bytes_total: items.iter().map(|item| item.to_string().len() as i64).sum(),
+        ..Progress::default()
+    };
+    let job = jobs::create(
+        client,
+        Kind::Import,
+        created_by,
+        idempotency_key,
+        Value::from(items),
+        serde_json::to_value(progress).unwrap_or_default(),
+    )
+    .await?;
+    if job.steps > 0 {
+        log::info!("resuming import {}", job.id);
+    }
+    let id = job.id;
+    Ok(jobs::work(client, id).await?.unwrap_or(job))
+}
+
+/// Inserts the next chunk of the import. Rows that cannot be inserted are recorded as
+/// failures instead of failing the chunk.
+pub async fn step(client: &Client, job: &Job) -> Result<Step, DbError> {
+    let _subsegment = xray::sql("import_chunk");
+    let mut progress: Progress = serde_json::from_value(job.progress.clone()).unwrap_or_default();
+    let chunk_size: i64 = config::var_or("IMPORT_CHUNK_SIZE", 100);
+
+    let row = client
+        .query_one(
+            "SELECT COALESCE(jsonb_agg(e ORDER BY i), '[]') FROM jobs AS j, jsonb_array_elements(j.input) WITH ORDINALITY AS t (e, i) WHERE j.id = $1 AND i > $2 AND i <= $2 + $3;",
+            &[&job.id, &progress.rows_processed, &chunk_size],
+        )
+        .await
+        .statement("import_chunk")?;
+    let chunk: Value = row.get(0);
+    let chunk = chunk.as_array().cloned().unwrap_or_default();
+
+    for (i, item) in chunk.iter().enumerate() {
+        let index = progress.rows_processed + i as i64;
+        progress.bytes_processed += item.to_string().len() as i64;
+
+        client
+            .batch_execute("SAVEPOINT import_row;")
+            .await
+            .statement("import_chunk")?;
+        match insert(client, item, job.created_by.clone()).await? {
+            Ok(rowid) => {
+                progress.last_key = rowid.or(progress.last_key);
+                client
+                    .batch_execute("RELEASE SAVEPOINT import_row;")
+                    .await
+                    .statement("import_chunk")?;
+            }
+            Err(error) => {
+                client
+                    .batch_execute(
+                        "ROLLBACK TO SAVEPOINT import_row; RELEASE SAVEPOINT import_row;",
+                    )
+                    .await
+                    .statement("import_chunk")?;
+                progress
+                    .failures
+                    .push(serde_json::json!({ "index": index, "error": error }));
+            }
+        }
+    }
+    progress.rows_processed += chunk.len() as i64;
+
+    let done = chunk.is_empty() || progress.rows_processed >= progress.rows_total;
+    let progress = serde_json::to_value(progress).unwrap_or_default();
+    Ok(if done {
+        Step::Done(progress)
+    } else {
+        Step::Continue(progress)
+    })
+}
+
+/// Inserts one row. The inner error describes a row that was rejected, and is recorded
+/// instead of failing the chunk.
+async fn insert(
+    client: &Client,
+    item: &Value,
+    created_by: Option<String>,
+) -> Result<Result<Option<i64>, String>, DbError> {
+    let mut quote: Quote = match serde_json::from_value(item.clone()) {
+        Ok(quote) => quote,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    if let Verdict::Reject(reason) = moderation::check(&quote) {
+        return Ok(Err(reason));
+    }
+    quote.created_by = created_by;
+    match quotes::insert_quote(client, quote).await {
+        Ok(quote) => Ok(Ok(quote.rowid)),
+        Err(e @ DbError::Constraint { .. }) => Ok(Err(e.to_string())),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,160 @@
+//! The transaction isolation level writes run under.
+//!
+//! CockroachDB runs transactions `SERIALIZABLE`, and only allows `READ COMMITTED` when the
+//! cluster setting `sql.txn.read_committed_isolation.enabled` is on. Under `READ COMMITTED`
+//! the server retries a statement that conflicts with a concurrent write instead of aborting
+//! the transaction, which keeps hot write paths from failing with serialization errors at
+//! the cost of reads in one transaction possibly seeing different snapshots.
+//!
+//! The level is chosen per route with `ISOLATION_LEVELS`, and admins can override it for
+//! one request with the `Isolation-Level` header. It is applied as the session default, so
+//! it covers implicit transactions as well as the ones handlers open.
+
+use std::sync::Mutex;
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use http::HeaderMap;
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::db::{DbError, StatementContext};
+use crate::{response, xray};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    Serializable,
+    ReadCommitted,
+}
+
+impl Isolation {
+    /// Accepts `serializable` and `read committed`, case-insensitively, with `_` or `-`
+    /// allowed in place of the space.
+    pub fn parse(level: &str) -> Option<Isolation> {
+        match level
+            .trim()
+            .to_ascii_lowercase()
+            .replace(['_', '-'], " ")
+            .as_str()
+        {
+            "serializable" => Some(Isolation::Serializable),
+            "read committed" => Some(Isolation::ReadCommitted),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Isolation::Serializable => "serializable",
+            Isolation::ReadCommitted => "read committed",
+        }
+    }
+}
+
+// The level of the request being handled; Lambda hands a process one request at a time.
+static CURRENT: Mutex<Isolation> = Mutex::new(Isolation::Serializable);
+// Whether the cached write connection may still default to `READ COMMITTED` from an
+// earlier request, so the next request has to set its level explicitly.
+static SESSION_CHANGED: Mutex<bool> = Mutex::new(false);
+// The cluster setting does not change often enough to check on every request.
+static READ_COMMITTED_ENABLED: Mutex<Option<bool>> = Mutex::new(None);
+
+/// The level transactions in this request run under.
+pub fn current() -> Isolation {
+    *CURRENT.lock().unwrap()
+}
+
+/// The level `ISOLATION_LEVELS` gives route `pattern`, as in
+/// `/quotes:transact=read_committed,/quotes/batch=read_committed`.
+pub fn for_route(pattern: &str) -> Option<Isolation> {
+    let levels = std::env::var("ISOLATION_LEVELS").ok()?;
+    levels
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .find(|(route, _)| route.trim() == pattern)
+        .and_then(|(_, level)| Isolation::parse(level))
+}
+
+/// The level for this request: the `Isolation-Level` header when an admin sends it,
+/// otherwise the route's configured level, otherwise `SERIALIZABLE`.
+pub fn requested(
+    pattern: Option<&str>,
+    headers: &HeaderMap,
+    principal: &Principal,
+) -> Result<Isolation, ApiGatewayProxyResponse> {
+    let header = match headers.get("isolation-level") {
+        Some(header) => header,
+        None => {
+            return Ok(pattern
+                .and_then(for_route)
+                .unwrap_or(Isolation::Serializable))
+        }
+    };
+    if !principal.is_admin() {
+        return Err(response::problem(
+            403,
+            "Forbidden",
+            "Only admin callers may choose the isolation level.",
+        ));
+    }
+    header
+        .to_str()
+        .ok()
+        .and_then(Isolation::parse)
+        .ok_or_else(|| {
+            response::problem(
+                400,
+                "Bad Request",
+                "Isolation-Level must be serializable or read committed.",
+            )
+        })
+}
+
+/// Makes `level` the session default on `client` for this request and returns the level
+/// in effect, which is `SERIALIZABLE` when the cluster does not allow `READ COMMITTED`.
+pub async fn apply(client: &Client, level: Isolation) -> Result<Isolation, DbError> {
+    let level = match level {
+        Isolation::ReadCommitted if !read_committed_enabled(client).await => {
+            log::warn!("READ COMMITTED is not enabled on the cluster, using SERIALIZABLE");
+            Isolation::Serializable
+        }
+        level => level,
+    };
+    *CURRENT.lock().unwrap() = level;
+
+    let changed = *SESSION_CHANGED.lock().unwrap();
+    if level == Isolation::Serializable && !changed {
+        return Ok(level);
+    }
+    let _subsegment = xray::sql("set_isolation");
+    client
+        .batch_execute(&format!(
+            "SET default_transaction_isolation = '{}';",
+            level.as_str()
+        ))
+        .await
+        .statement("set_isolation")?;
+    *SESSION_CHANGED.lock().unwrap() = level != Isolation::Serializable;
+    Ok(level)
+}
+
+async fn read_committed_enabled(client: &Client) -> bool {
+    if let Some(enabled) = *READ_COMMITTED_ENABLED.lock().unwrap() {
+        return enabled;
+    }
+    let _subsegment = xray::sql("read_committed_enabled");
+    let enabled = match client
+        .query_one(
+            "SHOW CLUSTER SETTING sql.txn.read_committed_isolation.enabled;",
+            &[],
+        )
+        .await
+    {
+        Ok(row) => row.try_get::<_, bool>(0).unwrap_or(false),
+        Err(e) => {
+            log::warn!("could not read the READ COMMITTED cluster setting: {}", e);
+            false
+        }
+    };
+    *READ_COMMITTED_ENABLED.lock().unwrap() = Some(enabled);
+    enabled
+}
@@ -0,0 +1,303 @@
+//! Long-running work split into steps across invocations.
+//!
+//! A job is a row in `jobs`. Each step does a bounded amount of work and saves the job's
+//! `progress` in the same transaction, so a job cut short by the Lambda timeout loses at
+//! most one step. The request that starts a job works on it until the invocation's budget
+//! is spent. With `JOBS_QUEUE_URL` set, the job id is then queued for the `quotes-jobs`
+//! Lambda, which carries on in later invocations; without it, the job advances when the
+//! request that started it is retried with the same `Idempotency-Key`.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use tokio_postgres::{Client, Row};
+use uuid::Uuid;
+
+use crate::db::{DbError, StatementContext};
+use crate::{admin, cache, config, deadline, imports, links, purge, queue, response, xray};
+
+const JOB_COLUMNS: &str =
+    "id, kind, status, created_by, progress, error, steps, created_at, updated_at";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Import,
+    Backup,
+    Purge,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Import => "import",
+            Kind::Backup => "backup",
+            Kind::Purge => "purge",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Kind> {
+        match kind {
+            "import" => Some(Kind::Import),
+            "backup" => Some(Kind::Backup),
+            "purge" => Some(Kind::Purge),
+            _ => None,
+        }
+    }
+
+    /// Whether steps of this kind write quotes.
+    fn changes_quotes(self) -> bool {
+        matches!(self, Kind::Import | Kind::Purge)
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Job {
+    pub id: Uuid,
+    /// `import`, `backup` or `purge`.
+    pub kind: String,
+    /// `queued`, `running`, `waiting` on something outside the service, `done` or `failed`.
+    pub status: String,
+    #[serde(skip)]
+    pub created_by: Option<String>,
+    /// How far the job has got, in a shape that depends on its kind.
+    pub progress: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub steps: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        self.status == "done" || self.status == "failed"
+    }
+}
+
+/// What a step leaves behind, with the job's new progress.
+pub enum Step {
+    /// More work is ready; the next step can follow at once.
+    Continue(Value),
+    /// The job waits on something outside the service, such as a CockroachDB job, and is
+    /// looked at again after `JOBS_WAIT_SECS`.
+    Wait(Value),
+    Done(Value),
+    Failed(Value, String),
+}
+
+fn job_from_row(row: &Row, statement: &'static str) -> Result<Job, DbError> {
+    let mapping = |e: tokio_postgres::Error| DbError::mapping(statement, e);
+    Ok(Job {
+        id: row.try_get("id").map_err(mapping)?,
+        kind: row.try_get("kind").map_err(mapping)?,
+        status: row.try_get("status").map_err(mapping)?,
+        created_by: row.try_get("created_by").map_err(mapping)?,
+        progress: row.try_get("progress").map_err(mapping)?,
+        error: row.try_get("error").map_err(mapping)?,
+        steps: row.try_get("steps").map_err(mapping)?,
+        created_at: row.try_get("created_at").map_err(mapping)?,
+        updated_at: row.try_get("updated_at").map_err(mapping)?,
+    })
+}
+
+pub async fn get(client: &Client, id: Uuid) -> Result<Option<Job>, DbError> {
+    let _subsegment = xray::sql("get_job");
+    let row = client
+        .query_opt(
+            format!("SELECT {} FROM jobs WHERE id = $1;", JOB_COLUMNS).as_str(),
+            &[&id],
+        )
+        .await
+        .statement("get_job")?;
+    row.map(|row| job_from_row(&row, "get_job")).transpose()
+}
+
+/// Records a new job, or returns the caller's earlier job of the same kind with the same
+/// `idempotency_key`.
+pub async fn create(
+    client: &Client,
+    kind: Kind,
+    created_by: Option<&str>,
+    idempotency_key: Option<&str>,
+    input: Value,
+    progress: Value,
+) -> Result<Job, DbError> {
+    let _subsegment = xray::sql("create_job");
+    if let Some(key) = idempotency_key {
+        let row = client
+            .query_opt(
+                format!(
+                    "SELECT {} FROM jobs WHERE kind = $1 AND created_by IS NOT DISTINCT FROM $2 AND idempotency_key = $3;",
+                    JOB_COLUMNS
+                )
+                .as_str(),
+                &[&kind.as_str(), &created_by, &key],
+            )
+            .await
+            .statement("create_job")?;
+        if let Some(row) = row {
+            return job_from_row(&row, "create_job");
+        }
+    }
+
+    let row = client
+        .query_one(
+            format!(
+                "INSERT INTO jobs (kind, created_by, idempotency_key, input, progress) VALUES ($1, $2, $3, $4, $5) RETURNING {};",
+                JOB_COLUMNS
+            )
+            .as_str(),
+            &[&kind.as_str(), &created_by, &idempotency_key, &input, &progress],
+        )
+        .await
+        .statement("create_job")?;
+    job_from_row(&row, "create_job")
+}
+
+/// Runs steps of job `id` until it finishes, has to wait, or the invocation's budget is
+/// spent, handing it to the queue in the last two cases. `None` when the job does not exist.
+pub async fn work(client: &Client, id: Uuid) -> Result<Option<Job>, DbError> {
+    loop {
+        if deadline::budget_spent() {
+            let job = get(client, id).await?;
+            if let Some(job) = job.as_ref().filter(|job| !job.is_finished()) {
+                hand_off(job, 0).await;
+            }
+            return Ok(job);
+        }
+
+        client.batch_execute("BEGIN;").await.statement("job_step")?;
+        let stepped = match step(client, id).await {
+            Ok(stepped) => {
+                client
+                    .batch_execute("COMMIT;")
+                    .await
+                    .statement("job_step")?;
+                // Each step commits its share of the changes, so cached reads go stale here
+                // whichever function runs the job.
+                if let Some((job, _)) = &stepped {
+                    if Kind::parse(&job.kind).map_or(false, Kind::changes_quotes) {
+                        cache::invalidate().await;
+                    }
+                }
+                stepped
+            }
+            Err(e) => {
+                if let Err(rollback_error) = client.batch_execute("ROLLBACK;").await {
+                    log::warn!("failed to roll back job step: {}", rollback_error);
+                }
+                match e {
+                    DbError::Connect { .. }
+                    | DbError::Timeout { .. }
+                    | DbError::Serialization { .. } => return Err(e),
+                    e => return fail(client, id, &e.to_string()).await,
+                }
+            }
+        };
+
+        match stepped {
+            None => return Ok(None),
+            Some((job, _)) if job.is_finished() => return Ok(Some(job)),
+            Some((job, true)) => {
+                hand_off(&job, config::var_or("JOBS_WAIT_SECS", 30)).await;
+                return Ok(Some(job));
+            }
+            Some((_, false)) => continue,
+        }
+    }
+}
+
+/// Runs one step with the job row locked, so two workers never step the same job at once.
+/// The flag says whether the job now waits.
+async fn step(client: &Client, id: Uuid) -> Result<Option<(Job, bool)>, DbError> {
+    let _subsegment = xray::sql("job_step");
+    let row = client
+        .query_opt(
+            format!("SELECT {} FROM jobs WHERE id = $1 FOR UPDATE;", JOB_COLUMNS).as_str(),
+            &[&id],
+        )
+        .await
+        .statement("job_step")?;
+    let job = match row {
+        Some(row) => job_from_row(&row, "job_step")?,
+        None => return Ok(None),
+    };
+    if job.is_finished() {
+        return Ok(Some((job, false)));
+    }
+
+    let step = match Kind::parse(&job.kind) {
+        Some(Kind::Import) => imports::step(client, &job).await?,
+        Some(Kind::Backup) => admin::backup_step(client, &job).await?,
+        Some(Kind::Purge) => purge::step(client, &job).await?,
+        None => Step::Failed(
+            job.progress.clone(),
+            format!("unknown job kind {}", job.kind),
+        ),
+    };
+    let (status, progress, error) = match step {
+        Step::Continue(progress) => ("running", progress, None),
+        Step::Wait(progress) => ("waiting", progress, None),
+        Step::Done(progress) => ("done", progress, None),
+        Step::Failed(progress, error) => ("failed", progress, Some(error)),
+    };
+    // The input is only needed until the job finishes.
+    let row = client
+        .query_one(
+            format!(
+                "UPDATE jobs SET status = $2, progress = $3, error = $4, steps = steps + 1, input = IF($2 IN ('done', 'failed'), '{{}}', input), updated_at = now() WHERE id = $1 RETURNING {};",
+                JOB_COLUMNS
+            )
+            .as_str(),
+            &[&id, &status, &progress, &error],
+        )
+        .await
+        .statement("job_step")?;
+    let job = job_from_row(&row, "job_step")?;
+    Ok(Some((job, status == "waiting")))
+}
+
+/// Marks a job failed after a step hit an error that retrying will not fix.
+async fn fail(client: &Client, id: Uuid, error: &str) -> Result<Option<Job>, DbError> {
+    log::error!("job {} failed: {}", id, error);
+    let row = client
+        .query_opt(
+            format!(
+                "UPDATE jobs SET status = 'failed', error = $2, input = '{{}}', updated_at = now() WHERE id = $1 RETURNING {};",
+                JOB_COLUMNS
+            )
+            .as_str(),
+            &[&id, &error],
+        )
+        .await
+        .statement("fail_job")?;
+    row.map(|row| job_from_row(&row, "fail_job")).transpose()
+}
+
+/// Queues the job for the `quotes-jobs` Lambda to continue after `delay_secs`.
+async fn hand_off(job: &Job, delay_secs: i32) {
+    let queue_url = match std::env::var("JOBS_QUEUE_URL") {
+        Ok(queue_url) => queue_url,
+        Err(_) => {
+            log::info!("job {} continues when its request is retried", job.id);
+            return;
+        }
+    };
+    match queue::enqueue_job(&queue_url, job.id, delay_secs).await {
+        Ok(()) => log::info!("queued job {} to continue in {}s", job.id, delay_secs),
+        Err(e) => log::error!("failed to queue job {}: {}", job.id, e),
+    }
+}
+
+/// `200` with a finished job, otherwise `202`, with a `Location` of its status URL.
+pub fn response(event: &ApiGatewayProxyRequest, job: &Job) -> ApiGatewayProxyResponse {
+    let status = if job.is_finished() { 200 } else { 202 };
+    let mut resp = response::json(status, serde_json::json!({ "data": job }).to_string());
+    let location = format!("{}/jobs/{}", links::base_url(event), job.id);
+    if let Ok(location) = http::HeaderValue::from_str(&location) {
+        resp.headers.insert(http::header::LOCATION, location);
+    }
+    resp
+}
@@ -0,0 +1,40 @@
+//! Language detection for quote text.
+
+use whatlang::Lang;
+
+// whatlang reports ISO 639-3 codes; the languages most quotes are likely to be in get their
+// shorter ISO 639-1 code so that `?lang=en` works as people expect.
+const ISO_639_1: &[(Lang, &str)] = &[
+    (Lang::Eng, "en"),
+    (Lang::Deu, "de"),
+    (Lang::Fra, "fr"),
+    (Lang::Spa, "es"),
+    (Lang::Ita, "it"),
+    (Lang::Por, "pt"),
+    (Lang::Nld, "nl"),
+    (Lang::Swe, "sv"),
+    (Lang::Dan, "da"),
+    (Lang::Fin, "fi"),
+    (Lang::Pol, "pl"),
+    (Lang::Ces, "cs"),
+    (Lang::Tur, "tr"),
+    (Lang::Rus, "ru"),
+    (Lang::Ukr, "uk"),
+    (Lang::Ara, "ar"),
+    (Lang::Hin, "hi"),
+    (Lang::Jpn, "ja"),
+    (Lang::Kor, "ko"),
+    (Lang::Cmn, "zh"),
+];
+
+/// The language of `text`, or `None` when the detection is not reliable, as short quotes
+/// often are not.
+pub fn detect(text: &str) -> Option<String> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    let code = ISO_639_1
+        .iter()
+        .find(|(lang, _)| *lang == info.lang())
+        .map(|(_, code)| *code)
+        .unwrap_or_else(|| info.lang().code());
+    Some(code.to_string())
+}
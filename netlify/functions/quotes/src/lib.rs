@@ -0,0 +1,58 @@
+//! Shared code for the quotes API function and the Lambdas deployed alongside it.
+
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod breaker;
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod db;
+pub mod deadline;
+pub mod deprecation;
+pub mod event;
+pub mod feed;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod guard;
+pub mod highlight;
+pub mod imports;
+pub mod isolation;
+pub mod jobs;
+pub mod lang;
+pub mod links;
+pub mod logging;
+pub mod metrics;
+pub mod moderation;
+pub mod multipart;
+pub mod outbox;
+pub mod pipeline;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod purge;
+pub mod queue;
+pub mod quota;
+pub mod quotes;
+pub mod redact;
+pub mod request_id;
+pub mod response;
+pub mod router;
+pub mod sanitize;
+pub mod schema;
+pub mod serializer;
+pub mod share;
+pub mod share_link;
+pub mod sitemap;
+pub mod slack;
+pub mod slug;
+pub mod snapshot;
+pub mod spam;
+pub mod stardate;
+pub mod timing;
+pub mod transact;
+pub mod types;
+pub mod usage;
+pub mod validation;
+pub mod warmup;
+pub mod xray;
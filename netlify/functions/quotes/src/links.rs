@@ -0,0 +1,147 @@
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::router;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub self_link: String,
+    pub collection: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<String>,
+}
+
+/// The absolute URL the request was made against, without its query string.
+///
+/// Uses the request context path, which keeps the API Gateway stage prefix, under the
+/// domain API Gateway received the request on, or `PUBLIC_BASE_URL` for events without one.
+/// The `Host` header is never used, because the client controls it.
+pub fn request_url(event: &ApiGatewayProxyRequest) -> String {
+    let path = event
+        .request_context
+        .path
+        .as_deref()
+        .or(event.path.as_deref())
+        .unwrap_or("/");
+    format!("{}{}", origin(event).unwrap_or_default(), path)
+}
+
+/// The scheme and host that absolute URLs are built under, such as `https://example.com`.
+pub fn origin(event: &ApiGatewayProxyRequest) -> Option<String> {
+    match event.request_context.domain_name.as_deref() {
+        Some(domain) if !domain.is_empty() => Some(format!("https://{}", domain)),
+        _ => std::env::var("PUBLIC_BASE_URL")
+            .ok()
+            .map(|url| url.trim_end_matches('/').to_string()),
+    }
+}
+
+/// The URL routes are resolved under, such as `https://example.com/api`.
+pub fn base_url(event: &ApiGatewayProxyRequest) -> String {
+    let url = request_url(event);
+    let url = url.trim_end_matches('/');
+    match router::route_path(event.path.as_deref().unwrap_or("/")) {
+        "/" => url.to_string(),
+        route => url.strip_suffix(route).unwrap_or(url).to_string(),
+    }
+}
+
+/// The canonical URL of the quotes collection, whichever route the request was made to.
+pub fn collection_url(event: &ApiGatewayProxyRequest) -> String {
+    format!("{}/quotes", base_url(event))
+}
+
+/// Links for a response that is not part of a paginated list.
+pub fn for_request(event: &ApiGatewayProxyRequest) -> Links {
+    Links {
+        self_link: request_url(event),
+        collection: collection_url(event),
+        next: None,
+        prev: None,
+        first: None,
+        last: None,
+    }
+}
+
+/// Links for a single quote, addressed by its public id.
+pub fn for_quote(event: &ApiGatewayProxyRequest, id: Option<String>) -> Links {
+    let collection = collection_url(event);
+    Links {
+        self_link: match id {
+            Some(id) => format!("{}/{}", collection, id),
+            None => collection.clone(),
+        },
+        collection,
+        next: None,
+        prev: None,
+        first: None,
+        last: None,
+    }
+}
+
+/// Links for one page of a list, keeping the request's other query parameters.
+///
+/// `last` is only linked when the caller knows the number of the last page. `as_of` is the
+/// timestamp every page of the list is read at, if any.
+pub fn for_page(
+    event: &ApiGatewayProxyRequest,
+    page: i64,
+    has_next: bool,
+    last_page: Option<i64>,
+    as_of: Option<&str>,
+) -> Links {
+    let url = request_url(event);
+    let page_link = |page: i64| {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.extend_pairs(
+            event
+                .query_string_parameters
+                .iter()
+                .filter(|(key, _)| *key != "page" && *key != "as_of"),
+        );
+        if let Some(as_of) = as_of {
+            query.append_pair("as_of", as_of);
+        }
+        query.append_pair("page", &page.to_string());
+        format!("{}?{}", url, query.finish())
+    };
+    Links {
+        self_link: page_link(page),
+        next: has_next.then(|| page_link(page + 1)),
+        prev: (page > 1).then(|| page_link(page - 1)),
+        first: Some(page_link(1)),
+        last: last_page.map(page_link),
+        collection: collection_url(event),
+    }
+}
+
+/// The number of the last page of a list of `total` items; an empty list still has page 1.
+pub fn last_page(total: i64, page_size: i64) -> i64 {
+    ((total + page_size - 1) / page_size).max(1)
+}
+
+/// An RFC 8288 `Link` header value for the pagination links that are present.
+pub fn header(links: &Links) -> Option<String> {
+    let rels = [
+        ("first", &links.first),
+        ("prev", &links.prev),
+        ("next", &links.next),
+        ("last", &links.last),
+    ];
+    let values: Vec<String> = rels
+        .iter()
+        .filter_map(|(rel, url)| {
+            url.as_ref()
+                .map(|url| format!("<{}>; rel=\"{}\"", url, rel))
+        })
+        .collect();
+    (!values.is_empty()).then(|| values.join(", "))
+}
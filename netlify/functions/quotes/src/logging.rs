@@ -0,0 +1,56 @@
+//! The process logger. It is installed at startup but only built on its first record, so
+//! invocations that log nothing never pay for it during the Lambda init phase. Records
+//! logged during a request start with its id in brackets.
+
+use std::sync::Mutex;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use simple_logger::SimpleLogger;
+
+use crate::request_id;
+
+static LOGGER: Deferred = Deferred {
+    inner: Mutex::new(None),
+};
+
+struct Deferred {
+    inner: Mutex<Option<SimpleLogger>>,
+}
+
+impl Log for Deferred {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let logger = inner.get_or_insert_with(|| SimpleLogger::new().with_level(log::max_level()));
+        match request_id::current() {
+            Some(id) => logger.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => logger.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = self.inner.lock().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Installs the logger for records up to `level`. Panics when called twice.
+pub fn init(level: LevelFilter) {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(level);
+}
@@ -1,288 +1,1210 @@
-use aws_lambda_events::{
-    encodings::Body,
-    event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse},
-};
-use http::header::HeaderMap;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use futures::FutureExt;
 use lambda_runtime::{service_fn, Error, LambdaEvent};
 use log::LevelFilter;
-use openssl::ssl::{SslConnector, SslMethod};
-use postgres_openssl::MakeTlsConnector;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, DisplayFromStr};
-use simple_logger::SimpleLogger;
-use tokio_postgres::types::Type;
 use tokio_postgres::Client;
 
-#[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
-struct Quote {
-    #[serde_as(as = "Option<DisplayFromStr>")]
-    rowid: Option<i64>,
-    quote: Option<String>,
-    characters: Option<String>,
-    stardate: Option<Decimal>,
-    episode: Option<i64>,
-}
+use quotes_api::db::{DbError, StatementContext};
+#[cfg(feature = "graphql")]
+use quotes_api::graphql;
+use quotes_api::quotes::{
+    self, delete_quote, get_quote, get_quotes, insert_quote, search_quotes, update_quote,
+    NaturalKey, Quote, RelatedLimits,
+};
+use quotes_api::router::{self, Endpoint, Params};
+use quotes_api::serializer::{self, Format, Meta};
+use quotes_api::{
+    admin, audit, auth, batch, breaker, cache, config, db, deadline, deprecation, event, feed,
+    guard, highlight, imports, isolation, jobs, links, logging, metrics, pipeline, purge, queue,
+    quota, redact, request_id, response, schema, share, share_link, sitemap, slack, snapshot, spam,
+    timing, transact, usage, validation, warmup, xray,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    SimpleLogger::new()
-        .with_level(LevelFilter::Info)
-        .init()
-        .unwrap();
+    logging::init(LevelFilter::Info);
+
+    warmup::eager_init().await;
+    if config::var_or("SELFCHECK_ON_START", false) {
+        let client = db::get_db_client().await?;
+        let report = schema::check(&client).await?;
+        if !report.ok {
+            log::error!("schema self-check failed: {}", report.problems.join("; "));
+            return Err("the database schema does not match this build".into());
+        }
+    }
 
     let processor = service_fn(handler);
     lambda_runtime::run(processor).await?;
     Ok(())
 }
 
-async fn handler(
+async fn handler(event: LambdaEvent<serde_json::Value>) -> Result<ApiGatewayProxyResponse, Error> {
+    let (payload, context) = event.into_parts();
+    if warmup::is_warmup(&payload) {
+        return Ok(warmup::ping().await);
+    }
+    let event: LambdaEvent<ApiGatewayProxyRequest> =
+        LambdaEvent::new(event::request(payload)?, context);
+
+    timing::start();
+    xray::begin(event.context.xray_trace_id.as_deref());
+    let request_id = request_id::begin(&event.payload.headers, &event.context.request_id);
+    let method = event.payload.http_method.to_string();
+    let path = event.payload.path.clone().unwrap_or_default();
+
+    let resp = AssertUnwindSafe(handle(event))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|panic| Ok(panicked(&request_id, panic)))
+        .or_else(|e| match e.downcast_ref::<DbError>() {
+            Some(db_error) => {
+                log::error!("request {} failed: {}", request_id, db_error);
+                Ok(response::db_error(db_error))
+            }
+            None => Err(redact::redact(&e.to_string()).into()),
+        });
+
+    let resp = resp.map(|mut resp| {
+        if let Ok(id) = http::HeaderValue::from_str(&request_id) {
+            resp.headers.insert("x-request-id", id);
+        }
+        resp
+    });
+    let status = match &resp {
+        Ok(resp) => resp.status_code,
+        Err(_) => 500,
+    };
+    timing::finish(&request_id, &method, &path, status);
+    audit::finish(&request_id, &method, &path, status);
+    if usage::finish() {
+        match db::get_db_client().await {
+            Ok(client) => {
+                if let Err(e) = usage::flush(&client).await {
+                    log::warn!("usage counts not written yet: {}", e);
+                }
+            }
+            Err(e) => log::warn!("usage counts not written yet: {}", e),
+        }
+    }
+    xray::end();
+    request_id::end();
+    resp
+}
+
+/// Turns a panic in a handler into a 500 carrying the request id, instead of failing the invocation.
+fn panicked(request_id: &str, panic: Box<dyn Any + Send>) -> ApiGatewayProxyResponse {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    log::error!(
+        "request {} panicked: {}",
+        request_id,
+        redact::redact(&message)
+    );
+
+    // The panic may have left a transaction open on a cached connection.
+    db::discard_clients();
+
+    response::problem_with(
+        500,
+        "Internal Server Error",
+        "The request failed unexpectedly.",
+        serde_json::json!({ "request_id": request_id }),
+    )
+}
+
+async fn handle(
     event: LambdaEvent<ApiGatewayProxyRequest>,
 ) -> Result<ApiGatewayProxyResponse, Error> {
-    let (event, _context) = event.into_parts();
-    let method = event.http_method;
+    let (event, context) = event.into_parts();
+    let method = event.http_method.clone();
+
+    let pipeline::Prepared {
+        endpoint,
+        params,
+        principal,
+        event,
+        deprecation,
+    } = match pipeline::prepare(event) {
+        Ok(prepared) => prepared,
+        Err(resp) => return Ok(resp),
+    };
+
+    if endpoint == Endpoint::AdminPool {
+        return admin::pool();
+    }
+    if endpoint == Endpoint::AdminTypes {
+        return admin::types();
+    }
+
+    // Before the cache, so cached reads count against quotas too.
+    let rate_limit = match (&principal.caller, quota::enabled()) {
+        (Some(caller), true) => {
+            let client = db::get_db_client().await?;
+            let writes = !matches!(method, http::Method::GET | http::Method::HEAD);
+            match quota::check(&client, caller, writes).await? {
+                quota::Verdict::Exceeded(quota) => return Ok(quota::exceeded(&quota)),
+                quota::Verdict::Within(quota) => Some(quota),
+                quota::Verdict::Unlimited => None,
+            }
+        }
+        _ => None,
+    };
+
+    let cache_key = cache::key(endpoint, &method, &event);
+    let mut generation = None;
+    if let Some(key) = &cache_key {
+        if let Some(lookup) = cache::lookup(key).await {
+            if let Some(entry) = lookup.entry {
+                return Ok(with_rate_limit(cache::response(entry), &rate_limit));
+            }
+            generation = Some(lookup.generation);
+        }
+    }
+
+    if let Err(retry_after) = breaker::check() {
+        return Ok(response::service_unavailable(retry_after));
+    }
+    let client = if method == http::Method::GET {
+        read_client(&principal).await
+    } else {
+        db::get_db_client().await
+    };
+    let client = match client {
+        Ok(client) => {
+            breaker::record_success();
+            client
+        }
+        Err(e) => {
+            breaker::record_failure();
+            return Err(e.into());
+        }
+    };
+    timing::mark("acquire");
+
+    if !matches!(method, http::Method::GET | http::Method::HEAD)
+        && !guard::writes_allowed(&client).await?
+    {
+        return Ok(response::problem(
+            503,
+            "Service Unavailable",
+            "Writes are disabled because the database is not the cluster this deployment expects.",
+        ));
+    }
+
+    if endpoint == Endpoint::Quotes
+        && method == http::Method::POST
+        && event.query_string_parameters.first("async") == Some("true")
+    {
+        let resp =
+            enqueue_handler(&event, &client, &principal, is_dry_run(&method, &event)).await?;
+        return Ok(with_rate_limit(resp, &rate_limit));
+    }
+
+    let requested = match method {
+        http::Method::GET | http::Method::HEAD => isolation::Isolation::Serializable,
+        _ => {
+            let pattern = router::pattern(event.path.as_deref().unwrap_or("/"));
+            match isolation::requested(pattern, &event.headers, &principal) {
+                Ok(level) => level,
+                Err(resp) => return Ok(resp),
+            }
+        }
+    };
+    let level = isolation::apply(&client, requested).await?;
+
+    let dry_run = is_dry_run(&method, &event);
+    if dry_run {
+        client.batch_execute("BEGIN;").await.statement("dry_run")?;
+    }
+    let session = client.clone();
+
+    // Follows the read retry onto its fresh connection, so the deadline cancels the query
+    // that is actually running.
+    let cancel_token = Mutex::new(client.cancel_token());
+    let work = async {
+        let resp = dispatch(
+            endpoint,
+            method.clone(),
+            event.clone(),
+            &params,
+            client.clone(),
+            principal.clone(),
+        )
+        .await;
+        match resp {
+            // Reads are safe to repeat, so a connection that died mid-query gets one retry
+            // on a fresh connection before the error reaches the client.
+            Err(e) if method == http::Method::GET && is_connection_lost(&e) => {
+                log::warn!("retrying read on a fresh connection: {}", e);
+                db::discard_clients();
+                let client = read_client(&principal).await?;
+                *cancel_token.lock().unwrap() = client.cancel_token();
+                let total = metrics::RECOVERED_READS.incr();
+                metrics::emit("RecoveredReads", 1.0, "Count");
+                log::info!("retried a read on a fresh connection ({} retries)", total);
+                dispatch(endpoint, method, event, &params, client, principal).await
+            }
+            resp => resp,
+        }
+    };
+
+    let deadline = deadline::from_context(&context);
+    deadline::start_budget(deadline);
+    let resp = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, work).await {
+            Ok(resp) => resp,
+            Err(_) => {
+                log::warn!(
+                    "request {} aborted at its deadline, cancelling its query",
+                    context.request_id
+                );
+                timing::abort();
+                let token = cancel_token.lock().unwrap().clone();
+                deadline::cancel(token).await;
+                Ok(response::text(504, "Gateway Timeout"))
+            }
+        },
+        None => work.await,
+    };
+    timing::mark("query");
+
+    // Reports the level actually used, which falls back to SERIALIZABLE when the cluster
+    // does not allow READ COMMITTED.
+    let resp = resp.map(|mut resp| {
+        if requested != isolation::Isolation::Serializable {
+            resp.headers.insert(
+                "isolation-level",
+                http::HeaderValue::from_static(level.as_str()),
+            );
+        }
+        match &deprecation {
+            Some((deprecation, successor)) => {
+                deprecation::apply(resp, deprecation, successor.as_deref())
+            }
+            None => resp,
+        }
+    });
+
+    if dry_run {
+        session
+            .batch_execute("ROLLBACK;")
+            .await
+            .statement("dry_run")?;
+        usage::rolled_back();
+        return resp.map(|mut resp| {
+            resp.headers
+                .insert("dry-run", http::HeaderValue::from_static("true"));
+            with_rate_limit(resp, &rate_limit)
+        });
+    }
+    // Any successful write may change what the cached reads return.
+    if let Ok(resp) = &resp {
+        match (&cache_key, generation) {
+            // A page cut short by the deadline is only right for this request.
+            (Some(key), Some(generation)) if !deadline::budget_spent() => {
+                cache::put(key, generation, resp).await
+            }
+            _ if !matches!(method, http::Method::GET | http::Method::HEAD)
+                && resp.status_code < 400 =>
+            {
+                cache::invalidate().await
+            }
+            _ => {}
+        }
+    }
+    // Per caller, so added after the response is cached.
+    resp.map(|resp| with_rate_limit(resp, &rate_limit))
+}
+
+fn with_rate_limit(
+    resp: ApiGatewayProxyResponse,
+    rate_limit: &Option<quota::Quota>,
+) -> ApiGatewayProxyResponse {
+    match rate_limit {
+        Some(rate_limit) => quota::with_headers(resp, rate_limit),
+        None => resp,
+    }
+}
+
+async fn dispatch(
+    endpoint: Endpoint,
+    method: http::Method,
+    event: ApiGatewayProxyRequest,
+    params: &Params,
+    client: Arc<Client>,
+    principal: auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    match endpoint {
+        Endpoint::Quotes => quotes_handler(method, event, params, &client, &principal).await,
+        Endpoint::Batch => batch_handler(&method, &event, &client, &principal).await,
+        Endpoint::Import => import_handler(&method, &event, &client, &principal).await,
+        Endpoint::JobStatus => job_status_handler(params, &client, &principal).await,
+        Endpoint::Transact => transact_handler(&method, &event, &client, &principal).await,
+        Endpoint::RelatedQuotes => related_handler(&event, params, &client).await,
+        Endpoint::Share => share_handler(&event, params, &client).await,
+        Endpoint::ShareLink => share_link_handler(&event, params, &client, &principal).await,
+        Endpoint::Feed => feed::handle(&event, &client).await,
+        Endpoint::Sitemap => sitemap::handle(&event, &client).await,
+        Endpoint::CharacterNames => character_names_handler(&event, &client).await,
+        Endpoint::MyQuotes => my_quotes_handler(&event, &client, &principal).await,
+        Endpoint::MyUsage => usage::mine(&event, &client, &principal).await,
+        Endpoint::Timeline => timeline_handler(&event, &client).await,
+        Endpoint::Lookup => lookup_handler(&event, &client).await,
+        Endpoint::SlackQuote => slack::handle(&event, &client).await,
+        #[cfg(feature = "graphql")]
+        Endpoint::GraphQL => graphql::handle(&event, client.clone(), principal).await,
+        Endpoint::AdminExplain => admin::explain(&event, &client).await,
+        Endpoint::AdminSchema => admin::schema(&client).await,
+        Endpoint::AdminPool => admin::pool(),
+        Endpoint::AdminTypes => admin::types(),
+        Endpoint::AdminUsage => usage::rollup(&event, &client).await,
+        Endpoint::AdminRepair => admin::repair(&event, &client).await,
+        Endpoint::AdminBackup if is_dry_run(&method, &event) => Ok(response::problem(
+            400,
+            "Bad Request",
+            "Backups run as jobs and cannot be dry runs.",
+        )),
+        Endpoint::AdminBackup => admin::backup(&event, &client, &principal).await,
+        Endpoint::AdminCluster => admin::cluster(&client).await,
+        Endpoint::AdminSelfcheck => admin::selfcheck(&client).await,
+        Endpoint::AdminIndexes => admin::indexes(&event, &client).await,
+        Endpoint::AdminPurge if is_dry_run(&method, &event) => {
+            purge::preview(&event, &client).await
+        }
+        Endpoint::AdminPurge => purge::start(&event, &client, &principal).await,
+        Endpoint::AdminBackupStatus => admin::backup_status(params, &client).await,
+        Endpoint::AdminSnapshot if method == http::Method::GET => snapshot::export(&client).await,
+        Endpoint::AdminSnapshot => {
+            snapshot::import(&event, &client, is_dry_run(&method, &event)).await
+        }
+    }
+}
 
-    let client = get_db_client().await?;
+/// The connection profile for a read: the read-only public role for unauthenticated callers.
+async fn read_client(principal: &auth::Principal) -> Result<Arc<Client>, DbError> {
+    match principal.authenticated {
+        true => db::get_read_client().await,
+        false => db::get_public_client().await,
+    }
+}
+
+/// Whether `error` comes from a connection that was lost, rather than from the statement.
+fn is_connection_lost(error: &Error) -> bool {
+    matches!(
+        error.downcast_ref::<DbError>(),
+        Some(DbError::Connect { .. })
+    )
+}
+
+/// A page of the quote list, either as quotes or already written out as JSON.
+enum Listing {
+    Quotes(quotes::Page),
+    Json(quotes::JsonPage),
+}
+
+impl Listing {
+    fn len(&self) -> usize {
+        match self {
+            Listing::Quotes(list) => list.quotes.len(),
+            Listing::Json(list) => list.len,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn truncated(&self) -> bool {
+        match self {
+            Listing::Quotes(list) => list.truncated,
+            Listing::Json(list) => list.truncated,
+        }
+    }
+}
+
+/// `?dry_run=true` on a mutating request runs it in a transaction that is always rolled back.
+fn is_dry_run(method: &http::Method, event: &ApiGatewayProxyRequest) -> bool {
+    method != http::Method::GET && event.query_string_parameters.first("dry_run") == Some("true")
+}
+
+async fn quotes_handler(
+    method: http::Method,
+    event: ApiGatewayProxyRequest,
+    params: &Params,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let format = Format::negotiate(&event.headers);
+
+    // `/quotes/{id}` is the canonical form; `?rowid=` is still accepted.
+    let rowid = match params
+        .get("rowid")
+        .or_else(|| event.query_string_parameters.first("rowid"))
+    {
+        Some(id) => match quotes::resolve_id(client, id).await? {
+            Some(rowid) => Some(rowid),
+            None => return Ok(missing_quote(id)),
+        },
+        None => None,
+    };
 
     let resp = match method {
         http::Method::GET => {
-            let json_resp = if let Some(rowid) = event.query_string_parameters.first("rowid") {
-                let quote = get_quote(client, rowid.parse::<i64>()?).await?;
-                serde_json::to_string(&quote)?
+            if let Some(rowid) = rowid {
+                let projection = match event.query_string_parameters.first("fields") {
+                    Some(fields) => match quotes::Projection::parse(fields) {
+                        Ok(projection) => Some(projection),
+                        Err(reason) => return Ok(response::problem(400, "Bad Request", &reason)),
+                    },
+                    None => None,
+                };
+                match quotes::get_quote_fields(client, rowid, projection.as_ref()).await? {
+                    Some(mut quote) => {
+                        usage::read(1);
+                        if projection.as_ref().map_or(true, |p| p.includes("lines")) {
+                            let lines = quotes::get_lines(client, rowid).await?;
+                            if !lines.is_empty() {
+                                quote.lines = Some(lines);
+                            }
+                        }
+                        let links = links::for_quote(&event, quote.public_id());
+                        let mut resp = match &projection {
+                            Some(projection) => serializer::projected_quote(
+                                format, 200, &quote, projection, &links,
+                            )?,
+                            None => serializer::quote(format, 200, &Some(quote), &links)?,
+                        };
+                        let canonical = format!("<{}>; rel=\"canonical\"", links.self_link);
+                        if let Ok(canonical) = http::HeaderValue::from_str(&canonical) {
+                            resp.headers.insert(http::header::LINK, canonical);
+                        }
+                        resp
+                    }
+                    None => missing_quote(rowid),
+                }
             } else {
-                let quotes = get_quotes(client).await?;
-                serde_json::to_string(&quotes)?
-            };
+                let page = match page_param(&event) {
+                    Ok(page) => page,
+                    Err(resp) => return Ok(resp),
+                };
+                let limit = match limit_param(&event) {
+                    Ok(limit) => limit,
+                    Err(resp) => return Ok(resp),
+                };
+                let lang = event.query_string_parameters.first("lang");
+                let region = match event.query_string_parameters.first("region") {
+                    Some("local") => match config::crdb_region() {
+                        Some(region) => Some(region),
+                        None => {
+                            return Ok(response::problem(
+                                400,
+                                "Bad Request",
+                                "region=local needs REGIONAL_BY_ROW.",
+                            ))
+                        }
+                    },
+                    Some(_) => {
+                        return Ok(response::problem(
+                            400,
+                            "Bad Request",
+                            "region must be local.",
+                        ))
+                    }
+                    None => None,
+                };
+                let region = region.as_deref();
+                let mut meta = Meta {
+                    limit_applied: Some(limit),
+                    ..Meta::default()
+                };
+                let as_of = match event.query_string_parameters.first("as_of") {
+                    Some(as_of) => match quotes::AsOf::parse(as_of) {
+                        Some(as_of) => match as_of.age() {
+                            Some(age) if age > quotes::page_snapshot_ttl() => {
+                                return Ok(response::problem(
+                                    410,
+                                    "Gone",
+                                    "The listing this page belongs to has expired; start again from the first page.",
+                                ))
+                            }
+                            Some(_) => Some(as_of),
+                            None => return Ok(response::text(400, "as_of is in the future")),
+                        },
+                        None => return Ok(response::text(400, "as_of is not a cluster timestamp")),
+                    },
+                    None if quotes::consistent_pages() => {
+                        Some(quotes::snapshot_timestamp(client).await?)
+                    }
+                    None => None,
+                };
+                let q = event.query_string_parameters.first("q");
+                let as_of_ref = as_of.as_ref();
+                // Plain JSON is written straight from the rows; other formats need quotes.
+                let mut list = match (q, format) {
+                    (Some(q), Format::Json) => Listing::Json(
+                        quotes::search_quotes_json(client, q, page, limit, lang, as_of_ref, region)
+                            .await?,
+                    ),
+                    (None, Format::Json) => Listing::Json(
+                        quotes::get_quotes_json(client, page, limit, lang, as_of_ref, region)
+                            .await?,
+                    ),
+                    (Some(q), _) => Listing::Quotes(
+                        search_quotes(client, q, page, limit, lang, as_of_ref, region).await?,
+                    ),
+                    (None, _) => Listing::Quotes(
+                        get_quotes(client, page, limit, lang, as_of_ref, region).await?,
+                    ),
+                };
+                if let Some(q) = q {
+                    if let Listing::Quotes(list) = &mut list {
+                        for quote in &mut list.quotes {
+                            quote.highlight = quote
+                                .quote
+                                .as_deref()
+                                .map(|text| highlight::highlight(text, q));
+                        }
+                    }
+                    if list.is_empty() && !list.truncated() {
+                        meta.suggestions = quotes::suggest(client, q).await?;
+                    }
+                }
+                meta.truncated = list.truncated();
+                usage::read(list.len());
 
-            ApiGatewayProxyResponse {
-                status_code: 200,
-                headers: HeaderMap::new(),
-                multi_value_headers: HeaderMap::new(),
-                body: Some(Body::Text(json_resp)),
-                is_base64_encoded: Some(false),
+                let as_of_param = as_of.as_ref().map(quotes::AsOf::as_str);
+                // Out of time: skip the count and link onwards without a last page.
+                let links = if list.truncated() || deadline::budget_spent() {
+                    let has_next = list.truncated() || list.len() as i64 == limit;
+                    links::for_page(&event, page, has_next, None, as_of_param)
+                } else {
+                    let total = match q {
+                        Some(q) => quotes::count_search(client, q, lang, as_of_ref, region).await?,
+                        None => quotes::count_quotes(client, lang, as_of_ref, region).await?,
+                    };
+                    let last_page = links::last_page(total, limit);
+                    links::for_page(&event, page, page < last_page, Some(last_page), as_of_param)
+                };
+                match list {
+                    Listing::Json(list) => serializer::quotes_json(200, &list.json, &links, &meta)?,
+                    Listing::Quotes(list) => {
+                        serializer::quotes(format, 200, &list.quotes, &links, &meta)?
+                    }
+                }
             }
         }
         http::Method::POST => {
-            let new_quote: Quote = serde_json::from_str(&event.body.unwrap())?;
-            let new_quote = insert_quote(client, new_quote).await?;
-            let quote_json = serde_json::to_string(&new_quote)?;
-            ApiGatewayProxyResponse {
-                status_code: 201,
-                headers: HeaderMap::new(),
-                multi_value_headers: HeaderMap::new(),
-                body: Some(Body::Text(quote_json)),
-                is_base64_encoded: Some(false),
+            if let Some(resp) = spam::check(&event, Some(client)).await? {
+                return Ok(resp);
             }
+            let mut new_quote = match validation::parse_quote(&event) {
+                Ok(quote) => quote,
+                Err(resp) => return Ok(resp),
+            };
+            new_quote.created_by = principal.owner();
+            let new_quote = insert_quote(client, new_quote).await?;
+            usage::wrote(1);
+            let links = links::for_quote(&event, new_quote.public_id());
+            serializer::created(format, &new_quote, &links)?
         }
-        http::Method::PUT => match event.query_string_parameters.first("rowid") {
+        http::Method::PUT => match rowid {
             Some(rowid) => {
-                let rowid: i64 = rowid.parse()?;
-
-                let updated_quote = serde_json::from_str(&event.body.unwrap())?;
-
-                let quote = update_quote(client, rowid, updated_quote).await?;
-
-                let json_resp = serde_json::to_string(&quote)?;
+                let owner = quotes::quote_owner(client, rowid, quotes::Lock::None).await?;
+                if !principal.may_modify(owner.as_deref()) {
+                    return Ok(auth::not_owner());
+                }
+                let updated_quote = match validation::parse_quote(&event) {
+                    Ok(quote) => quote,
+                    Err(resp) => return Ok(resp),
+                };
 
-                ApiGatewayProxyResponse {
-                    status_code: 200,
-                    headers: HeaderMap::new(),
-                    multi_value_headers: HeaderMap::new(),
-                    body: Some(Body::Text(json_resp)),
-                    is_base64_encoded: Some(false),
+                match update_quote(client, rowid, updated_quote).await? {
+                    Some(quote) => {
+                        usage::wrote(1);
+                        let links = links::for_quote(&event, quote.public_id());
+                        serializer::quote(format, 200, &Some(quote), &links)?
+                    }
+                    None => missing_quote(rowid),
                 }
             }
-            None => ApiGatewayProxyResponse {
-                status_code: 400,
-                headers: HeaderMap::new(),
-                multi_value_headers: HeaderMap::new(),
-                body: Some(Body::Text(String::from("rowid is required"))),
-                is_base64_encoded: Some(false),
-            },
+            None => response::rowid_required(),
         },
-        http::Method::DELETE => match event.query_string_parameters.first("rowid") {
+        http::Method::DELETE => match rowid {
             Some(rowid) => {
-                let rowid: i64 = rowid.parse()?;
-
-                let _res = delete_quote(client, rowid).await?;
-
-                ApiGatewayProxyResponse {
-                    status_code: 204,
-                    headers: HeaderMap::new(),
-                    multi_value_headers: HeaderMap::new(),
-                    body: Some(Body::Empty),
-                    is_base64_encoded: Some(false),
+                let owner = quotes::quote_owner(client, rowid, quotes::Lock::None).await?;
+                if !principal.may_modify(owner.as_deref()) {
+                    return Ok(auth::not_owner());
+                }
+                match delete_quote(client, rowid).await? {
+                    0 => missing_quote(rowid),
+                    deleted => {
+                        usage::wrote(deleted as usize);
+                        response::empty(204)
+                    }
                 }
             }
-            None => ApiGatewayProxyResponse {
-                status_code: 400,
-                headers: HeaderMap::new(),
-                multi_value_headers: HeaderMap::new(),
-                body: Some(Body::Text(String::from("rowid is required"))),
-                is_base64_encoded: Some(false),
-            },
-        },
-        _ => ApiGatewayProxyResponse {
-            status_code: 405,
-            headers: HeaderMap::new(),
-            multi_value_headers: HeaderMap::new(),
-            body: Some(Body::Text(String::from("Method Not Allowed"))),
-            is_base64_encoded: Some(false),
+            None => response::rowid_required(),
         },
+        _ => response::method_not_allowed(&["GET", "POST", "PUT", "DELETE"]),
     };
 
     Ok(resp)
 }
 
-async fn get_db_client() -> Result<Client, Error> {
-    let database_url = std::env::var("DATABASE_URL").expect("Must have a DATABASE_URL set");
+/// Validates a new quote and hands it to the write queue instead of inserting it directly.
+/// A dry run returns the validated quote without queueing it.
+async fn enqueue_handler(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &auth::Principal,
+    dry_run: bool,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let queue_url = match queue::queue_url() {
+        Some(url) => url,
+        None => {
+            return Ok(response::problem(
+                501,
+                "Not Implemented",
+                "Asynchronous writes are not configured.",
+            ))
+        }
+    };
+    // A dry run does not count towards the submission interval.
+    if let Some(resp) = spam::check(event, (!dry_run).then_some(client)).await? {
+        return Ok(resp);
+    }
+    let mut new_quote = match validation::parse_quote(event) {
+        Ok(quote) => quote,
+        Err(resp) => return Ok(resp),
+    };
+    new_quote.created_by = principal.owner();
+
+    if dry_run {
+        let body = serde_json::json!({ "data": { "quote": new_quote, "status": "validated" } });
+        let mut resp = response::json(200, body.to_string());
+        resp.headers
+            .insert("dry-run", http::HeaderValue::from_static("true"));
+        return Ok(resp);
+    }
+    let tracking_id = queue::enqueue(&queue_url, &new_quote).await?;
+    usage::wrote(1);
+    let body = serde_json::json!({ "data": { "tracking_id": tracking_id, "status": "queued" } });
+    Ok(response::json(202, body.to_string()))
+}
 
-    let cert = std::fs::read("../cc-ca.crt")?;
-    let cert = openssl::x509::X509::from_pem(&cert).unwrap();
-    let mut ctx = SslConnector::builder(SslMethod::tls())?;
-    ctx.set_certificate(&cert)?;
-    let connector = MakeTlsConnector::new(ctx.build());
+async fn batch_handler(
+    method: &http::Method,
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let mode = match batch::Mode::from_param(event.query_string_parameters.first("mode")) {
+        Some(mode) => mode,
+        None => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "mode must be best_effort, transactional or chunked.",
+            ))
+        }
+    };
+    let items: Vec<serde_json::Value> = match event.body.as_deref().map(serde_json::from_str) {
+        Some(Ok(items)) => items,
+        _ => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "The request body must be a JSON array of quotes.",
+            ))
+        }
+    };
+    let operation = if method == http::Method::POST {
+        batch::Operation::Insert
+    } else {
+        batch::Operation::Update
+    };
 
-    let (client, connection) = tokio_postgres::connect(&database_url, connector).await?;
+    let nested = is_dry_run(method, event);
+    if mode == batch::Mode::Chunked {
+        let chunk_size = match event.query_string_parameters.first("chunk_size") {
+            None => 100,
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    return Ok(response::problem(
+                        400,
+                        "Bad Request",
+                        "chunk_size must be a positive integer.",
+                    ))
+                }
+            },
+        };
+        let (results, chunks) =
+            batch::run_chunked(client, principal, operation, items, chunk_size, nested).await?;
+        usage::wrote(results.iter().filter(|result| result.status < 300).count());
+        let body = serde_json::json!({ "mode": mode, "results": results, "chunks": chunks });
+        return Ok(response::json(207, body.to_string()));
+    }
+    let results = batch::run(client, principal, operation, mode, items, nested).await?;
+    usage::wrote(results.iter().filter(|result| result.status < 300).count());
+    let body = serde_json::json!({ "mode": mode, "results": results });
+    Ok(response::json(207, body.to_string()))
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
+async fn import_handler(
+    method: &http::Method,
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    if is_dry_run(method, event) {
+        return Ok(response::problem(
+            400,
+            "Bad Request",
+            "Imports commit as they go and cannot be dry runs.",
+        ));
+    }
+    let items: Vec<serde_json::Value> = match imports::from_upload(event) {
+        Some(Ok(items)) => items,
+        Some(Err(reason)) => return Ok(response::problem(400, "Bad Request", &reason)),
+        None => {
+            let body = event.body.as_deref().unwrap_or_default().as_bytes();
+            let ndjson = event
+                .headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map_or(false, |value| value.starts_with("application/x-ndjson"));
+            let items = match ndjson {
+                true => imports::parse_ndjson(body),
+                false => imports::parse_json(body),
+            };
+            match items {
+                Ok(items) => items,
+                Err(reason) => return Ok(response::problem(400, "Bad Request", &reason)),
+            }
         }
-    });
+    };
+    if items.is_empty() {
+        return Ok(response::problem(
+            400,
+            "Bad Request",
+            "The import contains no quotes.",
+        ));
+    }
 
-    return Ok(client);
+    let key = event
+        .headers
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok());
+    let job = imports::start(client, principal.owner().as_deref(), key, items).await?;
+    Ok(jobs::response(event, &job))
 }
 
-async fn get_quotes(client: Client) -> Result<Vec<Quote>, tokio_postgres::Error> {
-    let mut quotes = Vec::new();
+async fn job_status_handler(
+    params: &Params,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let id = params.get("id").unwrap_or_default();
+    let job = match id.parse() {
+        Ok(id) => jobs::get(client, id).await?,
+        Err(_) => None,
+    };
+    match job {
+        // Jobs started without an owner, such as anonymous imports, can be read by anyone.
+        Some(job)
+            if job.created_by.is_none() || principal.may_modify(job.created_by.as_deref()) =>
+        {
+            Ok(response::json(
+                200,
+                serde_json::json!({ "data": job }).to_string(),
+            ))
+        }
+        _ => Ok(response::not_found(&format!("Job {} does not exist.", id))),
+    }
+}
 
-    for row in client
-        .query(
-            "SELECT rowid, quote, characters, stardate, episode FROM quotes ORDER BY episode asc LIMIT 20;",
-            &[],
-        )
-        .await?
+async fn transact_handler(
+    method: &http::Method,
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let operations: Vec<transact::Operation> = match event.body.as_deref().map(serde_json::from_str)
     {
-        let quote = Quote {
-            rowid: row.get(0),
-            quote: row.get(1),
-            characters: row.get(2),
-            stardate: row.get(3),
-            episode: row.get(4),
-        };
-        quotes.push(quote);
+        Some(Ok(operations)) => operations,
+        Some(Err(e)) => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                &format!("The request body must be a JSON array of operations: {}", e),
+            ))
+        }
+        None => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "The request body must be a JSON array of operations.",
+            ))
+        }
+    };
+    if operations.is_empty() || operations.len() > transact::max_operations() {
+        return Ok(response::problem(
+            400,
+            "Bad Request",
+            &format!(
+                "A transaction takes between 1 and {} operations.",
+                transact::max_operations()
+            ),
+        ));
     }
 
-    Ok(quotes)
-}
-
-async fn get_quote(client: Client, rowid: i64) -> Result<Option<Quote>, tokio_postgres::Error> {
-    let row = client
-        .query_opt(
-            "SELECT rowid, quote, characters, stardate, episode FROM quotes WHERE rowid=$1;",
-            &[&rowid],
-        )
-        .await?;
-
-    match row {
-        Some(row) => {
-            let quote = Quote {
-                rowid: row.get(0),
-                quote: row.get(1),
-                characters: row.get(2),
-                stardate: row.get(3),
-                episode: row.get(4),
-            };
-            Ok(Some(quote))
+    let nested = is_dry_run(method, event);
+    match transact::run(client, principal, &operations, nested).await? {
+        transact::Outcome::Committed { results, attempts } => {
+            usage::wrote(results.len());
+            let body = serde_json::json!({ "results": results, "attempts": attempts });
+            Ok(response::json(200, body.to_string()))
+        }
+        transact::Outcome::RolledBack(failed) => {
+            let title = http::StatusCode::from_u16(failed.status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or("Error");
+            Ok(response::problem_with(
+                i64::from(failed.status),
+                title,
+                failed.error.as_deref().unwrap_or_default(),
+                serde_json::json!({ "index": failed.index }),
+            ))
         }
-        None => Ok(None),
     }
 }
 
-async fn insert_quote(client: Client, new_quote: Quote) -> Result<Quote, tokio_postgres::Error> {
-    let statement = client
-        .prepare_typed(
-            "INSERT INTO quotes (quote, characters, stardate, episode) VALUES ($1, $2, $3, $4) RETURNING rowid, quote, characters, stardate, episode;",
-            &[Type::VARCHAR, Type::VARCHAR, Type::NUMERIC, Type::INT8],
-        )
-        .await?;
-
-    let row = client
-        .query_opt(
-            &statement,
-            &[
-                &new_quote.quote,
-                &new_quote.characters,
-                &new_quote.stardate,
-                &new_quote.episode,
-            ],
-        )
-        .await?
-        .unwrap();
+async fn related_handler(
+    event: &ApiGatewayProxyRequest,
+    params: &Params,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let id = params.get("rowid").unwrap_or_default();
+    let quote = match quotes::resolve_id(client, id).await? {
+        Some(rowid) => get_quote(client, rowid).await?,
+        None => None,
+    };
+    let quote = match quote {
+        Some(quote) => quote,
+        None => return Ok(missing_quote(id)),
+    };
 
-    let quote = Quote {
-        rowid: row.get(0),
-        quote: row.get(1),
-        characters: row.get(2),
-        stardate: row.get(3),
-        episode: row.get(4),
+    let query = &event.query_string_parameters;
+    let limit = |name: &str| -> Result<i64, ApiGatewayProxyResponse> {
+        match query.first(name) {
+            Some(limit) => match limit.parse::<i64>() {
+                Ok(limit) => Ok(limit.clamp(0, quotes::max_page_size())),
+                Err(_) => Err(response::problem(
+                    400,
+                    "Bad Request",
+                    &format!("{} must be an integer.", name),
+                )),
+            },
+            None => Ok(5),
+        }
+    };
+    let limits = match (
+        limit("episode_limit"),
+        limit("character_limit"),
+        limit("similar_limit"),
+    ) {
+        (Ok(episode), Ok(character), Ok(similar)) => RelatedLimits {
+            episode,
+            character,
+            similar,
+        },
+        (Err(resp), _, _) | (_, Err(resp), _) | (_, _, Err(resp)) => return Ok(resp),
     };
 
-    Ok(quote)
+    let related = quotes::related_quotes(client, &quote, &limits).await?;
+    let links = links::for_request(event);
+    let format = Format::negotiate(&event.headers);
+    Ok(serializer::quotes(
+        format,
+        200,
+        &related,
+        &links,
+        &Meta::default(),
+    )?)
 }
 
-async fn update_quote(
-    client: Client,
-    rowid: i64,
-    quote: Quote,
-) -> Result<Option<Quote>, tokio_postgres::Error> {
-    let mut builder = string_builder::Builder::default();
-    builder.append("UPDATE quotes SET ");
-    let mut cols = Vec::new();
-    if let Some(q) = quote.quote {
-        cols.push(format!("quote='{}'", q));
-    }
-    if let Some(q) = quote.characters {
-        cols.push(format!("characters='{}'", q));
-    }
-    if let Some(q) = quote.episode {
-        cols.push(format!("episode={}", q));
+async fn share_handler(
+    event: &ApiGatewayProxyRequest,
+    params: &Params,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let id = params.get("rowid").unwrap_or_default();
+    let quote = match quotes::resolve_id(client, id).await? {
+        Some(rowid) => get_quote(client, rowid).await?,
+        None => None,
+    };
+    match quote {
+        Some(quote) => Ok(share::response(
+            share::ShareFormat::negotiate(&event.headers),
+            &quote,
+        )),
+        None => Ok(missing_quote(id)),
     }
-    if let Some(q) = quote.stardate {
-        cols.push(format!("stardate={}", q));
+}
+
+/// Issues a link that reads the quote without credentials until it expires. Only the quote's
+/// owner or an admin may share it.
+async fn share_link_handler(
+    event: &ApiGatewayProxyRequest,
+    params: &Params,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let id = params.get("rowid").unwrap_or_default();
+    let quote = match quotes::resolve_id(client, id).await? {
+        Some(rowid) => get_quote(client, rowid).await?,
+        None => None,
+    };
+    let quote = match quote {
+        Some(quote) => quote,
+        None => return Ok(missing_quote(id)),
+    };
+    if !principal.may_modify(quote.created_by.as_deref()) {
+        return Ok(auth::not_owner());
     }
-    builder.append(cols.join(", "));
-    builder.append(format!(" WHERE rowid={}", rowid));
-    builder.append(" RETURNING rowid, quote, characters, stardate, episode;");
-
-    let sql = &builder.string().unwrap();
-    let statement = client.prepare(sql).await?;
-
-    let row = client.query_opt(&statement, &[]).await?;
-
-    match row {
-        Some(row) => {
-            let quote = Quote {
-                rowid: row.get(0),
-                quote: row.get(1),
-                characters: row.get(2),
-                stardate: row.get(3),
-                episode: row.get(4),
-            };
-            Ok(Some(quote))
+
+    let ttl = match event.query_string_parameters.first("ttl") {
+        Some(ttl) => match ttl.parse::<i64>() {
+            Ok(ttl) if (1..=share_link::max_ttl()).contains(&ttl) => Some(ttl),
+            _ => {
+                return Ok(response::problem(
+                    400,
+                    "Bad Request",
+                    &format!(
+                        "ttl must be between 1 and {} seconds.",
+                        share_link::max_ttl()
+                    ),
+                ))
+            }
+        },
+        None => None,
+    };
+    let public_id = quote.public_id().unwrap_or_default();
+    let (token, expires_at) = match share_link::token(&public_id, share_link::ttl(ttl)) {
+        Some(token) => token,
+        None => {
+            return Ok(response::problem(
+                501,
+                "Not Implemented",
+                "Share links are not configured.",
+            ))
         }
-        None => Ok(None),
+    };
+    let url = format!(
+        "{}/{}?token={}",
+        links::collection_url(event),
+        public_id,
+        token
+    );
+    let body = serde_json::json!({
+        "data": { "url": url, "token": token, "expires_at": expires_at.to_rfc3339() }
+    });
+    Ok(response::json(201, body.to_string()))
+}
+
+async fn character_names_handler(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let page = match page_param(event) {
+        Ok(page) => page,
+        Err(resp) => return Ok(resp),
+    };
+    let limit = match limit_param(event) {
+        Ok(limit) => limit,
+        Err(resp) => return Ok(resp),
+    };
+    let names = quotes::character_names(client, page, limit).await?;
+    let total = quotes::count_character_names(client).await?;
+    let last_page = links::last_page(total, limit);
+    let links = links::for_page(event, page, page < last_page, Some(last_page), None);
+    let meta = Meta {
+        limit_applied: Some(limit),
+        ..Meta::default()
+    };
+    let format = Format::negotiate(&event.headers);
+    Ok(serializer::character_names(
+        format, 200, &names, &links, &meta,
+    )?)
+}
+
+/// The quotes submitted by the calling JWT subject, newest first.
+async fn my_quotes_handler(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &auth::Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let subject = match principal.subject.as_deref() {
+        Some(subject) => subject,
+        None => {
+            return Ok(response::problem(
+                401,
+                "Unauthorized",
+                "A bearer token with a subject is required to list your quotes.",
+            ))
+        }
+    };
+    let page = match page_param(event) {
+        Ok(page) => page,
+        Err(resp) => return Ok(resp),
+    };
+    let limit = match limit_param(event) {
+        Ok(limit) => limit,
+        Err(resp) => return Ok(resp),
+    };
+    let quotes = quotes::quotes_by_creator(client, subject, page, limit).await?;
+    usage::read(quotes.len());
+    let total = quotes::count_by_creator(client, subject).await?;
+    let last_page = links::last_page(total, limit);
+    let links = links::for_page(event, page, page < last_page, Some(last_page), None);
+    let meta = Meta {
+        limit_applied: Some(limit),
+        ..Meta::default()
+    };
+    let format = Format::negotiate(&event.headers);
+    Ok(serializer::quotes(format, 200, &quotes, &links, &meta)?)
+}
+
+fn missing_quote(id: impl std::fmt::Display) -> ApiGatewayProxyResponse {
+    response::not_found(&format!("Quote {} does not exist.", id))
+}
+
+fn page_param(event: &ApiGatewayProxyRequest) -> Result<i64, ApiGatewayProxyResponse> {
+    match event.query_string_parameters.first("page") {
+        Some(page) => match page.parse::<i64>() {
+            Ok(page) => Ok(page.max(1)),
+            Err(_) => Err(response::problem(
+                400,
+                "Bad Request",
+                "page must be an integer.",
+            )),
+        },
+        None => Ok(1),
     }
 }
 
-async fn delete_quote(client: Client, rowid: i64) -> Result<u64, tokio_postgres::Error> {
-    let statement = client
-        .prepare_typed("DELETE FROM quotes WHERE rowid = $1", &[Type::INT8])
-        .await?;
+/// The page size for a list request, from `?limit=` or `DEFAULT_PAGE_SIZE`.
+fn limit_param(event: &ApiGatewayProxyRequest) -> Result<i64, ApiGatewayProxyResponse> {
+    let requested = match event.query_string_parameters.first("limit") {
+        Some(limit) => match limit.parse::<i64>() {
+            Ok(limit) => Some(limit),
+            Err(_) => {
+                return Err(response::problem(
+                    400,
+                    "Bad Request",
+                    "limit must be an integer.",
+                ))
+            }
+        },
+        None => None,
+    };
+    Ok(quotes::page_size(requested))
+}
 
-    let res = client.execute(&statement, &[&rowid]).await?;
+async fn timeline_handler(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let bucket_size = match event.query_string_parameters.first("bucket_size") {
+        Some(size) => match size.parse::<Decimal>() {
+            Ok(size) if size > Decimal::ZERO => size,
+            _ => {
+                return Ok(response::problem(
+                    400,
+                    "Bad Request",
+                    "bucket_size must be a positive number.",
+                ))
+            }
+        },
+        None => quotes::SEASON_BUCKET_SIZE,
+    };
 
-    Ok(res)
+    let buckets = quotes::timeline(client, bucket_size).await?;
+    let links = links::for_request(event);
+    let format = Format::negotiate(&event.headers);
+    Ok(serializer::timeline(format, 200, &buckets, &links)?)
+}
+
+async fn lookup_handler(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let query = &event.query_string_parameters;
+    let episode = match query.first("episode").map(str::parse::<i64>).transpose() {
+        Ok(episode) => episode,
+        Err(_) => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "episode must be an integer.",
+            ))
+        }
+    };
+    let stardate = match query
+        .first("stardate")
+        .map(str::parse::<Decimal>)
+        .transpose()
+    {
+        Ok(stardate) => stardate,
+        Err(_) => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "stardate must be a number.",
+            ))
+        }
+    };
+    let key = NaturalKey {
+        episode,
+        character: query.first("character").map(String::from),
+        stardate,
+    };
+    if key.episode.is_none() && key.character.is_none() && key.stardate.is_none() {
+        return Ok(response::text(
+            400,
+            "at least one of episode, character or stardate is required",
+        ));
+    }
+
+    let format = Format::negotiate(&event.headers);
+    let mut matches = quotes::lookup_quotes(client, &key).await?;
+    match matches.len() {
+        0 => Ok(response::not_found("No quote matches the given key.")),
+        1 => {
+            let quote = matches.pop();
+            let links = links::for_quote(event, quote.as_ref().and_then(Quote::public_id));
+            Ok(serializer::quote(format, 200, &quote, &links)?)
+        }
+        _ => {
+            let links = links::for_request(event);
+            Ok(serializer::quotes(
+                format,
+                300,
+                &matches,
+                &links,
+                &Meta::default(),
+            )?)
+        }
+    }
 }
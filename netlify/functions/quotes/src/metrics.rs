@@ -0,0 +1,51 @@
+//! In-process counters, published to CloudWatch using the embedded metric format.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+const NAMESPACE: &str = "quotes";
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Counter {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn incr(&self) -> u64 {
+        self.add(1)
+    }
+
+    pub fn add(&self, n: u64) -> u64 {
+        self.0.fetch_add(n, Ordering::Relaxed) + n
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub static RECONNECTS: Counter = Counter::new();
+pub static RECOVERED_READS: Counter = Counter::new();
+
+/// Writes a single metric as an embedded metric format record on stdout.
+pub fn emit(name: &str, value: f64, unit: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut record = json!({
+        "_aws": {
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": NAMESPACE,
+                "Dimensions": [[]],
+                "Metrics": [{ "Name": name, "Unit": unit }],
+            }],
+        },
+    });
+    record[name] = json!(value);
+    println!("{}", record);
+}
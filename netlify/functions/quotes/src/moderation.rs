@@ -0,0 +1,97 @@
+//! Content policy checks for submitted quotes.
+//!
+//! Deployments that accept public submissions can `install` their own [`ContentFilter`];
+//! otherwise the [`Wordlist`] filter runs when `CONTENT_FILTER_WORDS` is set.
+
+use std::sync::{Arc, Mutex};
+
+use crate::config;
+use crate::quotes::Quote;
+
+pub enum Verdict {
+    Allow,
+    /// Accepted, but logged for review with the reason.
+    Flag(String),
+    /// Refused with `422` and the reason.
+    Reject(String),
+}
+
+pub trait ContentFilter: Send + Sync {
+    fn check(&self, quote: &Quote) -> Verdict;
+}
+
+static FILTER: Mutex<Option<Arc<dyn ContentFilter>>> = Mutex::new(None);
+
+/// Replaces the filter used for every later submission.
+pub fn install(filter: Arc<dyn ContentFilter>) {
+    *FILTER.lock().unwrap() = Some(filter);
+}
+
+/// Runs the installed filter, or the wordlist filter configured from the environment.
+pub fn check(quote: &Quote) -> Verdict {
+    let installed = FILTER.lock().unwrap().clone();
+    let verdict = match installed {
+        Some(filter) => filter.check(quote),
+        None => match Wordlist::from_env() {
+            Some(wordlist) => wordlist.check(quote),
+            None => Verdict::Allow,
+        },
+    };
+    if let Verdict::Flag(reason) = &verdict {
+        log::warn!("content filter flagged a submission: {}", reason);
+    }
+    verdict
+}
+
+/// Matches whole words, ignoring case, in the quote text, characters and dialogue lines.
+pub struct Wordlist {
+    words: Vec<String>,
+    reject: bool,
+}
+
+impl Wordlist {
+    pub fn new(words: Vec<String>, reject: bool) -> Wordlist {
+        let words = words
+            .into_iter()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Wordlist { words, reject }
+    }
+
+    /// Reads the comma-separated `CONTENT_FILTER_WORDS`. `CONTENT_FILTER_ACTION=flag` accepts
+    /// matching submissions instead of rejecting them.
+    pub fn from_env() -> Option<Wordlist> {
+        let words = std::env::var("CONTENT_FILTER_WORDS").ok()?;
+        let reject = config::var_or("CONTENT_FILTER_ACTION", String::from("reject")) != "flag";
+        Some(Wordlist::new(
+            words.split(',').map(String::from).collect(),
+            reject,
+        ))
+    }
+}
+
+impl ContentFilter for Wordlist {
+    fn check(&self, quote: &Quote) -> Verdict {
+        let mut texts: Vec<&str> = Vec::new();
+        texts.extend(quote.quote.as_deref());
+        texts.extend(quote.characters.iter().flatten().map(String::as_str));
+        for line in quote.lines.iter().flatten() {
+            texts.push(&line.text);
+            texts.extend(line.speaker.as_deref());
+        }
+
+        let found = texts
+            .iter()
+            .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+            .map(str::to_lowercase)
+            .find(|word| self.words.contains(word));
+        match found {
+            Some(word) if self.reject => {
+                Verdict::Reject(format!("contains the blocked word \"{}\"", word))
+            }
+            Some(word) => Verdict::Flag(format!("contains the blocked word \"{}\"", word)),
+            None => Verdict::Allow,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! `multipart/form-data` request bodies, as sent by browser file uploads.
+//!
+//! API Gateway hands the whole body over at once, usually base64-encoded, so the parts are
+//! cut out of it in memory.
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use http::header::CONTENT_TYPE;
+
+#[derive(Debug, Default)]
+pub struct Part {
+    /// The form field, from `Content-Disposition: form-data; name="..."`.
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// The boundary of a `multipart/form-data` request, `None` for other content types.
+pub fn boundary(event: &ApiGatewayProxyRequest) -> Option<String> {
+    let content_type = event.headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// Splits the request body into its parts.
+pub fn parts(event: &ApiGatewayProxyRequest, boundary: &str) -> Result<Vec<Part>, &'static str> {
+    let body = event.body.as_deref().ok_or("The upload has no body.")?;
+    let body = match event.is_base64_encoded.unwrap_or(false) {
+        true => {
+            openssl::base64::decode_block(body).map_err(|_| "The upload is not valid base64.")?
+        }
+        false => body.as_bytes().to_vec(),
+    };
+    parse(&body, boundary)
+}
+
+fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, &'static str> {
+    const MALFORMED: &str = "The upload is not valid multipart/form-data.";
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n--{}", boundary);
+
+    let mut at = find(body, delimiter.as_bytes(), 0).ok_or(MALFORMED)? + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        if body[at..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        at = find(body, b"\r\n", at).ok_or(MALFORMED)? + 2;
+        let headers_end = find(body, b"\r\n\r\n", at).ok_or(MALFORMED)?;
+        let end = find(body, next_delimiter.as_bytes(), headers_end).ok_or(MALFORMED)?;
+
+        let mut part = Part {
+            body: body[headers_end + 4..end].to_vec(),
+            ..Part::default()
+        };
+        let headers = std::str::from_utf8(&body[at..headers_end]).map_err(|_| MALFORMED)?;
+        for header in headers.split("\r\n") {
+            let (name, value) = match header.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    match param.split_once('=') {
+                        Some((key, value)) if key.trim() == "name" => {
+                            part.name = Some(value.trim().trim_matches('"').to_string())
+                        }
+                        Some((key, value)) if key.trim() == "filename" => {
+                            part.filename = Some(value.trim().trim_matches('"').to_string())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        parts.push(part);
+        at = end + next_delimiter.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
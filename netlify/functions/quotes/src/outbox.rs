@@ -0,0 +1,187 @@
+//! Events about changed data, written to the `outbox` table by the statement that changes
+//! the data and delivered afterwards by the `quotes-outbox` Lambda.
+//!
+//! Publishing inline can fail after the change has committed, losing the event; an event
+//! in the outbox commits or rolls back with its change. Delivery is retried with backoff
+//! and carries the event id as `Idempotency-Key`, so receivers can drop the rare duplicate.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use crate::db::{DbError, StatementContext};
+use crate::{config, deadline, redact, request_id, xray};
+
+/// Whether changes record events, which needs the `outbox` table.
+pub fn enabled() -> bool {
+    config::var_or("OUTBOX", false)
+}
+
+/// Wraps a mutating statement ending in `RETURNING ...` so that, with the outbox on, the same
+/// statement records `event` with every returned row as its payload, and the id of the
+/// request that made the change. The wrapped statement returns the same columns.
+pub fn with_event(statement: &str, event: &'static str) -> String {
+    if !enabled() {
+        return request_id::tag(statement);
+    }
+    with_event_and(statement, event, &[])
+}
+
+/// [`with_event`] for a change spanning tables: each of `also` is a `name AS (...)` clause
+/// run in the same statement, which can read the rows the statement returns from `changed`.
+/// The statement returns the same columns whether the outbox is on or off.
+pub fn with_event_and(statement: &str, event: &'static str, also: &[String]) -> String {
+    let statement = statement.trim_end().trim_end_matches(';');
+    let mut clauses = vec![format!("changed AS ({})", statement)];
+    clauses.extend(also.iter().cloned());
+    if enabled() {
+        clauses.push(format!(
+            "recorded AS (INSERT INTO outbox (event, payload, request_id) SELECT '{}', row_to_json(changed), {} FROM changed RETURNING id)",
+            event,
+            request_id::literal()
+        ));
+    }
+    request_id::tag(&format!(
+        "WITH {} SELECT * FROM changed;",
+        clauses.join(", ")
+    ))
+}
+
+/// Records `event` unless one with the same `dedupe_key` already exists, for changes that
+/// cannot record it in their own statement. Does nothing with the outbox off.
+pub async fn record(
+    client: &Client,
+    event: &str,
+    dedupe_key: &str,
+    payload: &Value,
+) -> Result<(), DbError> {
+    if !enabled() {
+        return Ok(());
+    }
+    let _subsegment = xray::sql("record_event");
+    client
+        .execute(
+            "INSERT INTO outbox (event, dedupe_key, payload) VALUES ($1, $2, $3) ON CONFLICT (dedupe_key) DO NOTHING;",
+            &[&event, &dedupe_key, payload],
+        )
+        .await
+        .statement("record_event")?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub delivered: u64,
+    pub failed: u64,
+}
+
+struct Event {
+    id: Uuid,
+    event: String,
+    payload: Value,
+    created_at: DateTime<Utc>,
+    request_id: Option<String>,
+}
+
+/// Delivers due events in batches of `OUTBOX_BATCH_SIZE` until none are left or the
+/// invocation's budget is spent.
+///
+/// Claiming an event pushes its next attempt back by the retry backoff, so a dispatcher
+/// that dies mid-delivery leaves it to be retried later, and two dispatchers never deliver
+/// one event at the same time. Events are given up after `OUTBOX_MAX_ATTEMPTS`.
+pub async fn dispatch(client: &Client) -> Result<Report, DbError> {
+    let batch_size: i64 = config::var_or("OUTBOX_BATCH_SIZE", 50);
+    let max_attempts: i64 = config::var_or("OUTBOX_MAX_ATTEMPTS", 20);
+    let http = reqwest::Client::new();
+    let mut report = Report::default();
+
+    while !deadline::budget_spent() {
+        let events = claim(client, batch_size, max_attempts).await?;
+        if events.is_empty() {
+            break;
+        }
+        for event in events {
+            match deliver(&http, &event).await {
+                Ok(()) => {
+                    client
+                        .execute(
+                            "UPDATE outbox SET delivered_at = now(), last_error = NULL WHERE id = $1;",
+                            &[&event.id],
+                        )
+                        .await
+                        .statement("deliver_event")?;
+                    report.delivered += 1;
+                }
+                Err(error) => {
+                    log::warn!("failed to deliver event {}: {}", event.id, error);
+                    client
+                        .execute(
+                            "UPDATE outbox SET last_error = $2 WHERE id = $1;",
+                            &[&event.id, &error],
+                        )
+                        .await
+                        .statement("deliver_event")?;
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+async fn claim(client: &Client, limit: i64, max_attempts: i64) -> Result<Vec<Event>, DbError> {
+    let _subsegment = xray::sql("claim_events");
+    let rows = client
+        .query(
+            "UPDATE outbox SET attempts = attempts + 1, next_attempt_at = now() + least(power(2, attempts)::INT8, 3600) * INTERVAL '1 second' WHERE id IN (SELECT id FROM outbox WHERE delivered_at IS NULL AND next_attempt_at <= now() AND attempts < $2 ORDER BY next_attempt_at LIMIT $1) RETURNING id, event, payload, created_at, request_id;",
+            &[&limit, &max_attempts],
+        )
+        .await
+        .statement("claim_events")?;
+    rows.iter()
+        .map(|row| {
+            let mapping = |e: tokio_postgres::Error| DbError::mapping("claim_events", e);
+            Ok(Event {
+                id: row.try_get("id").map_err(mapping)?,
+                event: row.try_get("event").map_err(mapping)?,
+                payload: row.try_get("payload").map_err(mapping)?,
+                created_at: row.try_get("created_at").map_err(mapping)?,
+                request_id: row.try_get("request_id").map_err(mapping)?,
+            })
+        })
+        .collect()
+}
+
+/// Posts the event to its webhook. The quote of the day goes to `QOTD_WEBHOOK_URL` as its
+/// Slack-compatible payload; everything else goes to `OUTBOX_WEBHOOK_URL` in an envelope.
+/// Events without a webhook count as delivered.
+async fn deliver(http: &reqwest::Client, event: &Event) -> Result<(), String> {
+    let (url, body) = match event.event.as_str() {
+        "qotd.published" => (std::env::var("QOTD_WEBHOOK_URL"), event.payload.clone()),
+        _ => (
+            std::env::var("OUTBOX_WEBHOOK_URL"),
+            serde_json::json!({
+                "id": event.id,
+                "event": event.event,
+                "created_at": event.created_at,
+                "request_id": event.request_id,
+                "data": event.payload,
+            }),
+        ),
+    };
+    let url = match url {
+        Ok(url) => url,
+        Err(_) => return Ok(()),
+    };
+
+    http.post(&url)
+        .header("Idempotency-Key", event.id.to_string())
+        .json(&body)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map(|_| ())
+        .map_err(|e| redact::redact(&e.to_string()))
+}
@@ -0,0 +1,85 @@
+//! What the handler does with a request before it needs the database: routing,
+//! authentication, the scope check and body decompression. The contract tests replay
+//! recorded events through the same steps.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+
+use crate::auth::{self, Principal};
+use crate::router::{self, Deprecation, Endpoint, Params, Resolution};
+use crate::{audit, compression, response, share_link, timing, usage};
+
+/// A request that may be dispatched to its endpoint.
+#[derive(Debug)]
+pub struct Prepared {
+    pub endpoint: Endpoint,
+    pub params: Params,
+    pub principal: Principal,
+    /// The request, with its body decompressed.
+    pub event: ApiGatewayProxyRequest,
+    /// The deprecation of the route and the path of its successor, if it is deprecated.
+    pub deprecation: Option<(&'static Deprecation, Option<String>)>,
+}
+
+/// Runs the steps before dispatch, or returns the response that ends the request early.
+pub fn prepare(event: ApiGatewayProxyRequest) -> Result<Prepared, ApiGatewayProxyResponse> {
+    let method = event.http_method.clone();
+    let path = event.path.as_deref().unwrap_or("/");
+
+    let resolution = router::resolve(&method, path);
+    timing::mark("decode");
+    let (endpoint, params, scope) = match resolution {
+        Resolution::Matched(endpoint, params, scope) => (endpoint, params, scope),
+        Resolution::MethodNotAllowed(allowed) => return Err(response::method_not_allowed(allowed)),
+        Resolution::NotFound => return Err(response::route_not_found()),
+    };
+    let deprecation = router::deprecation(path).map(|deprecation| {
+        let successor = deprecation
+            .successor
+            .map(|successor| router::successor_path(path, successor, &params));
+        (deprecation, successor)
+    });
+
+    let principal =
+        auth::principal(&event).map_err(|reason| response::problem(401, "Unauthorized", reason))?;
+    let principal = auth::on_behalf_of(principal, &event.headers)?;
+    audit::begin(&principal);
+    usage::begin(&principal);
+    if let Some(scope) = scope {
+        if !principal.has(scope) && !shared_with_link(&method, endpoint, &params, &event) {
+            return Err(auth::missing_scope(&principal, scope));
+        }
+    }
+    timing::mark("auth");
+
+    // Signed requests are verified against the body as sent, so this comes after auth.
+    let event = compression::decompress(event)?;
+
+    Ok(Prepared {
+        endpoint,
+        params,
+        principal,
+        event,
+        deprecation,
+    })
+}
+
+/// Whether the request reads a single quote with a valid `?token=` from a share link, which
+/// stands in for the `quotes:read` scope.
+fn shared_with_link(
+    method: &http::Method,
+    endpoint: Endpoint,
+    params: &Params,
+    event: &ApiGatewayProxyRequest,
+) -> bool {
+    match (
+        params.get("rowid"),
+        event.query_string_parameters.first("token"),
+    ) {
+        (Some(id), Some(token)) => {
+            method == http::Method::GET
+                && endpoint == Endpoint::Quotes
+                && share_link::verify(id, token)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,195 @@
+//! `application/x-protobuf` responses for bandwidth-sensitive clients, behind the `protobuf`
+//! feature.
+//!
+//! The messages are written out with prost derives rather than generated at build time, so
+//! building needs no `protoc`. They mirror `proto/quotes.proto`, which clients compile.
+
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use prost::Message;
+
+use crate::{links, quotes, response, serializer, timing, xray};
+
+pub const PROTOBUF: &str = "application/x-protobuf";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Line {
+    #[prost(string, optional, tag = "1")]
+    pub speaker: Option<String>,
+    #[prost(string, tag = "2")]
+    pub text: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Quote {
+    #[prost(string, optional, tag = "1")]
+    pub id: Option<String>,
+    #[prost(int64, optional, tag = "2")]
+    pub rowid: Option<i64>,
+    #[prost(string, optional, tag = "3")]
+    pub uuid: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub slug: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub quote: Option<String>,
+    #[prost(string, repeated, tag = "6")]
+    pub characters: Vec<String>,
+    #[prost(string, optional, tag = "7")]
+    pub stardate: Option<String>,
+    #[prost(int64, optional, tag = "8")]
+    pub episode: Option<i64>,
+    #[prost(string, optional, tag = "9")]
+    pub lang: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub created_by: Option<String>,
+    #[prost(message, repeated, tag = "11")]
+    pub lines: Vec<Line>,
+    #[prost(string, optional, tag = "12")]
+    pub highlight: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Links {
+    #[prost(string, tag = "1")]
+    pub self_link: String,
+    #[prost(string, tag = "2")]
+    pub collection: String,
+    #[prost(string, optional, tag = "3")]
+    pub next: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub prev: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub first: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub last: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Meta {
+    #[prost(string, repeated, tag = "1")]
+    pub suggestions: Vec<String>,
+    #[prost(string, optional, tag = "2")]
+    pub location: Option<String>,
+    #[prost(int64, optional, tag = "3")]
+    pub limit_applied: Option<i64>,
+    #[prost(bool, tag = "4")]
+    pub truncated: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QuoteResponse {
+    #[prost(message, optional, tag = "1")]
+    pub data: Option<Quote>,
+    #[prost(message, optional, tag = "2")]
+    pub links: Option<Links>,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<Meta>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QuoteList {
+    #[prost(message, repeated, tag = "1")]
+    pub data: Vec<Quote>,
+    #[prost(message, optional, tag = "2")]
+    pub links: Option<Links>,
+    #[prost(message, optional, tag = "3")]
+    pub meta: Option<Meta>,
+}
+
+impl From<&quotes::Quote> for Quote {
+    fn from(quote: &quotes::Quote) -> Self {
+        Quote {
+            id: quote.public_id(),
+            rowid: quote.rowid.filter(|_| !quotes::uuid_ids()),
+            uuid: quote.uuid.map(|uuid| uuid.to_string()),
+            slug: quote.slug.clone(),
+            quote: quote.quote.clone(),
+            characters: quote.characters.clone().unwrap_or_default(),
+            stardate: quote.stardate.map(|stardate| stardate.to_string()),
+            episode: quote.episode,
+            lang: quote.lang.clone(),
+            created_by: quote.created_by.clone(),
+            lines: quote
+                .lines
+                .iter()
+                .flatten()
+                .map(|line| Line {
+                    speaker: line.speaker.clone(),
+                    text: line.text.clone(),
+                })
+                .collect(),
+            highlight: quote.highlight.clone(),
+        }
+    }
+}
+
+impl From<&links::Links> for Links {
+    fn from(links: &links::Links) -> Self {
+        Links {
+            self_link: links.self_link.clone(),
+            collection: links.collection.clone(),
+            next: links.next.clone(),
+            prev: links.prev.clone(),
+            first: links.first.clone(),
+            last: links.last.clone(),
+        }
+    }
+}
+
+impl From<&serializer::Meta> for Meta {
+    fn from(meta: &serializer::Meta) -> Self {
+        Meta {
+            suggestions: meta.suggestions.clone(),
+            location: meta.location.clone(),
+            limit_applied: meta.limit_applied,
+            truncated: meta.truncated,
+        }
+    }
+}
+
+pub fn quote(
+    status_code: i64,
+    quote: Option<&quotes::Quote>,
+    links: &links::Links,
+    meta: Option<&serializer::Meta>,
+) -> ApiGatewayProxyResponse {
+    let message = QuoteResponse {
+        data: quote.map(Quote::from),
+        links: Some(links.into()),
+        meta: meta.map(Meta::from),
+    };
+    encode(status_code, &message, links)
+}
+
+pub fn quotes(
+    status_code: i64,
+    quotes: &[quotes::Quote],
+    links: &links::Links,
+    meta: &serializer::Meta,
+) -> ApiGatewayProxyResponse {
+    let message = QuoteList {
+        data: quotes.iter().map(Quote::from).collect(),
+        links: Some(links.into()),
+        meta: Some(meta.into()),
+    };
+    encode(status_code, &message, links)
+}
+
+fn encode(
+    status_code: i64,
+    message: &impl Message,
+    links: &links::Links,
+) -> ApiGatewayProxyResponse {
+    let _subsegment = xray::subsegment("serialize");
+    let body = timing::measure("serialize", || message.encode_to_vec());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(PROTOBUF));
+    if let Some(link) = links::header(links).and_then(|link| HeaderValue::from_str(&link).ok()) {
+        headers.insert(http::header::LINK, link);
+    }
+    let mut resp = response::new(status_code, headers, Body::Binary(body));
+    resp.is_base64_encoded = Some(true);
+    resp
+}
@@ -0,0 +1,210 @@
+//! Bulk deletes for `POST /admin/purge`, run as `purge` jobs.
+//!
+//! Deleting many quotes in one statement can exceed CockroachDB's transaction size limits,
+//! so each job step deletes at most `PURGE_BATCH_SIZE` matching quotes and commits with the
+//! job's progress. A purge cut short carries on where it stopped, since the quotes it
+//! already deleted no longer match.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use chrono::{DateTime, Utc};
+use lambda_runtime::Error;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::db::{DbError, StatementContext};
+use crate::jobs::{self, Job, Kind, Step};
+use crate::{config, outbox, response, xray};
+
+/// Which quotes to delete: those matching every field given. It is the job's input.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode: Option<i64>,
+    /// One of the quote's characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub character: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl Filter {
+    /// The `WHERE` condition, with every value passed as a parameter. `None` when no field
+    /// is set, so that a purge never matches every quote by accident.
+    fn condition(&self) -> Option<(String, Vec<&(dyn ToSql + Sync)>)> {
+        let mut clauses = Vec::new();
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+        if let Some(created_by) = &self.created_by {
+            params.push(created_by);
+            clauses.push(format!("created_by = ${}", params.len()));
+        }
+        if let Some(lang) = &self.lang {
+            params.push(lang);
+            clauses.push(format!("lang = ${}", params.len()));
+        }
+        if let Some(episode) = &self.episode {
+            params.push(episode);
+            clauses.push(format!("episode = ${}", params.len()));
+        }
+        if let Some(character) = &self.character {
+            params.push(character);
+            clauses.push(format!("${} = ANY(characters)", params.len()));
+        }
+        if let Some(created_before) = &self.created_before {
+            params.push(created_before);
+            clauses.push(format!("created_at < ${}", params.len()));
+        }
+        match clauses.is_empty() {
+            true => None,
+            false => Some((clauses.join(" AND "), params)),
+        }
+    }
+}
+
+/// The `progress` of a purge job.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Progress {
+    rows_deleted: i64,
+    batches: i64,
+}
+
+fn filter(event: &ApiGatewayProxyRequest) -> Result<Filter, ApiGatewayProxyResponse> {
+    let filter: Filter = match serde_json::from_str(event.body.as_deref().unwrap_or("{}")) {
+        Ok(filter) => filter,
+        Err(e) => {
+            return Err(response::problem(
+                400,
+                "Bad Request",
+                &format!("The request body is not a valid purge filter: {}", e),
+            ))
+        }
+    };
+    match filter.condition() {
+        Some(_) => Ok(filter),
+        None => Err(response::problem(
+            400,
+            "Bad Request",
+            "Give at least one of created_by, lang, episode, character or created_before.",
+        )),
+    }
+}
+
+/// Starts purging the quotes matching the filter in the request body, or resumes the
+/// caller's earlier purge with the same `Idempotency-Key`.
+pub async fn start(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let filter = match filter(event) {
+        Ok(filter) => filter,
+        Err(resp) => return Ok(resp),
+    };
+    let key = event
+        .headers
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok());
+    let job = jobs::create(
+        client,
+        Kind::Purge,
+        principal.subject.as_deref(),
+        key,
+        serde_json::to_value(&filter)?,
+        serde_json::to_value(Progress::default())?,
+    )
+    .await?;
+    let id = job.id;
+    let job = jobs::work(client, id).await?.unwrap_or(job);
+    Ok(jobs::response(event, &job))
+}
+
+/// Counts the quotes a purge with the filter in the request body would delete.
+pub async fn preview(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let filter = match filter(event) {
+        Ok(filter) => filter,
+        Err(resp) => return Ok(resp),
+    };
+    let (condition, params) = filter.condition().unwrap_or_default();
+    let row = client
+        .query_one(
+            format!("SELECT count(*) FROM quotes WHERE {};", condition).as_str(),
+            &params,
+        )
+        .await
+        .statement("purge_preview")?;
+    let matching: i64 = row.get(0);
+    Ok(response::json(
+        200,
+        serde_json::json!({ "dry_run": true, "matching": matching }).to_string(),
+    ))
+}
+
+/// Deletes one batch of matching quotes with their dialogue lines. The purge is done once
+/// a batch comes back short.
+pub async fn step(client: &Client, job: &Job) -> Result<Step, DbError> {
+    let _subsegment = xray::sql("purge_batch");
+    let mut progress: Progress = serde_json::from_value(job.progress.clone()).unwrap_or_default();
+    let batch_size: i64 = config::var_or("PURGE_BATCH_SIZE", 1000).max(1);
+
+    let input: serde_json::Value = client
+        .query_one("SELECT input FROM jobs WHERE id = $1;", &[&job.id])
+        .await
+        .statement("purge_batch")?
+        .get(0);
+    let filter: Filter = match serde_json::from_value(input) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(Step::Failed(job.progress.clone(), e.to_string())),
+    };
+    let (condition, mut params) = match filter.condition() {
+        Some(condition) => condition,
+        None => {
+            return Ok(Step::Failed(
+                job.progress.clone(),
+                String::from("The purge filter is empty."),
+            ))
+        }
+    };
+    params.push(&batch_size);
+    let sql = outbox::with_event(
+        &format!(
+            "DELETE FROM quotes WHERE {} ORDER BY rowid LIMIT ${} RETURNING rowid, uuid, slug;",
+            condition,
+            params.len()
+        ),
+        "quote.deleted",
+    );
+    let rows = client
+        .query(sql.as_str(), &params)
+        .await
+        .statement("purge_batch")?;
+    let rowids = rows
+        .iter()
+        .map(|row| row.try_get("rowid"))
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|e| DbError::mapping("purge_batch", e))?;
+    client
+        .execute(
+            "DELETE FROM quote_lines WHERE quote_rowid = ANY($1);",
+            &[&rowids],
+        )
+        .await
+        .statement("purge_batch")?;
+
+    progress.rows_deleted += rowids.len() as i64;
+    progress.batches += 1;
+    let progress_value = serde_json::to_value(&progress).unwrap_or_default();
+    if (rowids.len() as i64) < batch_size {
+        Ok(Step::Done(progress_value))
+    } else {
+        Ok(Step::Continue(progress_value))
+    }
+}
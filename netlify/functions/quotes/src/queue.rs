@@ -0,0 +1,54 @@
+//! Asynchronous writes through SQS, consumed by the `quotes-writer` Lambda.
+
+use std::sync::Mutex;
+
+use lambda_runtime::Error;
+use uuid::Uuid;
+
+use crate::quotes::Quote;
+
+static SQS: Mutex<Option<aws_sdk_sqs::Client>> = Mutex::new(None);
+
+/// The queue asynchronous writes go to, if `WRITE_QUEUE_URL` is configured.
+pub fn queue_url() -> Option<String> {
+    std::env::var("WRITE_QUEUE_URL").ok()
+}
+
+async fn client() -> aws_sdk_sqs::Client {
+    if let Some(client) = SQS.lock().unwrap().as_ref() {
+        return client.clone();
+    }
+    let config = aws_config::load_from_env().await;
+    let client = aws_sdk_sqs::Client::new(&config);
+    *SQS.lock().unwrap() = Some(client.clone());
+    client
+}
+
+/// Queues job `id` for the `quotes-jobs` Lambda to continue after `delay_secs`.
+pub async fn enqueue_job(queue_url: &str, id: Uuid, delay_secs: i32) -> Result<(), Error> {
+    client()
+        .await
+        .send_message()
+        .queue_url(queue_url)
+        .message_body(serde_json::json!({ "job_id": id }).to_string())
+        .delay_seconds(delay_secs.clamp(0, 900))
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Enqueues a validated quote for insertion, returning the message id used to track it.
+pub async fn enqueue(queue_url: &str, quote: &Quote) -> Result<String, Error> {
+    let output = client()
+        .await
+        .send_message()
+        .queue_url(queue_url)
+        .message_body(serde_json::to_string(quote)?)
+        .send()
+        .await?;
+
+    output
+        .message_id()
+        .map(String::from)
+        .ok_or_else(|| "SQS did not return a message id".into())
+}
@@ -0,0 +1,151 @@
+//! Daily and monthly quotas per caller, set in the `api_keys` table and checked against
+//! their [`usage`](crate::usage) with `QUOTAS=true`.
+//!
+//! Usage is written in batches, so the counts of other function instances that are not
+//! written yet are not seen; lower `USAGE_FLUSH_SECS` to enforce quotas more closely.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use http::HeaderValue;
+use tokio_postgres::Client;
+
+use crate::db::{DbError, StatementContext};
+use crate::{config, response, usage, xray};
+
+/// The quota reported in the `X-RateLimit-*` headers.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub limit: i64,
+    /// Including the current request for request quotas.
+    pub used: i64,
+    /// Unix time at which the quota starts over.
+    pub reset: i64,
+    writes: bool,
+}
+
+impl Quota {
+    pub fn remaining(&self) -> i64 {
+        (self.limit - self.used).max(0)
+    }
+
+    fn exceeded(&self) -> bool {
+        match self.writes {
+            // The rows a request writes are only known afterwards.
+            true => self.used >= self.limit,
+            false => self.used > self.limit,
+        }
+    }
+}
+
+pub enum Verdict {
+    /// No quota applies to the caller.
+    Unlimited,
+    /// The quota closest to running out.
+    Within(Quota),
+    /// The exceeded quota that resets last.
+    Exceeded(Quota),
+}
+
+pub fn enabled() -> bool {
+    config::var_or("QUOTAS", false)
+}
+
+/// Checks the quotas of `caller`, counting the current request. Write quotas only apply to
+/// requests that may write.
+pub async fn check(client: &Client, caller: &str, writes: bool) -> Result<Verdict, DbError> {
+    let _subsegment = xray::sql("quota_check");
+    let row = client
+        .query_opt(
+            "SELECT k.daily_requests, k.monthly_requests, k.daily_writes, k.monthly_writes, coalesce(sum(u.requests) FILTER (WHERE u.day = current_date()), 0)::INT8, coalesce(sum(u.requests), 0)::INT8, coalesce(sum(u.rows_written) FILTER (WHERE u.day = current_date()), 0)::INT8, coalesce(sum(u.rows_written), 0)::INT8, extract(epoch FROM (current_date() + 1)::TIMESTAMPTZ)::INT8, extract(epoch FROM date_trunc('month', now()) + INTERVAL '1 month')::INT8 FROM api_keys AS k LEFT JOIN api_usage AS u ON u.caller = k.caller AND u.day >= date_trunc('month', now())::DATE WHERE k.caller = $1 GROUP BY k.caller, k.daily_requests, k.monthly_requests, k.daily_writes, k.monthly_writes;",
+            &[&caller],
+        )
+        .await
+        .statement("quota_check")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(Verdict::Unlimited),
+    };
+    let (today, month) = usage::pending(caller);
+    let (day_reset, month_reset): (i64, i64) = (row.get(8), row.get(9));
+    let candidates: [(Option<i64>, i64, i64, bool); 4] = [
+        (
+            row.get(0),
+            row.get::<_, i64>(4) + today.requests + 1,
+            day_reset,
+            false,
+        ),
+        (
+            row.get(1),
+            row.get::<_, i64>(5) + month.requests + 1,
+            month_reset,
+            false,
+        ),
+        (
+            row.get(2),
+            row.get::<_, i64>(6) + today.rows_written,
+            day_reset,
+            true,
+        ),
+        (
+            row.get(3),
+            row.get::<_, i64>(7) + month.rows_written,
+            month_reset,
+            true,
+        ),
+    ];
+    let quotas: Vec<Quota> = candidates
+        .into_iter()
+        .filter(|(_, _, _, write_quota)| writes || !write_quota)
+        .filter_map(|(limit, used, reset, write_quota)| {
+            Some(Quota {
+                limit: limit?,
+                used,
+                reset,
+                writes: write_quota,
+            })
+        })
+        .collect();
+
+    if let Some(quota) = quotas
+        .iter()
+        .filter(|quota| quota.exceeded())
+        .max_by_key(|quota| quota.reset)
+    {
+        return Ok(Verdict::Exceeded(*quota));
+    }
+    Ok(match quotas.iter().min_by_key(|quota| quota.remaining()) {
+        Some(quota) => Verdict::Within(*quota),
+        None => Verdict::Unlimited,
+    })
+}
+
+/// Adds the `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset` headers.
+pub fn with_headers(mut resp: ApiGatewayProxyResponse, quota: &Quota) -> ApiGatewayProxyResponse {
+    resp.headers
+        .insert("x-ratelimit-limit", HeaderValue::from(quota.limit));
+    resp.headers.insert(
+        "x-ratelimit-remaining",
+        HeaderValue::from(quota.remaining()),
+    );
+    resp.headers
+        .insert("x-ratelimit-reset", HeaderValue::from(quota.reset));
+    resp
+}
+
+/// `429` for a caller over `quota`, retryable once it resets.
+pub fn exceeded(quota: &Quota) -> ApiGatewayProxyResponse {
+    let detail = match quota.writes {
+        true => "The write quota of these credentials is used up.",
+        false => "The request quota of these credentials is used up.",
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default();
+    let resp = response::with_retry_after(
+        response::problem(429, "Too Many Requests", detail),
+        Duration::from_secs((quota.reset - now).max(0) as u64),
+    );
+    with_headers(resp, quota)
+}
@@ -0,0 +1,1375 @@
+use chrono::{DateTime, Utc};
+use futures::{pin_mut, TryStreamExt};
+use rust_decimal::Decimal;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use serde_with::formats::PreferOne;
+use serde_with::{serde_as, DisplayFromStr, OneOrMany};
+use tokio_postgres::types::{FromSql, ToSql, Type};
+use tokio_postgres::{Client, Row};
+use uuid::Uuid;
+
+use crate::db::{DbError, StatementContext};
+use crate::router::{self, QuoteId};
+use crate::{config, deadline, highlight, lang, outbox, sanitize, slug, stardate, xray};
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Quote {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "rowid_is_internal")]
+    #[schemars(with = "Option<String>")]
+    pub rowid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// Generated from the quote text on insert unless one is given, and kept when the text
+    /// is later edited so URLs stay stable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    pub quote: Option<String>,
+    /// Everyone speaking in the quote. A single character is read and written as a plain
+    /// string, as before dialogues were supported.
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    #[schemars(with = "Option<Vec<String>>")]
+    pub characters: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "stardate::deserialize")]
+    #[schemars(with = "Option<Decimal>")]
+    pub stardate: Option<Decimal>,
+    pub episode: Option<i64>,
+    /// Language of the text, detected on insert unless given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// JWT subject of the caller who submitted the quote. Only they and admins may change it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    /// The exchange line by line, for quotes that are a dialogue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<Line>>,
+    /// The quote text with the words of a `?q=` search marked, set only on search results.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<String>,
+}
+
+/// A quote borrowed from its row, for writing large lists out without copying every column
+/// into an owned [`Quote`] first. Serializes like a [`Quote`] without `lines`.
+#[serde_as]
+#[derive(Serialize)]
+pub struct QuoteRef<'a> {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "rowid_is_internal")]
+    rowid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slug: Option<&'a str>,
+    quote: Option<&'a str>,
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    characters: Option<Vec<&'a str>>,
+    /// As the database prints it, which is how [`Decimal`] serializes too.
+    stardate: Option<&'a str>,
+    episode: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_by: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    highlight: Option<String>,
+}
+
+impl<'a> QuoteRef<'a> {
+    /// Reads a quote from a row selected with [`text_columns`].
+    fn from_row(row: &'a Row, statement: &'static str) -> Result<QuoteRef<'a>, DbError> {
+        let mapping = |e: tokio_postgres::Error| DbError::mapping(statement, e);
+        Ok(QuoteRef {
+            rowid: row.try_get("rowid").map_err(mapping)?,
+            uuid: row.try_get("uuid").map_err(mapping)?,
+            slug: row.try_get("slug").map_err(mapping)?,
+            quote: row.try_get("quote").map_err(mapping)?,
+            characters: row.try_get("characters").map_err(mapping)?,
+            stardate: row.try_get("stardate").map_err(mapping)?,
+            episode: row.try_get("episode").map_err(mapping)?,
+            lang: row.try_get("lang").map_err(mapping)?,
+            created_by: row.try_get("created_by").map_err(mapping)?,
+            highlight: None,
+        })
+    }
+}
+
+/// One line of a dialogue, stored in `quote_lines` by its position in the exchange.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Line {
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Whether quotes are identified by `uuid` in URLs and payloads, keeping rowids internal.
+pub fn uuid_ids() -> bool {
+    config::var_or("UUID_IDS", false)
+}
+
+fn rowid_is_internal(_: &Option<i64>) -> bool {
+    uuid_ids()
+}
+
+/// The identifier a quote is addressed by in URLs: its uuid with `UUID_IDS`, otherwise its rowid.
+pub fn public_id(rowid: Option<i64>, uuid: Option<Uuid>) -> Option<String> {
+    if uuid_ids() {
+        uuid.map(|uuid| uuid.to_string())
+    } else {
+        rowid.map(|rowid| rowid.to_string())
+    }
+}
+
+impl Quote {
+    pub fn public_id(&self) -> Option<String> {
+        public_id(self.rowid, self.uuid)
+    }
+
+    /// The characters as one comma-separated string, matching the `characters_text` column.
+    pub fn characters_text(&self) -> Option<String> {
+        self.characters
+            .as_ref()
+            .filter(|characters| !characters.is_empty())
+            .map(|characters| characters.join(", "))
+    }
+
+    /// Fills `quote` and `characters` from `lines` when only the dialogue was given, so
+    /// clients reading the simple shape still see the whole exchange.
+    pub fn fill_from_lines(&mut self) {
+        let lines = match &self.lines {
+            Some(lines) if !lines.is_empty() => lines,
+            _ => return,
+        };
+        if self.quote.is_none() {
+            let text: Vec<String> = lines
+                .iter()
+                .map(|line| match &line.speaker {
+                    Some(speaker) => format!("{}: {}", speaker, line.text),
+                    None => line.text.clone(),
+                })
+                .collect();
+            self.quote = Some(text.join("\n"));
+        }
+        if self.characters.is_none() {
+            let mut speakers: Vec<String> = Vec::new();
+            for speaker in lines.iter().filter_map(|line| line.speaker.as_ref()) {
+                if !speakers.contains(speaker) {
+                    speakers.push(speaker.clone());
+                }
+            }
+            if !speakers.is_empty() {
+                self.characters = Some(speakers);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CharacterName {
+    pub name: String,
+    pub quotes: i64,
+}
+
+/// Quotes whose stardates fall in `[start, end)`, ordered by stardate.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TimelineBucket {
+    pub start: Decimal,
+    pub end: Decimal,
+    pub count: i64,
+    pub quotes: serde_json::Value,
+}
+
+// TNG stardates advance by 1000 per season (41xxx is season 1).
+pub const SEASON_BUCKET_SIZE: Decimal = Decimal::ONE_THOUSAND;
+
+/// Identifies a quote by what it says rather than its rowid.
+#[derive(Debug, Default)]
+pub struct NaturalKey {
+    pub episode: Option<i64>,
+    pub character: Option<String>,
+    pub stardate: Option<Decimal>,
+}
+
+/// The columns every query selects or returns for a [`Quote`]. Rows are read back by name,
+/// so this is the only place a new column has to be added.
+pub const QUOTE_COLUMNS: &[&str] = &[
+    "rowid",
+    "uuid",
+    "slug",
+    "quote",
+    "characters",
+    "stardate",
+    "episode",
+    "lang",
+    "created_by",
+];
+
+/// [`QUOTE_COLUMNS`] as a select list, qualified with `table` when the query joins others.
+pub fn columns(table: Option<&str>) -> String {
+    QUOTE_COLUMNS
+        .iter()
+        .map(|column| match table {
+            Some(table) => format!("{}.{}", table, column),
+            None => column.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The fields a `?fields=` list asks for. Only their columns are selected, along with
+/// `rowid` and `uuid`, which a quote's id and links are built from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Projection(Vec<&'static str>);
+
+impl Projection {
+    /// Parses a comma-separated list of quote fields, any of [`QUOTE_COLUMNS`] or `lines`.
+    pub fn parse(fields: &str) -> Result<Projection, String> {
+        let mut projected = Vec::new();
+        for field in fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+        {
+            let column = QUOTE_COLUMNS
+                .iter()
+                .chain(&["lines"])
+                .find(|column| **column == field)
+                .ok_or_else(|| format!("fields: {} is not a quote field.", field))?;
+            if !projected.contains(column) {
+                projected.push(*column);
+            }
+        }
+        Ok(Projection(projected))
+    }
+
+    pub fn includes(&self, field: &str) -> bool {
+        matches!(field, "rowid" | "uuid") || self.0.contains(&field)
+    }
+
+    /// The select list for the projected columns, in [`QUOTE_COLUMNS`] order.
+    pub fn columns(&self) -> String {
+        QUOTE_COLUMNS
+            .iter()
+            .filter(|column| self.includes(column))
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Reads a quote from a row with the projected columns; the others are left `None`.
+    pub fn quote_from_row(&self, row: &Row, statement: &'static str) -> Result<Quote, DbError> {
+        let mapping = |e: tokio_postgres::Error| DbError::mapping(statement, e);
+        Ok(Quote {
+            rowid: projected(row, "rowid").map_err(mapping)?,
+            uuid: projected(row, "uuid").map_err(mapping)?,
+            slug: projected(row, "slug").map_err(mapping)?,
+            quote: projected(row, "quote").map_err(mapping)?,
+            characters: projected(row, "characters").map_err(mapping)?,
+            stardate: projected(row, "stardate").map_err(mapping)?,
+            episode: projected(row, "episode").map_err(mapping)?,
+            lang: projected(row, "lang").map_err(mapping)?,
+            created_by: projected(row, "created_by").map_err(mapping)?,
+            lines: None,
+            highlight: None,
+        })
+    }
+
+    /// Drops the members of a serialized quote that were not asked for.
+    pub fn retain(&self, quote: &mut Map<String, Value>) {
+        quote.retain(|field, _| self.includes(field));
+    }
+}
+
+fn projected<'a, T: FromSql<'a>>(
+    row: &'a Row,
+    column: &str,
+) -> Result<Option<T>, tokio_postgres::Error> {
+    if row
+        .columns()
+        .iter()
+        .any(|selected| selected.name() == column)
+    {
+        row.try_get(column)
+    } else {
+        Ok(None)
+    }
+}
+
+/// [`columns`] with `stardate` as text, for reading rows into a [`QuoteRef`].
+fn text_columns() -> String {
+    QUOTE_COLUMNS
+        .iter()
+        .map(|column| match *column {
+            "stardate" => "stardate::STRING AS stardate".to_string(),
+            column => column.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Page size used when `DEFAULT_PAGE_SIZE` is unset.
+pub const PAGE_SIZE: i64 = 20;
+
+/// A cluster timestamp that every page of one listing is read at, so that pages fetched
+/// while quotes are being written neither repeat nor skip rows. It is captured with the
+/// first page and carried to the others in the `as_of` parameter of the pagination links.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsOf(String);
+
+impl AsOf {
+    /// Reads a timestamp as returned by `cluster_logical_timestamp()`: nanoseconds since the
+    /// epoch with an optional logical part after a `.`. Anything else could not be spliced
+    /// into `AS OF SYSTEM TIME` safely.
+    pub fn parse(as_of: &str) -> Option<AsOf> {
+        let (wall, logical) = as_of.split_once('.').unwrap_or((as_of, "0"));
+        let digits = |part: &str, max: usize| {
+            !part.is_empty() && part.len() <= max && part.bytes().all(|b| b.is_ascii_digit())
+        };
+        (digits(wall, 19) && digits(logical, 10)).then(|| AsOf(as_of.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// How long ago the timestamp was, `None` when it is in the future.
+    pub fn age(&self) -> Option<std::time::Duration> {
+        let wall: u64 = self.0.split('.').next()?.parse().ok()?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        now.checked_sub(std::time::Duration::from_nanos(wall))
+    }
+
+    /// The ` AS OF SYSTEM TIME` clause to read at the timestamp, empty for `None`.
+    pub fn clause(as_of: Option<&AsOf>) -> String {
+        match as_of {
+            Some(as_of) => format!(" AS OF SYSTEM TIME '{}'", as_of.0),
+            None => String::new(),
+        }
+    }
+}
+
+/// Whether list routes read every page at the timestamp of the first, from `CONSISTENT_PAGES`.
+pub fn consistent_pages() -> bool {
+    config::var_or("CONSISTENT_PAGES", true)
+}
+
+/// How long a listing's timestamp stays usable, from `PAGE_SNAPSHOT_SECS`. It has to stay
+/// well inside the cluster's garbage collection window, after which old versions are gone.
+pub fn page_snapshot_ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(config::var_or("PAGE_SNAPSHOT_SECS", 3600))
+}
+
+/// The timestamp the first page of a listing is read at.
+pub async fn snapshot_timestamp(client: &Client) -> Result<AsOf, DbError> {
+    let _subsegment = xray::sql("snapshot_timestamp");
+    let row = client
+        .query_one("SELECT cluster_logical_timestamp()::STRING;", &[])
+        .await
+        .statement("snapshot_timestamp")?;
+    let as_of: String = row
+        .try_get(0)
+        .map_err(|e| DbError::mapping("snapshot_timestamp", e))?;
+    Ok(AsOf(as_of))
+}
+
+pub fn list_quotes_sql(as_of: Option<&AsOf>) -> String {
+    list_sql(&columns(None), as_of, false)
+}
+
+fn list_sql(select: &str, as_of: Option<&AsOf>, regional: bool) -> String {
+    format!(
+        "SELECT {} FROM quotes{} WHERE ($3::STRING IS NULL OR lang = $3){} ORDER BY episode asc, rowid asc LIMIT $1 OFFSET $2;",
+        select,
+        AsOf::clause(as_of),
+        region_clause(regional, 4)
+    )
+}
+
+pub fn search_quotes_sql(as_of: Option<&AsOf>) -> String {
+    search_sql(&columns(None), as_of, false)
+}
+
+fn search_sql(select: &str, as_of: Option<&AsOf>, regional: bool) -> String {
+    format!(
+        "SELECT {} FROM quotes{} WHERE (quote % $1 OR characters_text % $1) AND ($4::STRING IS NULL OR lang = $4){} ORDER BY greatest(COALESCE(similarity(quote, $1), 0), COALESCE(similarity(characters_text, $1), 0)) DESC, rowid asc LIMIT $2 OFFSET $3;",
+        select,
+        AsOf::clause(as_of),
+        region_clause(regional, 5)
+    )
+}
+
+/// Limits a list to the quotes homed in the region passed as parameter `param`. Only used
+/// with `REGIONAL_BY_ROW`, as other tables have no `crdb_region` column.
+fn region_clause(regional: bool, param: usize) -> String {
+    match regional {
+        true => format!(" AND crdb_region = ${}::crdb_internal_region", param),
+        false => String::new(),
+    }
+}
+
+/// `params` followed by `region`, when the list is limited to one.
+fn with_region<'a>(
+    params: &[&'a (dyn ToSql + Sync)],
+    region: &'a Option<&str>,
+) -> Vec<&'a (dyn ToSql + Sync)> {
+    let mut params = params.to_vec();
+    if region.is_some() {
+        params.push(region);
+    }
+    params
+}
+
+pub fn get_quote_sql() -> String {
+    format!("SELECT {} FROM quotes WHERE rowid=$1;", columns(None))
+}
+
+/// The largest page a list request may ask for, from `MAX_PAGE_SIZE`.
+pub fn max_page_size() -> i64 {
+    config::var_or("MAX_PAGE_SIZE", 100).max(1)
+}
+
+/// The page size for a list request: the requested `limit`, or `DEFAULT_PAGE_SIZE`, clamped
+/// to `1..=MAX_PAGE_SIZE`.
+pub fn page_size(requested: Option<i64>) -> i64 {
+    requested
+        .unwrap_or_else(|| config::var_or("DEFAULT_PAGE_SIZE", PAGE_SIZE))
+        .clamp(1, max_page_size())
+}
+
+/// Reads a quote from a row that includes the [`QUOTE_COLUMNS`].
+pub fn quote_from_row(row: &Row, statement: &'static str) -> Result<Quote, DbError> {
+    let mapping = |e: tokio_postgres::Error| DbError::mapping(statement, e);
+    Ok(Quote {
+        rowid: row.try_get("rowid").map_err(mapping)?,
+        uuid: row.try_get("uuid").map_err(mapping)?,
+        slug: row.try_get("slug").map_err(mapping)?,
+        quote: row.try_get("quote").map_err(mapping)?,
+        characters: row.try_get("characters").map_err(mapping)?,
+        stardate: row.try_get("stardate").map_err(mapping)?,
+        episode: row.try_get("episode").map_err(mapping)?,
+        lang: row.try_get("lang").map_err(mapping)?,
+        created_by: row.try_get("created_by").map_err(mapping)?,
+        lines: None,
+        highlight: None,
+    })
+}
+
+/// One page of a list, possibly cut short by the invocation's time budget.
+pub struct Page {
+    pub quotes: Vec<Quote>,
+    /// The budget ran out before every row of the page was read.
+    pub truncated: bool,
+}
+
+/// Lists one page of quotes, only those in `lang` and homed in `region` when given. Pages
+/// are numbered from 1.
+pub async fn get_quotes(
+    client: &Client,
+    page: i64,
+    limit: i64,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<Page, DbError> {
+    let _subsegment = xray::sql("get_quotes");
+    let offset = (page.max(1) - 1) * limit;
+    read_page(
+        client,
+        &list_sql(&columns(None), as_of, region.is_some()),
+        &with_region(&[&limit, &offset, &lang], &region),
+        "get_quotes",
+    )
+    .await
+}
+
+/// Fuzzy-matches quote text and character names using trigram similarity, best matches first.
+pub async fn search_quotes(
+    client: &Client,
+    q: &str,
+    page: i64,
+    limit: i64,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<Page, DbError> {
+    let _subsegment = xray::sql("search_quotes");
+    let offset = (page.max(1) - 1) * limit;
+    read_page(
+        client,
+        &search_sql(&columns(None), as_of, region.is_some()),
+        &with_region(&[&q, &limit, &offset, &lang], &region),
+        "search_quotes",
+    )
+    .await
+}
+
+/// One page of a list written out as a JSON array of quotes, for responses that need
+/// nothing else from them.
+pub struct JsonPage {
+    pub json: String,
+    pub len: usize,
+    /// The budget ran out before every row of the page was read.
+    pub truncated: bool,
+}
+
+/// [`get_quotes`] written straight from the rows into JSON.
+pub async fn get_quotes_json(
+    client: &Client,
+    page: i64,
+    limit: i64,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<JsonPage, DbError> {
+    let _subsegment = xray::sql("get_quotes");
+    let offset = (page.max(1) - 1) * limit;
+    write_page(
+        client,
+        &list_sql(&text_columns(), as_of, region.is_some()),
+        &with_region(&[&limit, &offset, &lang], &region),
+        "get_quotes",
+        None,
+    )
+    .await
+}
+
+/// [`search_quotes`] written straight from the rows into JSON, with the matches of `q`
+/// highlighted.
+pub async fn search_quotes_json(
+    client: &Client,
+    q: &str,
+    page: i64,
+    limit: i64,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<JsonPage, DbError> {
+    let _subsegment = xray::sql("search_quotes");
+    let offset = (page.max(1) - 1) * limit;
+    write_page(
+        client,
+        &search_sql(&text_columns(), as_of, region.is_some()),
+        &with_region(&[&q, &limit, &offset, &lang], &region),
+        "search_quotes",
+        Some(q),
+    )
+    .await
+}
+
+/// Reads quotes as the rows arrive until they run out or the time budget does, in which case
+/// the statement is cancelled and the rows read so far are returned.
+async fn read_page(
+    client: &Client,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+    statement: &'static str,
+) -> Result<Page, DbError> {
+    let mut quotes = Vec::new();
+    let truncated = read_rows(client, sql, params, statement, |row| {
+        quotes.push(quote_from_row(row, statement)?);
+        Ok(())
+    })
+    .await?;
+    Ok(Page { quotes, truncated })
+}
+
+/// Like [`read_page`], but serializes each row as it arrives and drops it, so a page is never
+/// held as rows, quotes and JSON at once.
+async fn write_page(
+    client: &Client,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+    statement: &'static str,
+    q: Option<&str>,
+) -> Result<JsonPage, DbError> {
+    let mut json = Vec::with_capacity(4096);
+    json.push(b'[');
+    let mut len = 0;
+    let truncated = read_rows(client, sql, params, statement, |row| {
+        let mut quote = QuoteRef::from_row(row, statement)?;
+        if let (Some(q), Some(text)) = (q, quote.quote) {
+            quote.highlight = Some(highlight::highlight(text, q));
+        }
+        if len > 0 {
+            json.push(b',');
+        }
+        serde_json::to_writer(&mut json, &quote).expect("quotes serialize to JSON");
+        len += 1;
+        Ok(())
+    })
+    .await?;
+    json.push(b']');
+    Ok(JsonPage {
+        json: String::from_utf8(json).expect("serde_json writes UTF-8"),
+        len,
+        truncated,
+    })
+}
+
+/// Hands every row of `sql` to `each` as it arrives, until the rows run out or the time
+/// budget does, in which case the statement is cancelled. Returns whether the budget ran out.
+async fn read_rows(
+    client: &Client,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+    statement: &'static str,
+    mut each: impl FnMut(&Row) -> Result<(), DbError>,
+) -> Result<bool, DbError> {
+    let rows = client
+        .query_raw(sql, params.iter().map(|param| *param as &dyn ToSql))
+        .await
+        .statement(statement)?;
+    pin_mut!(rows);
+
+    loop {
+        let next = match deadline::soft_deadline() {
+            Some(soft) => match tokio::time::timeout_at(soft, rows.try_next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    deadline::cancel(client.cancel_token()).await;
+                    return Ok(true);
+                }
+            },
+            None => rows.try_next().await,
+        };
+        match next.statement(statement)? {
+            Some(row) => each(&row)?,
+            None => return Ok(false),
+        }
+    }
+}
+
+/// Suggests character names and words close to a search that matched nothing.
+pub async fn suggest(client: &Client, q: &str) -> Result<Vec<String>, DbError> {
+    let _subsegment = xray::sql("suggest");
+    let rows = client
+        .query(
+            "SELECT suggestion FROM (
+                SELECT name AS suggestion, similarity(name, $1) AS score FROM (SELECT DISTINCT unnest(characters) AS name FROM quotes) AS names
+                UNION
+                SELECT word, similarity(word, $1) FROM (
+                    SELECT DISTINCT regexp_split_to_table(lower(quote), '[^a-z0-9'']+') AS word FROM quotes
+                ) AS words WHERE length(word) > 2
+            ) AS candidates WHERE score > 0.2 ORDER BY score DESC LIMIT 5;",
+            &[&q],
+        )
+        .await.statement("suggest")?;
+
+    let mut suggestions: Vec<String> = Vec::new();
+    for row in rows {
+        let suggestion: String = row.get(0);
+        if !suggestions.contains(&suggestion) {
+            suggestions.push(suggestion);
+        }
+    }
+
+    Ok(suggestions)
+}
+
+/// How many quotes each bucket of [`related_quotes`] may contribute.
+pub struct RelatedLimits {
+    pub episode: i64,
+    pub character: i64,
+    pub similar: i64,
+}
+
+/// Quotes from the same episode, by the same character, and with similar text,
+/// merged in that order without duplicates.
+pub async fn related_quotes(
+    client: &Client,
+    quote: &Quote,
+    limits: &RelatedLimits,
+) -> Result<Vec<Quote>, DbError> {
+    let _subsegment = xray::sql("related_quotes");
+    let rowid = quote.rowid.unwrap_or_default();
+    let mut rows = Vec::new();
+
+    if let Some(episode) = quote.episode {
+        rows.extend(
+            client
+                .query(
+                    format!("SELECT {} FROM quotes WHERE episode = $1 AND rowid != $2 ORDER BY stardate, rowid LIMIT $3;", columns(None)).as_str(),
+                    &[&episode, &rowid, &limits.episode],
+                )
+                .await.statement("related_quotes")?,
+        );
+    }
+    if let Some(characters) = &quote.characters {
+        rows.extend(
+            client
+                .query(
+                    format!("SELECT {} FROM quotes WHERE characters && $1 AND rowid != $2 ORDER BY episode, rowid LIMIT $3;", columns(None)).as_str(),
+                    &[characters, &rowid, &limits.character],
+                )
+                .await.statement("related_quotes")?,
+        );
+    }
+    if let Some(text) = &quote.quote {
+        rows.extend(
+            client
+                .query(
+                    format!("SELECT {} FROM quotes WHERE quote % $1 AND rowid != $2 ORDER BY similarity(quote, $1) DESC, rowid LIMIT $3;", columns(None)).as_str(),
+                    &[text, &rowid, &limits.similar],
+                )
+                .await.statement("related_quotes")?,
+        );
+    }
+
+    let mut quotes: Vec<Quote> = Vec::new();
+    for row in rows {
+        let quote = quote_from_row(&row, "related_quotes")?;
+        if !quotes.iter().any(|q| q.rowid == quote.rowid) {
+            quotes.push(quote);
+        }
+    }
+
+    Ok(quotes)
+}
+
+/// Lists every character with the number of quotes they speak in.
+pub async fn character_names(
+    client: &Client,
+    page: i64,
+    limit: i64,
+) -> Result<Vec<CharacterName>, DbError> {
+    let _subsegment = xray::sql("character_names");
+    let offset = (page.max(1) - 1) * limit;
+    let rows = client
+        .query(
+            "SELECT name, count(*) FROM (SELECT unnest(characters) AS name FROM quotes) AS names GROUP BY name ORDER BY name LIMIT $1 OFFSET $2;",
+            &[&limit, &offset],
+        )
+        .await.statement("character_names")?;
+
+    Ok(rows
+        .iter()
+        .map(|row| CharacterName {
+            name: row.get(0),
+            quotes: row.get(1),
+        })
+        .collect())
+}
+
+/// Groups quotes with a stardate into buckets of `bucket_size` using a single query.
+pub async fn timeline(
+    client: &Client,
+    bucket_size: Decimal,
+) -> Result<Vec<TimelineBucket>, DbError> {
+    let _subsegment = xray::sql("timeline");
+    // Rowids stay out of the buckets when `UUID_IDS` keeps them internal, as they do for quotes.
+    let rowid = match uuid_ids() {
+        true => "",
+        false => "'rowid', rowid::STRING, ",
+    };
+    let rows = client
+        .query(
+            format!("SELECT floor(stardate / $1) * $1 AS bucket, count(*), jsonb_agg(jsonb_build_object({}'uuid', uuid::STRING, 'quote', quote, 'characters', CASE WHEN array_length(characters, 1) = 1 THEN to_jsonb(characters[1]) ELSE to_jsonb(characters) END, 'stardate', stardate::STRING, 'episode', episode) ORDER BY stardate, rowid) FROM quotes WHERE stardate IS NOT NULL GROUP BY bucket ORDER BY bucket;", rowid).as_str(),
+            &[&bucket_size],
+        )
+        .await.statement("timeline")?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let start: Decimal = row.get(0);
+            TimelineBucket {
+                start,
+                end: start + bucket_size,
+                count: row.get(1),
+                quotes: row.get(2),
+            }
+        })
+        .collect())
+}
+
+/// The query for [`lookup_quotes`], with every part of the key passed as a parameter.
+pub fn lookup_quotes_sql(key: &NaturalKey) -> (String, Vec<&(dyn ToSql + Sync)>) {
+    let mut clauses = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(episode) = &key.episode {
+        params.push(episode);
+        clauses.push(format!("episode = ${}", params.len()));
+    }
+    if let Some(character) = &key.character {
+        params.push(character);
+        clauses.push(format!(
+            "lower(${}) IN (SELECT lower(name) FROM unnest(characters) AS name)",
+            params.len()
+        ));
+    }
+    if let Some(stardate) = &key.stardate {
+        params.push(stardate);
+        clauses.push(format!("stardate = ${}", params.len()));
+    }
+
+    let sql = format!(
+        "SELECT {} FROM quotes WHERE {} ORDER BY rowid LIMIT 20;",
+        columns(None),
+        clauses.join(" AND ")
+    );
+    (sql, params)
+}
+
+/// Finds the quotes matching every part of the natural key that was provided.
+pub async fn lookup_quotes(client: &Client, key: &NaturalKey) -> Result<Vec<Quote>, DbError> {
+    let _subsegment = xray::sql("lookup_quotes");
+    let (sql, params) = lookup_quotes_sql(key);
+
+    client
+        .query(sql.as_str(), &params)
+        .await
+        .statement("lookup_quotes")?
+        .iter()
+        .map(|row| quote_from_row(row, "lookup_quotes"))
+        .collect()
+}
+
+pub async fn get_quote(client: &Client, rowid: i64) -> Result<Option<Quote>, DbError> {
+    get_quote_fields(client, rowid, None).await
+}
+
+/// [`get_quote`] selecting only the columns of `projection`, when given.
+pub async fn get_quote_fields(
+    client: &Client,
+    rowid: i64,
+    projection: Option<&Projection>,
+) -> Result<Option<Quote>, DbError> {
+    let _subsegment = xray::sql("get_quote");
+    let select = match projection {
+        Some(projection) => projection.columns(),
+        None => columns(None),
+    };
+    let mut row = None;
+    if let Some(region) = config::crdb_region() {
+        row = client
+            .query_opt(
+                format!(
+                    "SELECT {} FROM quotes WHERE crdb_region=$2::crdb_internal_region AND rowid=$1;",
+                    select
+                )
+                .as_str(),
+                &[&rowid, &region],
+            )
+            .await.statement("get_quote")?;
+    }
+    if row.is_none() {
+        row = client
+            .query_opt(
+                format!("SELECT {} FROM quotes WHERE rowid=$1;", select).as_str(),
+                &[&rowid],
+            )
+            .await
+            .statement("get_quote")?;
+    }
+
+    match (row, projection) {
+        (Some(row), Some(projection)) => Ok(Some(projection.quote_from_row(&row, "get_quote")?)),
+        (Some(row), None) => Ok(Some(quote_from_row(&row, "get_quote")?)),
+        (None, _) => Ok(None),
+    }
+}
+
+/// The most recently added quotes with the time they were added, newest first.
+pub async fn recent_quotes(
+    client: &Client,
+    limit: i64,
+) -> Result<Vec<(Quote, DateTime<Utc>)>, DbError> {
+    let _subsegment = xray::sql("recent_quotes");
+    let rows = client
+        .query(
+            format!(
+                "SELECT {}, created_at FROM quotes ORDER BY created_at DESC, rowid DESC LIMIT $1;",
+                columns(None)
+            )
+            .as_str(),
+            &[&limit],
+        )
+        .await
+        .statement("recent_quotes")?;
+
+    rows.iter()
+        .map(|row| {
+            let created_at = row
+                .try_get("created_at")
+                .map_err(|e| DbError::mapping("recent_quotes", e))?;
+            Ok((quote_from_row(row, "recent_quotes")?, created_at))
+        })
+        .collect()
+}
+
+/// How a read that precedes a write in the same transaction locks the rows it reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lock {
+    None,
+    /// `SELECT ... FOR UPDATE`: the row is locked until the transaction ends, so a concurrent
+    /// writer waits for it instead of both going ahead and one of them being aborted with a
+    /// serialization failure. Only meaningful inside an explicit transaction.
+    ForUpdate,
+}
+
+/// Who submitted the quote, or `None` when it has no owner or does not exist.
+pub async fn quote_owner(
+    client: &Client,
+    rowid: i64,
+    lock: Lock,
+) -> Result<Option<String>, DbError> {
+    let _subsegment = xray::sql("quote_owner");
+    let sql = match lock {
+        Lock::None => "SELECT created_by FROM quotes WHERE rowid=$1;",
+        Lock::ForUpdate => "SELECT created_by FROM quotes WHERE rowid=$1 FOR UPDATE;",
+    };
+    let row = client
+        .query_opt(sql, &[&rowid])
+        .await
+        .statement("quote_owner")?;
+    Ok(row.and_then(|row| row.get(0)))
+}
+
+/// The uuid of quote `rowid`, or `None` when it does not exist.
+pub async fn quote_uuid(client: &Client, rowid: i64) -> Result<Option<Uuid>, DbError> {
+    let _subsegment = xray::sql("quote_uuid");
+    let row = client
+        .query_opt("SELECT uuid FROM quotes WHERE rowid=$1;", &[&rowid])
+        .await
+        .statement("quote_uuid")?;
+    row.map(|row| {
+        row.try_get(0)
+            .map_err(|e| DbError::mapping("quote_uuid", e))
+    })
+    .transpose()
+}
+
+/// One page of the quotes submitted by `subject`, newest first.
+pub async fn quotes_by_creator(
+    client: &Client,
+    subject: &str,
+    page: i64,
+    limit: i64,
+) -> Result<Vec<Quote>, DbError> {
+    let _subsegment = xray::sql("quotes_by_creator");
+    let offset = (page.max(1) - 1) * limit;
+    let rows = client
+        .query(
+            format!(
+                "SELECT {} FROM quotes WHERE created_by = $1 ORDER BY created_at DESC, rowid DESC LIMIT $2 OFFSET $3;",
+                columns(None)
+            )
+            .as_str(),
+            &[&subject, &limit, &offset],
+        )
+        .await
+        .statement("quotes_by_creator")?;
+
+    rows.iter()
+        .map(|row| quote_from_row(row, "quotes_by_creator"))
+        .collect()
+}
+
+pub async fn count_by_creator(client: &Client, subject: &str) -> Result<i64, DbError> {
+    let _subsegment = xray::sql("count_by_creator");
+    let row = client
+        .query_one(
+            "SELECT count(*) FROM quotes WHERE created_by = $1;",
+            &[&subject],
+        )
+        .await
+        .statement("count_by_creator")?;
+    Ok(row.get(0))
+}
+
+pub async fn count_quotes(
+    client: &Client,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<i64, DbError> {
+    let _subsegment = xray::sql("count_quotes");
+    let row = client
+        .query_one(
+            format!(
+                "SELECT count(*) FROM quotes{} WHERE ($1::STRING IS NULL OR lang = $1){};",
+                AsOf::clause(as_of),
+                region_clause(region.is_some(), 2)
+            )
+            .as_str(),
+            &with_region(&[&lang], &region),
+        )
+        .await
+        .statement("count_quotes")?;
+    Ok(row.get(0))
+}
+
+/// The number of quotes `search_quotes` matches across all pages.
+pub async fn count_search(
+    client: &Client,
+    q: &str,
+    lang: Option<&str>,
+    as_of: Option<&AsOf>,
+    region: Option<&str>,
+) -> Result<i64, DbError> {
+    let _subsegment = xray::sql("count_search");
+    let row = client
+        .query_one(
+            format!(
+                "SELECT count(*) FROM quotes{} WHERE (quote % $1 OR characters_text % $1) AND ($2::STRING IS NULL OR lang = $2){};",
+                AsOf::clause(as_of),
+                region_clause(region.is_some(), 3)
+            )
+            .as_str(),
+            &with_region(&[&q, &lang], &region),
+        )
+        .await
+        .statement("count_search")?;
+    Ok(row.get(0))
+}
+
+pub async fn count_character_names(client: &Client) -> Result<i64, DbError> {
+    let _subsegment = xray::sql("count_character_names");
+    let row = client
+        .query_one(
+            "SELECT count(DISTINCT name) FROM (SELECT unnest(characters) AS name FROM quotes) AS names;",
+            &[],
+        )
+        .await
+        .statement("count_character_names")?;
+    Ok(row.get(0))
+}
+
+/// Public ids with the time each quote was added, in rowid order.
+pub async fn quote_timestamps(
+    client: &Client,
+    offset: i64,
+    limit: i64,
+) -> Result<Vec<(Option<String>, DateTime<Utc>)>, DbError> {
+    let _subsegment = xray::sql("quote_timestamps");
+    let rows = client
+        .query(
+            "SELECT rowid, uuid, created_at FROM quotes ORDER BY rowid LIMIT $1 OFFSET $2;",
+            &[&limit, &offset],
+        )
+        .await
+        .statement("quote_timestamps")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (public_id(row.get(0), row.get(1)), row.get(2)))
+        .collect())
+}
+
+/// The rowid behind an id taken from a URL: a uuid, a slug, or a rowid unless `UUID_IDS` keeps
+/// rowids internal. `None` when no quote has that uuid or slug.
+pub async fn resolve_id(client: &Client, id: &str) -> Result<Option<i64>, DbError> {
+    let row = match QuoteId::parse(id) {
+        QuoteId::Rowid(rowid) if !uuid_ids() => return Ok(Some(rowid)),
+        QuoteId::Rowid(_) => return Ok(None),
+        QuoteId::Uuid(uuid) => {
+            let _subsegment = xray::sql("resolve_id");
+            client
+                .query_opt("SELECT rowid FROM quotes WHERE uuid = $1;", &[&uuid])
+                .await
+                .statement("resolve_id")?
+        }
+        QuoteId::Slug(slug) => {
+            let _subsegment = xray::sql("resolve_id");
+            client
+                .query_opt("SELECT rowid FROM quotes WHERE slug = $1;", &[&slug])
+                .await
+                .statement("resolve_id")?
+        }
+    };
+    row.map(|row| {
+        row.try_get(0)
+            .map_err(|e| DbError::mapping("resolve_id", e))
+    })
+    .transpose()
+}
+
+/// `base`, or `base-2`, `base-3`, ... for the first of those no quote uses yet and no
+/// route such as `/quotes/batch` shadows.
+///
+/// A concurrent insert can still take the same slug; the unique index then rejects the
+/// second insert as a conflict.
+async fn free_slug(client: &Client, base: &str) -> Result<String, DbError> {
+    let _subsegment = xray::sql("free_slug");
+    // Slugs only contain letters, digits and `-`, so `base` has no LIKE wildcards.
+    let taken: Vec<String> = client
+        .query(
+            "SELECT slug FROM quotes WHERE slug = $1 OR slug LIKE $2;",
+            &[&base, &format!("{}-%", base)],
+        )
+        .await
+        .statement("free_slug")?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let slug = std::iter::once(base.to_string())
+        .chain((2..).map(|n| format!("{}-{}", base, n)))
+        .find(|candidate| !taken.contains(candidate) && !router::shadows_quote(candidate))
+        .unwrap_or_default();
+    Ok(slug)
+}
+
+pub async fn random_quote(client: &Client) -> Result<Option<Quote>, DbError> {
+    let _subsegment = xray::sql("random_quote");
+    let row = client
+        .query_opt(
+            format!(
+                "SELECT {} FROM quotes ORDER BY random() LIMIT 1;",
+                columns(None)
+            )
+            .as_str(),
+            &[],
+        )
+        .await
+        .statement("random_quote")?;
+
+    row.map(|row| quote_from_row(&row, "random_quote"))
+        .transpose()
+}
+
+/// Picks today's quote, preferring quotes that have not been picked before, and records it
+/// in `qotd`. Running it again on the same day returns the quote already picked.
+pub async fn quote_of_the_day(client: &Client) -> Result<Option<(String, Quote)>, DbError> {
+    let _subsegment = xray::sql("quote_of_the_day");
+    client
+        .batch_execute(
+            "INSERT INTO qotd (day, quote_rowid) SELECT current_date(), rowid FROM quotes WHERE rowid NOT IN (SELECT quote_rowid FROM qotd) ORDER BY random() LIMIT 1 ON CONFLICT (day) DO NOTHING;
+             INSERT INTO qotd (day, quote_rowid) SELECT current_date(), rowid FROM quotes ORDER BY random() LIMIT 1 ON CONFLICT (day) DO NOTHING;",
+        )
+        .await.statement("quote_of_the_day")?;
+
+    let row = client
+        .query_opt(
+            format!(
+                "SELECT qotd.day::STRING AS day, {} FROM qotd JOIN quotes ON quotes.rowid = qotd.quote_rowid WHERE qotd.day = current_date();",
+                columns(Some("quotes"))
+            )
+            .as_str(),
+            &[],
+        )
+        .await.statement("quote_of_the_day")?;
+
+    row.map(|row| {
+        let day = row
+            .try_get("day")
+            .map_err(|e| DbError::mapping("quote_of_the_day", e))?;
+        Ok((day, quote_from_row(&row, "quote_of_the_day")?))
+    })
+    .transpose()
+}
+
+/// The dialogue lines of a quote in order, empty for quotes without any.
+pub async fn get_lines(client: &Client, rowid: i64) -> Result<Vec<Line>, DbError> {
+    let _subsegment = xray::sql("get_lines");
+    let rows = client
+        .query(
+            "SELECT speaker, text FROM quote_lines WHERE quote_rowid = $1 ORDER BY position;",
+            &[&rowid],
+        )
+        .await
+        .statement("get_lines")?;
+
+    rows.iter()
+        .map(|row| {
+            let mapping = |e: tokio_postgres::Error| DbError::mapping("get_lines", e);
+            Ok(Line {
+                speaker: row.try_get(0).map_err(mapping)?,
+                text: row.try_get(1).map_err(mapping)?,
+            })
+        })
+        .collect()
+}
+
+/// A clause for [`outbox::with_event_and`] inserting `lines` for the quote in `changed`, with
+/// their values added to `params`.
+fn insert_lines_clause<'a>(
+    lines: &'a [Line],
+    positions: &'a [i64],
+    params: &mut Vec<&'a (dyn ToSql + Sync)>,
+) -> String {
+    let mut values = Vec::new();
+    for (line, position) in lines.iter().zip(positions) {
+        params.push(position);
+        params.push(&line.speaker);
+        params.push(&line.text);
+        let n = params.len();
+        values.push(format!(
+            "(${}::INT8, ${}::STRING, ${}::STRING)",
+            n - 2,
+            n - 1,
+            n
+        ));
+    }
+    format!(
+        "lines AS (INSERT INTO quote_lines (quote_rowid, position, speaker, text) SELECT changed.rowid, line.position, line.speaker, line.text FROM changed, (VALUES {}) AS line (position, speaker, text))",
+        values.join(", ")
+    )
+}
+
+/// Inserts a quote in either the simple shape or the dialogue shape with `lines`.
+pub async fn insert_quote(client: &Client, mut new_quote: Quote) -> Result<Quote, DbError> {
+    let _subsegment = xray::sql("insert_quote");
+    sanitize::quote(&mut new_quote);
+    new_quote.fill_from_lines();
+    if new_quote.lang.is_none() {
+        new_quote.lang = new_quote.quote.as_deref().and_then(lang::detect);
+    }
+    let base = match (&new_quote.slug, &new_quote.quote) {
+        (Some(given), _) => slug::slugify(given),
+        (None, Some(text)) => slug::slugify(text),
+        (None, None) => slug::slugify(""),
+    };
+    let slug = free_slug(client, &base).await?;
+    let region = config::crdb_region();
+    let insert = match region {
+        Some(_) => format!("INSERT INTO quotes (quote, characters, stardate, episode, slug, lang, created_by, crdb_region) VALUES ($1, $2, $3, $4, $5, $6, $7, $8::crdb_internal_region) RETURNING {};", columns(None)),
+        None => format!("INSERT INTO quotes (quote, characters, stardate, episode, slug, lang, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {};", columns(None)),
+    };
+    // The lines go in with the quote, so a failure cannot leave one without the other.
+    let lines = new_quote.lines.as_deref().unwrap_or_default();
+    let positions: Vec<i64> = (1..=lines.len() as i64).collect();
+    let mut types = vec![
+        Type::VARCHAR,
+        Type::TEXT_ARRAY,
+        Type::NUMERIC,
+        Type::INT8,
+        Type::VARCHAR,
+        Type::VARCHAR,
+        Type::VARCHAR,
+    ];
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![
+        &new_quote.quote,
+        &new_quote.characters,
+        &new_quote.stardate,
+        &new_quote.episode,
+        &slug,
+        &new_quote.lang,
+        &new_quote.created_by,
+    ];
+    if let Some(region) = &region {
+        types.push(Type::VARCHAR);
+        params.push(region);
+    }
+    let sql = match lines.is_empty() {
+        true => outbox::with_event(&insert, "quote.created"),
+        false => outbox::with_event_and(
+            &insert,
+            "quote.created",
+            &[insert_lines_clause(lines, &positions, &mut params)],
+        ),
+    };
+    let statement = client
+        .prepare_typed(&sql, &types)
+        .await
+        .statement("insert_quote")?;
+
+    let row = client
+        .query_opt(&statement, &params)
+        .await
+        .statement("insert_quote")?
+        .ok_or(DbError::NoRowReturned {
+            statement: "insert_quote",
+        })?;
+
+    let mut quote = quote_from_row(&row, "insert_quote")?;
+    if !lines.is_empty() {
+        quote.lines = Some(lines.to_vec());
+    }
+
+    Ok(quote)
+}
+
+/// The `UPDATE` setting the fields present in `quote`, with every value passed as a parameter.
+/// `None` when no field is present, as there is nothing to set.
+pub fn update_quote_sql<'a>(
+    rowid: &'a i64,
+    quote: &'a Quote,
+) -> Option<(String, Vec<&'a (dyn ToSql + Sync)>)> {
+    let mut builder = string_builder::Builder::default();
+    builder.append("UPDATE quotes SET ");
+    let mut cols = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(q) = &quote.quote {
+        params.push(q);
+        cols.push(format!("quote=${}", params.len()));
+    }
+    if let Some(q) = &quote.characters {
+        params.push(q);
+        cols.push(format!("characters=${}::STRING[]", params.len()));
+    }
+    if let Some(q) = &quote.episode {
+        params.push(q);
+        cols.push(format!("episode=${}", params.len()));
+    }
+    if let Some(q) = &quote.stardate {
+        params.push(q);
+        cols.push(format!("stardate=${}", params.len()));
+    }
+    if cols.is_empty() {
+        return None;
+    }
+    params.push(rowid);
+    builder.append(cols.join(", "));
+    builder.append(format!(" WHERE rowid=${}", params.len()));
+    builder.append(format!(" RETURNING {};", columns(None)));
+    Some((builder.string().unwrap(), params))
+}
+
+pub async fn update_quote(
+    client: &Client,
+    rowid: i64,
+    mut quote: Quote,
+) -> Result<Option<Quote>, DbError> {
+    let _subsegment = xray::sql("update_quote");
+    sanitize::quote(&mut quote);
+    // An empty patch changes nothing, so the quote is returned as it is.
+    let (sql, params) = match update_quote_sql(&rowid, &quote) {
+        Some(update) => update,
+        None => return get_quote(client, rowid).await,
+    };
+
+    let sql = outbox::with_event(&sql, "quote.updated");
+    let statement = client.prepare(&sql).await.statement("update_quote")?;
+
+    let row = client
+        .query_opt(&statement, &params)
+        .await
+        .statement("update_quote")?;
+
+    match row {
+        Some(row) => {
+            let quote = quote_from_row(&row, "update_quote")?;
+            Ok(Some(quote))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn delete_quote(client: &Client, rowid: i64) -> Result<u64, DbError> {
+    let _subsegment = xray::sql("delete_quote");
+    // The lines go with the quote in the same statement.
+    let statement = client
+        .prepare_typed(
+            &outbox::with_event_and(
+                "DELETE FROM quotes WHERE rowid = $1 RETURNING rowid, uuid, slug",
+                "quote.deleted",
+                &[String::from(
+                    "lines AS (DELETE FROM quote_lines WHERE quote_rowid = $1)",
+                )],
+            ),
+            &[Type::INT8],
+        )
+        .await
+        .statement("delete_quote")?;
+
+    let res = client
+        .execute(&statement, &[&rowid])
+        .await
+        .statement("delete_quote")?;
+
+    Ok(res)
+}
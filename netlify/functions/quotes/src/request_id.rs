@@ -0,0 +1,63 @@
+//! The id of the current request: the caller's `X-Request-Id` when it is a plausible id,
+//! otherwise the Lambda request id, which is a UUID. It is sent back in `X-Request-Id` and
+//! attached to log records, X-Ray subsegments, and the statements and outbox events of
+//! changes.
+//!
+//! Like the timings, it lives in a static rather than being threaded through every handler.
+
+use std::sync::Mutex;
+
+use http::HeaderMap;
+
+static CURRENT: Mutex<Option<String>> = Mutex::new(None);
+
+// Long enough for UUIDs, ULIDs and trace ids with a prefix.
+const MAX_LEN: usize = 128;
+
+/// Whether `id` may be used as a request id. Only letters, digits and `-`, `_`, `.` and `:`
+/// are allowed, so it can be written into log lines and SQL without escaping.
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b':'))
+}
+
+/// Starts the request with the id from `X-Request-Id`, or `fallback` when it is missing or
+/// not valid.
+pub fn begin(headers: &HeaderMap, fallback: &str) -> String {
+    let id = headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| is_valid(id))
+        .unwrap_or(fallback)
+        .to_string();
+    *CURRENT.lock().unwrap() = Some(id.clone());
+    id
+}
+
+pub fn current() -> Option<String> {
+    CURRENT.lock().unwrap().clone()
+}
+
+pub fn end() {
+    *CURRENT.lock().unwrap() = None;
+}
+
+/// `statement` with a leading `/* request_id=<id> */` comment during a request.
+pub fn tag(statement: &str) -> String {
+    match current() {
+        Some(id) => format!("/* request_id={} */ {}", id, statement),
+        None => statement.to_string(),
+    }
+}
+
+/// The id as a SQL string literal, or `NULL` outside a request.
+pub fn literal() -> String {
+    match current() {
+        Some(id) => format!("'{}'", id),
+        None => String::from("NULL"),
+    }
+}
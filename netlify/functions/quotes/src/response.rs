@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use aws_lambda_events::{encodings::Body, event::apigw::ApiGatewayProxyResponse};
+use http::header::{HeaderMap, HeaderValue, ALLOW, CONTENT_TYPE, RETRY_AFTER};
+
+use crate::db::DbError;
+use crate::{breaker, config};
+
+// The errors scanners and broken clients trigger most, serialized once. Keys are in the order
+// `problem` writes them.
+const ROUTE_NOT_FOUND: &str = r#"{"detail":"No route matches the requested path.","status":404,"title":"Not Found","type":"about:blank"}"#;
+const ROWID_REQUIRED: &str = "rowid is required";
+
+// 405 bodies and `Allow` values by the methods a route takes, each built on first use.
+type MethodNotAllowed = HashMap<Vec<&'static str>, (String, Option<HeaderValue>)>;
+static METHOD_NOT_ALLOWED: Mutex<Option<MethodNotAllowed>> = Mutex::new(None);
+
+pub fn new(status_code: i64, headers: HeaderMap, body: Body) -> ApiGatewayProxyResponse {
+    ApiGatewayProxyResponse {
+        status_code,
+        headers,
+        multi_value_headers: HeaderMap::new(),
+        body: Some(body),
+        is_base64_encoded: Some(false),
+    }
+}
+
+pub fn body(status_code: i64, content_type: &'static str, body: String) -> ApiGatewayProxyResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    new(status_code, headers, Body::Text(body))
+}
+
+pub fn json(status_code: i64, body: String) -> ApiGatewayProxyResponse {
+    self::body(status_code, "application/json", body)
+}
+
+pub fn text(status_code: i64, body: impl Into<String>) -> ApiGatewayProxyResponse {
+    self::body(status_code, "text/plain", body.into())
+}
+
+/// An RFC 7807 `application/problem+json` response.
+pub fn problem(status_code: i64, title: &str, detail: &str) -> ApiGatewayProxyResponse {
+    problem_with(status_code, title, detail, serde_json::json!({}))
+}
+
+/// A problem response with extension members merged into the problem object.
+pub fn problem_with(
+    status_code: i64,
+    title: &str,
+    detail: &str,
+    extensions: serde_json::Value,
+) -> ApiGatewayProxyResponse {
+    let mut body = serde_json::json!({
+        "type": "about:blank",
+        "title": title,
+        "status": status_code,
+        "detail": detail,
+    });
+    if let (Some(body), serde_json::Value::Object(extensions)) = (body.as_object_mut(), extensions)
+    {
+        body.extend(extensions);
+    }
+    self::body(status_code, "application/problem+json", body.to_string())
+}
+
+pub fn not_found(detail: &str) -> ApiGatewayProxyResponse {
+    problem(404, "Not Found", detail)
+}
+
+/// The 404 for a path no route matches.
+pub fn route_not_found() -> ApiGatewayProxyResponse {
+    body(404, "application/problem+json", ROUTE_NOT_FOUND.to_string())
+}
+
+/// The 400 for a request that needs a rowid and has none.
+pub fn rowid_required() -> ApiGatewayProxyResponse {
+    text(400, ROWID_REQUIRED)
+}
+
+pub fn method_not_allowed(allowed: &[&'static str]) -> ApiGatewayProxyResponse {
+    let (text, allow) = {
+        let mut cache = METHOD_NOT_ALLOWED.lock().unwrap();
+        let cache = cache.get_or_insert_with(HashMap::new);
+        if !cache.contains_key(allowed) {
+            cache.insert(allowed.to_vec(), method_not_allowed_parts(allowed));
+        }
+        cache[allowed].clone()
+    };
+    let mut resp = body(405, "application/problem+json", text);
+    if let Some(allow) = allow {
+        resp.headers.insert(ALLOW, allow);
+    }
+    resp
+}
+
+/// The body and `Allow` value of the 405 for a route taking `allowed`.
+fn method_not_allowed_parts(allowed: &[&str]) -> (String, Option<HeaderValue>) {
+    let allow = allowed.join(", ");
+    // Method names are tokens, so the body is written out without escaping.
+    let body = format!(
+        r#"{{"detail":"This resource only supports {}.","status":405,"title":"Method Not Allowed","type":"about:blank"}}"#,
+        allow
+    );
+    (body, HeaderValue::from_str(&allow).ok())
+}
+
+pub fn empty(status_code: i64) -> ApiGatewayProxyResponse {
+    new(status_code, HeaderMap::new(), Body::Empty)
+}
+
+pub fn service_unavailable(retry_after: Duration) -> ApiGatewayProxyResponse {
+    with_retry_after(text(503, "Service Unavailable"), retry_after)
+}
+
+/// Sets `Retry-After` to `delay` rounded up to whole seconds, plus up to `RETRY_JITTER_SECS`
+/// of random jitter so that clients turned away together do not all come back together.
+pub fn with_retry_after(
+    mut resp: ApiGatewayProxyResponse,
+    delay: Duration,
+) -> ApiGatewayProxyResponse {
+    let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+    let secs = secs + jitter(config::var_or("RETRY_JITTER_SECS", 2));
+    resp.headers.insert(RETRY_AFTER, HeaderValue::from(secs));
+    resp
+}
+
+fn jitter(max_secs: u64) -> u64 {
+    let mut bytes = [0u8; 8];
+    match openssl::rand::rand_bytes(&mut bytes) {
+        Ok(()) => u64::from_le_bytes(bytes) % (max_secs + 1),
+        Err(_) => 0,
+    }
+}
+
+/// A problem response for a database failure, with enough context to find it in the logs.
+pub fn db_error(error: &DbError) -> ApiGatewayProxyResponse {
+    match error {
+        DbError::Connect { .. } | DbError::Tls(_) => with_retry_after(
+            problem(503, "Service Unavailable", "The database is unavailable."),
+            breaker::retry_after(),
+        ),
+        // Serialization conflicts clear as soon as the competing transaction finishes.
+        DbError::Serialization { statement, .. } => with_retry_after(
+            problem(
+                503,
+                "Service Unavailable",
+                &format!(
+                    "{} conflicted with a concurrent transaction; retry the request.",
+                    statement
+                ),
+            ),
+            Duration::from_secs(1),
+        ),
+        DbError::Timeout { statement, .. } => problem(
+            504,
+            "Gateway Timeout",
+            &format!("{} did not finish in time.", statement),
+        ),
+        DbError::Constraint { statement, .. } => problem_with(
+            409,
+            "Conflict",
+            &format!("{} violates a database constraint.", statement),
+            serde_json::json!({ "sqlstate": error.code().map(|code| code.code()) }),
+        ),
+        DbError::NoRowReturned { statement } => problem(
+            500,
+            "Internal Server Error",
+            &format!("The database returned no row for {}.", statement),
+        ),
+        DbError::Mapping { statement, .. } | DbError::Query { statement, .. } => problem(
+            500,
+            "Internal Server Error",
+            &format!("The database request for {} failed.", statement),
+        ),
+    }
+}
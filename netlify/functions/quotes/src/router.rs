@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use http::Method;
+use uuid::Uuid;
+
+use crate::auth::Scope;
+
+// Prefixes the function can be reached under: its own URL and the `/api/*` rewrite.
+const BASE_PATHS: &[&str] = &["/.netlify/functions/quotes", "/api"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Quotes,
+    Batch,
+    Transact,
+    Import,
+    JobStatus,
+    RelatedQuotes,
+    Share,
+    ShareLink,
+    Timeline,
+    Lookup,
+    Feed,
+    Sitemap,
+    CharacterNames,
+    MyQuotes,
+    MyUsage,
+    SlackQuote,
+    AdminExplain,
+    AdminSchema,
+    AdminPool,
+    AdminRepair,
+    AdminSnapshot,
+    AdminBackup,
+    AdminBackupStatus,
+    AdminPurge,
+    AdminCluster,
+    AdminSelfcheck,
+    AdminIndexes,
+    AdminTypes,
+    AdminUsage,
+    #[cfg(feature = "graphql")]
+    GraphQL,
+}
+
+/// Who may call a route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    /// Authenticated by the handler itself, such as Slack's request signature.
+    Public,
+    /// `quotes:read` for `GET`, `quotes:write` for every other method.
+    Quotes,
+    Admin,
+}
+
+impl Access {
+    fn scope(self, method: &Method) -> Option<Scope> {
+        match self {
+            Access::Public => None,
+            Access::Quotes if method == Method::GET => Some(Scope::QuotesRead),
+            Access::Quotes => Some(Scope::QuotesWrite),
+            Access::Admin => Some(Scope::Admin),
+        }
+    }
+}
+
+struct Route {
+    pattern: &'static str,
+    methods: &'static [&'static str],
+    endpoint: Endpoint,
+    access: Access,
+}
+
+static ROUTES: &[Route] = &[
+    Route {
+        pattern: "/",
+        methods: &["GET", "POST", "PUT", "DELETE"],
+        endpoint: Endpoint::Quotes,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes",
+        methods: &["GET", "POST", "PUT", "DELETE"],
+        endpoint: Endpoint::Quotes,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/batch",
+        methods: &["POST", "PUT"],
+        endpoint: Endpoint::Batch,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes:transact",
+        methods: &["POST"],
+        endpoint: Endpoint::Transact,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/import",
+        methods: &["POST"],
+        endpoint: Endpoint::Import,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/timeline",
+        methods: &["GET"],
+        endpoint: Endpoint::Timeline,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/lookup",
+        methods: &["GET"],
+        endpoint: Endpoint::Lookup,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/feed.xml",
+        methods: &["GET"],
+        endpoint: Endpoint::Feed,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/{rowid}/related",
+        methods: &["GET"],
+        endpoint: Endpoint::RelatedQuotes,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/{rowid}/share",
+        methods: &["GET"],
+        endpoint: Endpoint::Share,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/{rowid}/share-link",
+        methods: &["POST"],
+        endpoint: Endpoint::ShareLink,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/quotes/{rowid}",
+        methods: &["GET", "PUT", "DELETE"],
+        endpoint: Endpoint::Quotes,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/characters/names",
+        methods: &["GET"],
+        endpoint: Endpoint::CharacterNames,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/jobs/{id}",
+        methods: &["GET"],
+        endpoint: Endpoint::JobStatus,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/imports/{id}",
+        methods: &["GET"],
+        endpoint: Endpoint::JobStatus,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/me/quotes",
+        methods: &["GET"],
+        endpoint: Endpoint::MyQuotes,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/me/usage",
+        methods: &["GET"],
+        endpoint: Endpoint::MyUsage,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/slack/quote",
+        methods: &["POST"],
+        endpoint: Endpoint::SlackQuote,
+        access: Access::Public,
+    },
+    #[cfg(feature = "graphql")]
+    Route {
+        pattern: "/graphql",
+        methods: &["POST"],
+        endpoint: Endpoint::GraphQL,
+        access: Access::Quotes,
+    },
+    Route {
+        pattern: "/admin/explain",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminExplain,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/schema",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminSchema,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/pool",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminPool,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/repair",
+        methods: &["POST"],
+        endpoint: Endpoint::AdminRepair,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/snapshot",
+        methods: &["GET", "POST"],
+        endpoint: Endpoint::AdminSnapshot,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/backup",
+        methods: &["POST"],
+        endpoint: Endpoint::AdminBackup,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/backup/{job}",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminBackupStatus,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/cluster",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminCluster,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/selfcheck",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminSelfcheck,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/purge",
+        methods: &["POST"],
+        endpoint: Endpoint::AdminPurge,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/indexes",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminIndexes,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/types",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminTypes,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/admin/usage",
+        methods: &["GET"],
+        endpoint: Endpoint::AdminUsage,
+        access: Access::Admin,
+    },
+    Route {
+        pattern: "/sitemap.xml",
+        methods: &["GET"],
+        endpoint: Endpoint::Sitemap,
+        access: Access::Quotes,
+    },
+];
+
+/// A route kept for existing clients while they move to its successor.
+#[derive(Debug)]
+pub struct Deprecation {
+    /// Unix time from which the route is deprecated.
+    pub since: i64,
+    /// Unix time after which the route may be removed.
+    pub sunset: Option<i64>,
+    /// The pattern of the route replacing it.
+    pub successor: Option<&'static str>,
+}
+
+// Route metadata by pattern. Routes listed here answer with `Deprecation` and `Sunset`
+// headers and a `meta.deprecation` notice.
+static DEPRECATED: &[(&str, Deprecation)] = &[(
+    "/imports/{id}",
+    Deprecation {
+        since: 1792108800,
+        sunset: None,
+        successor: Some("/jobs/{id}"),
+    },
+)];
+
+/// The deprecation of the route `path` resolves to, if it is deprecated.
+pub fn deprecation(path: &str) -> Option<&'static Deprecation> {
+    let pattern = pattern(path)?;
+    DEPRECATED
+        .iter()
+        .find(|(deprecated, _)| *deprecated == pattern)
+        .map(|(_, deprecation)| deprecation)
+}
+
+/// The path of `successor` for a request to `path`, under the same base path and with the
+/// same parameters.
+pub fn successor_path(path: &str, successor: &str, params: &Params) -> String {
+    let base = BASE_PATHS
+        .iter()
+        .find(|base| {
+            path.strip_prefix(*base)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .copied()
+        .unwrap_or_default();
+    let successor: Vec<String> = successor
+        .split('/')
+        .map(|segment| {
+            match segment
+                .strip_prefix('{')
+                .and_then(|name| name.strip_suffix('}'))
+                .and_then(|name| params.get(name))
+            {
+                Some(value) => value.to_string(),
+                None => segment.to_string(),
+            }
+        })
+        .collect();
+    format!("{}{}", base, successor.join("/"))
+}
+
+/// Values captured by `{name}` segments of the matched pattern.
+#[derive(Debug, Default)]
+pub struct Params(HashMap<&'static str, String>);
+
+/// What the `{rowid}` segment of a quote URL refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuoteId {
+    Rowid(i64),
+    Uuid(Uuid),
+    Slug(String),
+}
+
+impl QuoteId {
+    /// Reads a uuid or an integer rowid, and takes anything else as a slug. Generated slugs
+    /// never look like either.
+    pub fn parse(segment: &str) -> QuoteId {
+        if let Ok(uuid) = segment.parse() {
+            return QuoteId::Uuid(uuid);
+        }
+        match segment.parse() {
+            Ok(rowid) => QuoteId::Rowid(rowid),
+            Err(_) => QuoteId::Slug(segment.to_string()),
+        }
+    }
+}
+
+pub enum Resolution {
+    /// The scope is the one the caller needs for this route and method, if any.
+    Matched(Endpoint, Params, Option<Scope>),
+    /// The path exists but not for this method; carries the methods it does accept.
+    MethodNotAllowed(&'static [&'static str]),
+    NotFound,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// Strips the function's base path, leaving the route path the table is matched against.
+pub fn route_path(path: &str) -> &str {
+    let path = BASE_PATHS
+        .iter()
+        .filter_map(|base| path.strip_prefix(base))
+        .find(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(path);
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+/// Resolves against the first route whose pattern matches, so literal routes listed before a
+/// `{param}` route take precedence over it.
+pub fn resolve(method: &Method, path: &str) -> Resolution {
+    let path = route_path(path);
+
+    for route in ROUTES {
+        if let Some(params) = match_pattern(route.pattern, path) {
+            if route.methods.contains(&method.as_str()) {
+                return Resolution::Matched(route.endpoint, params, route.access.scope(method));
+            }
+            return Resolution::MethodNotAllowed(route.methods);
+        }
+    }
+
+    Resolution::NotFound
+}
+
+/// The pattern of the route `path` resolves to, whatever the method.
+pub fn pattern(path: &str) -> Option<&'static str> {
+    let path = route_path(path);
+    ROUTES
+        .iter()
+        .find(|route| match_pattern(route.pattern, path).is_some())
+        .map(|route| route.pattern)
+}
+
+/// Whether `/quotes/{id}` is taken by another route, such as `/quotes/batch`, so a quote
+/// with that slug could not be addressed by it.
+pub fn shadows_quote(id: &str) -> bool {
+    pattern(&format!("/quotes/{}", id)) != Some("/quotes/{rowid}")
+}
+
+fn match_pattern(pattern: &'static str, path: &str) -> Option<Params> {
+    let mut params = HashMap::new();
+    let mut segments = path.split('/');
+
+    for expected in pattern.split('/') {
+        let segment = segments.next()?;
+        match expected.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(name) if !segment.is_empty() => {
+                params.insert(name, segment.to_string());
+            }
+            _ if expected == segment => {}
+            _ => return None,
+        }
+    }
+
+    match segments.next() {
+        Some(_) => None,
+        None => Some(Params(params)),
+    }
+}
@@ -0,0 +1,50 @@
+//! Normalizes quote text on write, so rows that look the same are stored the same.
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config;
+use crate::quotes::Quote;
+
+// Characters that render as nothing but make otherwise equal strings differ.
+const INVISIBLE: &[char] = &['\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}'];
+
+/// Normalizes every text field of `quote` unless `NORMALIZE_TEXT=false`.
+pub fn quote(quote: &mut Quote) {
+    if !config::var_or("NORMALIZE_TEXT", true) {
+        return;
+    }
+    for field in [&mut quote.quote, &mut quote.slug].into_iter().flatten() {
+        *field = text(field);
+    }
+    for character in quote.characters.iter_mut().flatten() {
+        *character = text(character);
+    }
+    for line in quote.lines.iter_mut().flatten() {
+        line.text = text(&line.text);
+        if let Some(speaker) = &mut line.speaker {
+            *speaker = text(speaker);
+        }
+    }
+}
+
+/// NFC-normalizes `value`, drops invisible characters, trims it, and collapses each run of
+/// whitespace into a single space, or a single newline when the run contains one.
+pub fn text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut pending: Option<char> = None;
+    for c in value.nfc().filter(|c| !INVISIBLE.contains(c)) {
+        if c.is_whitespace() {
+            if c == '\n' || pending.is_none() {
+                pending = Some(if c == '\n' { '\n' } else { ' ' });
+            }
+            continue;
+        }
+        if let Some(space) = pending.take() {
+            if !out.is_empty() {
+                out.push(space);
+            }
+        }
+        out.push(c);
+    }
+    out
+}
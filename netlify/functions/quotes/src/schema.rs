@@ -0,0 +1,202 @@
+//! The schema this binary expects, and a self-check that compares it with the database.
+//!
+//! Run at cold start with `SELFCHECK_ON_START=true` or through `GET /admin/selfcheck`, it
+//! reports every missing table or column and mismatched type at once, instead of the first
+//! query failing to read a row.
+
+use serde::Serialize;
+use tokio_postgres::Client;
+
+/// The number of the latest file in `migrations/`; bump it with every new migration.
+pub const SCHEMA_VERSION: i64 = 19;
+
+/// Tables and the columns the code reads or writes, with their CockroachDB types.
+const EXPECTED: &[(&str, &[(&str, &str)])] = &[
+    (
+        "quotes",
+        &[
+            ("rowid", "INT8"),
+            ("uuid", "UUID"),
+            ("slug", "STRING"),
+            ("quote", "STRING"),
+            ("characters", "STRING[]"),
+            ("characters_text", "STRING"),
+            ("stardate", "DECIMAL"),
+            ("episode", "INT8"),
+            ("lang", "STRING"),
+            ("created_by", "STRING"),
+            ("created_at", "TIMESTAMPTZ"),
+        ],
+    ),
+    (
+        "quote_lines",
+        &[
+            ("quote_rowid", "INT8"),
+            ("position", "INT8"),
+            ("speaker", "STRING"),
+            ("text", "STRING"),
+        ],
+    ),
+    (
+        "qotd",
+        &[
+            ("day", "DATE"),
+            ("quote_rowid", "INT8"),
+            ("published_at", "TIMESTAMPTZ"),
+        ],
+    ),
+    (
+        "rate_limits",
+        &[("key", "STRING"), ("last_at", "TIMESTAMPTZ")],
+    ),
+    ("schema_migrations", &[("version", "INT8")]),
+    (
+        "jobs",
+        &[
+            ("id", "UUID"),
+            ("kind", "STRING"),
+            ("status", "STRING"),
+            ("created_by", "STRING"),
+            ("idempotency_key", "STRING"),
+            ("input", "JSONB"),
+            ("progress", "JSONB"),
+            ("error", "STRING"),
+            ("steps", "INT8"),
+            ("created_at", "TIMESTAMPTZ"),
+            ("updated_at", "TIMESTAMPTZ"),
+        ],
+    ),
+    (
+        "outbox",
+        &[
+            ("id", "UUID"),
+            ("event", "STRING"),
+            ("dedupe_key", "STRING"),
+            ("payload", "JSONB"),
+            ("created_at", "TIMESTAMPTZ"),
+            ("attempts", "INT8"),
+            ("next_attempt_at", "TIMESTAMPTZ"),
+            ("delivered_at", "TIMESTAMPTZ"),
+            ("last_error", "STRING"),
+            ("request_id", "STRING"),
+        ],
+    ),
+    (
+        "api_usage",
+        &[
+            ("caller", "STRING"),
+            ("day", "DATE"),
+            ("requests", "INT8"),
+            ("rows_read", "INT8"),
+            ("rows_written", "INT8"),
+        ],
+    ),
+    (
+        "api_keys",
+        &[
+            ("caller", "STRING"),
+            ("daily_requests", "INT8"),
+            ("monthly_requests", "INT8"),
+            ("daily_writes", "INT8"),
+            ("monthly_writes", "INT8"),
+        ],
+    ),
+];
+
+/// Secondary indexes the queries rely on, by table, as created in `migrations/`.
+pub const INDEXES: &[(&str, &str)] = &[
+    ("quotes", "quotes_quote_trgm_idx"),
+    ("quotes", "quotes_characters_text_trgm_idx"),
+    ("quotes", "quotes_characters_idx"),
+    ("quotes", "quotes_created_at_idx"),
+    ("quotes", "quotes_uuid_idx"),
+    ("quotes", "quotes_slug_idx"),
+    ("quotes", "quotes_lang_idx"),
+    ("quotes", "quotes_created_by_idx"),
+    ("quotes", "quotes_episode_idx"),
+    ("quotes", "quotes_episode_stardate_idx"),
+    ("quotes", "quotes_stardate_idx"),
+    ("outbox", "outbox_due_idx"),
+    ("api_usage", "api_usage_day_idx"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub expected_version: i64,
+    /// The highest version in `schema_migrations`, if the table exists.
+    pub found_version: Option<i64>,
+    pub problems: Vec<String>,
+}
+
+/// Compares the database with [`EXPECTED`] and [`SCHEMA_VERSION`].
+pub async fn check(client: &Client) -> Result<Report, tokio_postgres::Error> {
+    let tables: Vec<&str> = EXPECTED.iter().map(|(table, _)| *table).collect();
+    let rows = client
+        .query(
+            "SELECT table_name, column_name, crdb_sql_type FROM information_schema.columns WHERE table_schema = 'public' AND table_name = ANY($1);",
+            &[&tables],
+        )
+        .await?;
+    let found: Vec<(String, String, String)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+
+    let mut problems = Vec::new();
+    for (table, columns) in EXPECTED {
+        if !found.iter().any(|(t, _, _)| t == table) {
+            problems.push(format!("table {} is missing", table));
+            continue;
+        }
+        for (column, expected) in columns.iter() {
+            match found.iter().find(|(t, c, _)| t == table && c == column) {
+                None => problems.push(format!("{}.{} is missing", table, column)),
+                Some((_, _, actual)) if family(actual) != family(expected) => problems.push(
+                    format!("{}.{} is {}, expected {}", table, column, actual, expected),
+                ),
+                Some(_) => {}
+            }
+        }
+    }
+
+    let found_version = match found.iter().any(|(t, _, _)| t == "schema_migrations") {
+        true => client
+            .query_one("SELECT max(version) FROM schema_migrations;", &[])
+            .await?
+            .get(0),
+        false => None,
+    };
+    if found_version != Some(SCHEMA_VERSION) {
+        problems.push(format!(
+            "schema version is {}, expected {}",
+            found_version.map_or(String::from("unknown"), |v| v.to_string()),
+            SCHEMA_VERSION
+        ));
+    }
+
+    Ok(Report {
+        ok: problems.is_empty(),
+        expected_version: SCHEMA_VERSION,
+        found_version,
+        problems,
+    })
+}
+
+/// Groups CockroachDB type names that the driver reads the same way, so `VARCHAR(100)` and
+/// `STRING` or `INT` and `INT8` both match.
+fn family(sql_type: &str) -> String {
+    let sql_type = sql_type.to_ascii_uppercase();
+    let (base, array) = match sql_type.strip_suffix("[]") {
+        Some(base) => (base, "[]"),
+        None => (sql_type.as_str(), ""),
+    };
+    let base = base.split('(').next().unwrap_or(base).trim();
+    let base = match base {
+        "STRING" | "TEXT" | "VARCHAR" | "CHAR" | "CHARACTER VARYING" => "STRING",
+        "INT" | "INT8" | "INTEGER" | "BIGINT" => "INT8",
+        "DECIMAL" | "NUMERIC" => "DECIMAL",
+        other => other,
+    };
+    format!("{}{}", base, array)
+}
@@ -0,0 +1,304 @@
+//! Renders quotes in the representation negotiated from the `Accept` header.
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use http::header::{HeaderMap, HeaderValue, ACCEPT, LINK, LOCATION};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::links::{self, Links};
+use crate::quotes::{self, CharacterName, Projection, Quote, TimelineBucket};
+use crate::{response, timing, xray};
+
+pub const JSON_API: &str = "application/vnd.api+json";
+
+// About the size of one serialized quote, for sizing response bodies up front.
+const QUOTE_BYTES: usize = 320;
+
+/// Extra information about a list response, rendered under `meta`.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct Meta {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// The page size actually used, after defaulting and clamping the requested `limit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_applied: Option<i64>,
+    /// The time budget ran out and the page holds only the rows read until then.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    JsonApi,
+    /// Only quotes and lists of quotes have a protobuf form; other responses fall back to
+    /// plain JSON.
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Format {
+    pub fn negotiate(headers: &HeaderMap) -> Format {
+        let accepts = |wanted: &str| {
+            headers
+                .get_all(ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|media_type| media_type.trim().starts_with(wanted))
+        };
+
+        #[cfg(feature = "protobuf")]
+        if accepts(crate::protobuf::PROTOBUF) {
+            return Format::Protobuf;
+        }
+        if accepts(JSON_API) {
+            Format::JsonApi
+        } else {
+            Format::Json
+        }
+    }
+}
+
+pub fn quote(
+    format: Format,
+    status_code: i64,
+    quote: &Option<Quote>,
+    links: &Links,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    match format {
+        Format::Json => document(format, status_code, quote, links, None, QUOTE_BYTES),
+        Format::JsonApi => document(
+            format,
+            status_code,
+            quote.as_ref().map(resource),
+            links,
+            None,
+            QUOTE_BYTES,
+        ),
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => Ok(crate::protobuf::quote(
+            status_code,
+            quote.as_ref(),
+            links,
+            None,
+        )),
+    }
+}
+
+/// A quote read with `?fields=`, rendered with only the fields asked for and its id.
+pub fn projected_quote(
+    format: Format,
+    status_code: i64,
+    quote: &Quote,
+    projection: &Projection,
+    links: &Links,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    match format {
+        Format::Json => {
+            let mut data = json!(quote);
+            if let Some(data) = data.as_object_mut() {
+                projection.retain(data);
+            }
+            document(format, status_code, data, links, None, QUOTE_BYTES)
+        }
+        Format::JsonApi => {
+            let mut data = resource(quote);
+            if let Some(attributes) = data["attributes"].as_object_mut() {
+                projection.retain(attributes);
+            }
+            document(format, status_code, data, links, None, QUOTE_BYTES)
+        }
+        // Fields that were not selected are unset, which protobuf leaves out.
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => Ok(crate::protobuf::quote(
+            status_code,
+            Some(quote),
+            links,
+            None,
+        )),
+    }
+}
+
+/// A `201 Created` response for a new quote, with its absolute URL in `Location` and `meta.location`.
+pub fn created(
+    format: Format,
+    quote: &Quote,
+    links: &Links,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let meta = Meta {
+        location: Some(links.self_link.clone()),
+        ..Meta::default()
+    };
+    let mut resp = match format {
+        Format::Json => document(format, 201, quote, links, Some(&meta), QUOTE_BYTES)?,
+        Format::JsonApi => document(
+            format,
+            201,
+            resource(quote),
+            links,
+            Some(&meta),
+            QUOTE_BYTES,
+        )?,
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => crate::protobuf::quote(201, Some(quote), links, Some(&meta)),
+    };
+    if let Ok(location) = HeaderValue::from_str(&links.self_link) {
+        resp.headers.insert(LOCATION, location);
+    }
+    Ok(resp)
+}
+
+pub fn quotes(
+    format: Format,
+    status_code: i64,
+    quotes: &[Quote],
+    links: &Links,
+    meta: &Meta,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let capacity = (quotes.len() + 2) * QUOTE_BYTES;
+    match format {
+        Format::Json => document(format, status_code, quotes, links, Some(meta), capacity),
+        Format::JsonApi => {
+            let resources: Vec<Value> = quotes.iter().map(resource).collect();
+            document(format, status_code, resources, links, Some(meta), capacity)
+        }
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => Ok(crate::protobuf::quotes(status_code, quotes, links, meta)),
+    }
+}
+
+/// A list whose `data` is already a JSON array, such as a [`quotes::JsonPage`]. Plain JSON
+/// only.
+pub fn quotes_json(
+    status_code: i64,
+    data: &str,
+    links: &Links,
+    meta: &Meta,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let _subsegment = xray::subsegment("serialize");
+    let body = timing::measure("serialize", || {
+        let links = serde_json::to_string(links)?;
+        let meta = serde_json::to_string(meta)?;
+        let mut body = String::with_capacity(data.len() + links.len() + meta.len() + 30);
+        body.push_str("{\"data\":");
+        body.push_str(data);
+        body.push_str(",\"links\":");
+        body.push_str(&links);
+        body.push_str(",\"meta\":");
+        body.push_str(&meta);
+        body.push('}');
+        Ok::<_, serde_json::Error>(body)
+    })?;
+    Ok(with_link(response::json(status_code, body), links))
+}
+
+pub fn character_names(
+    format: Format,
+    status_code: i64,
+    names: &[CharacterName],
+    links: &Links,
+    meta: &Meta,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let capacity = (names.len() + 2) * 64;
+    match format {
+        Format::JsonApi => {
+            let resources: Vec<Value> = names
+                .iter()
+                .map(|name| {
+                    json!({
+                        "type": "character-names",
+                        "id": name.name,
+                        "attributes": { "quotes": name.quotes },
+                    })
+                })
+                .collect();
+            document(format, status_code, resources, links, Some(meta), capacity)
+        }
+        _ => document(format, status_code, names, links, Some(meta), capacity),
+    }
+}
+
+pub fn timeline(
+    format: Format,
+    status_code: i64,
+    buckets: &[TimelineBucket],
+    links: &Links,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let capacity = (buckets.len() + 2) * 128;
+    match format {
+        Format::JsonApi => {
+            let resources: Vec<Value> = buckets
+                .iter()
+                .map(|bucket| {
+                    json!({
+                        "type": "timeline-buckets",
+                        "id": bucket.start.to_string(),
+                        "attributes": bucket,
+                    })
+                })
+                .collect();
+            document(format, status_code, resources, links, None, capacity)
+        }
+        _ => document(format, status_code, buckets, links, None, capacity),
+    }
+}
+
+/// The envelope every JSON response shares.
+#[derive(Serialize)]
+struct Document<'a, T> {
+    data: T,
+    links: &'a Links,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<&'a Meta>,
+}
+
+/// Serializes the envelope in one pass into a body of about `capacity` bytes, instead of
+/// building it as a `Value` first and holding both at once.
+fn document<T: Serialize>(
+    format: Format,
+    status_code: i64,
+    data: T,
+    links: &Links,
+    meta: Option<&Meta>,
+    capacity: usize,
+) -> Result<ApiGatewayProxyResponse, serde_json::Error> {
+    let _subsegment = xray::subsegment("serialize");
+    let body = timing::measure("serialize", || {
+        let mut body = Vec::with_capacity(capacity);
+        serde_json::to_writer(&mut body, &Document { data, links, meta })?;
+        Ok::<_, serde_json::Error>(String::from_utf8(body).expect("serde_json writes UTF-8"))
+    })?;
+
+    let resp = match format {
+        Format::JsonApi => response::body(status_code, JSON_API, body),
+        _ => response::json(status_code, body),
+    };
+    Ok(with_link(resp, links))
+}
+
+fn with_link(mut resp: ApiGatewayProxyResponse, links: &Links) -> ApiGatewayProxyResponse {
+    if let Some(link) = links::header(links).and_then(|link| HeaderValue::from_str(&link).ok()) {
+        resp.headers.insert(LINK, link);
+    }
+    resp
+}
+
+fn resource(quote: &Quote) -> Value {
+    // Serialize through `Quote` so attributes use the same shapes as plain JSON.
+    let mut attributes = json!(quote);
+    if let Some(attributes) = attributes.as_object_mut() {
+        attributes.remove("rowid");
+        if quotes::uuid_ids() {
+            attributes.remove("uuid");
+        }
+    }
+    json!({
+        "type": "quotes",
+        "id": quote.public_id(),
+        "attributes": attributes,
+    })
+}
@@ -0,0 +1,77 @@
+//! Ready-to-paste renderings of a quote for copy/paste and social-sharing bots.
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use http::header::{HeaderMap, HeaderValue, ACCEPT, VARY};
+
+use crate::quotes::Quote;
+use crate::response;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShareFormat {
+    Text,
+    Markdown,
+}
+
+impl ShareFormat {
+    /// Markdown when the client accepts `text/markdown`, plain text otherwise.
+    pub fn negotiate(headers: &HeaderMap) -> ShareFormat {
+        let wants_markdown = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_type| media_type.trim().starts_with("text/markdown"));
+
+        if wants_markdown {
+            ShareFormat::Markdown
+        } else {
+            ShareFormat::Text
+        }
+    }
+}
+
+/// `"Make it so." — Picard, Episode 42, stardate 41153.7`
+pub fn text(quote: &Quote) -> String {
+    format!(
+        "\"{}\" — {}",
+        quote.quote.as_deref().unwrap_or_default(),
+        attribution(quote)
+    )
+}
+
+pub fn markdown(quote: &Quote) -> String {
+    format!(
+        "> {}\n>\n> — {}",
+        quote
+            .quote
+            .as_deref()
+            .unwrap_or_default()
+            .replace('\n', "\n> "),
+        attribution(quote)
+    )
+}
+
+pub fn response(format: ShareFormat, quote: &Quote) -> ApiGatewayProxyResponse {
+    let mut resp = match format {
+        ShareFormat::Text => response::body(200, "text/plain; charset=utf-8", text(quote)),
+        ShareFormat::Markdown => {
+            response::body(200, "text/markdown; charset=utf-8", markdown(quote))
+        }
+    };
+    resp.headers
+        .insert(VARY, HeaderValue::from_static("Accept"));
+    resp
+}
+
+fn attribution(quote: &Quote) -> String {
+    let mut parts = vec![quote
+        .characters_text()
+        .unwrap_or_else(|| String::from("Unknown"))];
+    if let Some(episode) = quote.episode {
+        parts.push(format!("Episode {}", episode));
+    }
+    if let Some(stardate) = quote.stardate {
+        parts.push(format!("stardate {}", stardate));
+    }
+    parts.join(", ")
+}
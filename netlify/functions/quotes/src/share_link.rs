@@ -0,0 +1,66 @@
+//! Signed, expiring links that let anyone holding them read one quote without credentials.
+//!
+//! A token is the expiry time and an HMAC-SHA256 of the quote's public id and that time,
+//! keyed by `SHARE_LINK_SECRET`, so links can be checked without a database lookup.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{auth, config};
+
+fn secret() -> Option<String> {
+    std::env::var("SHARE_LINK_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+/// The longest a link may stay valid, in seconds.
+pub fn max_ttl() -> i64 {
+    config::var_or("SHARE_LINK_MAX_TTL_SECS", 30 * 24 * 60 * 60)
+}
+
+/// How long a link stays valid: `ttl` seconds, or `SHARE_LINK_TTL_SECS`, at most
+/// [`max_ttl`].
+pub fn ttl(requested: Option<i64>) -> Duration {
+    let secs = requested.unwrap_or_else(|| config::var_or("SHARE_LINK_TTL_SECS", 24 * 60 * 60));
+    Duration::seconds(secs.clamp(1, max_ttl()))
+}
+
+/// A token for the quote `id` that expires after `ttl`, or `None` when share links are not
+/// configured.
+pub fn token(id: &str, ttl: Duration) -> Option<(String, DateTime<Utc>)> {
+    let secret = secret()?;
+    let expires_at = Utc::now() + ttl;
+    let signature = sign(&secret, id, expires_at.timestamp())?;
+    Some((
+        format!("{}.{}", expires_at.timestamp(), signature),
+        expires_at,
+    ))
+}
+
+/// Whether `token` was issued for the quote `id` and has not expired yet.
+pub fn verify(id: &str, token: &str) -> bool {
+    let secret = match secret() {
+        Some(secret) => secret,
+        None => return false,
+    };
+    let (expires_at, signature) = match token.split_once('.') {
+        Some((expires_at, signature)) => (expires_at, signature),
+        None => return false,
+    };
+    let expires_at: i64 = match expires_at.parse() {
+        Ok(expires_at) => expires_at,
+        Err(_) => return false,
+    };
+    if expires_at <= Utc::now().timestamp() {
+        return false;
+    }
+    match sign(&secret, id, expires_at) {
+        Some(expected) => auth::constant_time_eq(signature.as_bytes(), expected.as_bytes()),
+        None => false,
+    }
+}
+
+fn sign(secret: &str, id: &str, expires_at: i64) -> Option<String> {
+    let mac = auth::hmac_sha256(secret, format!("{}.{}", id, expires_at).as_bytes()).ok()?;
+    Some(auth::hex(&mac))
+}
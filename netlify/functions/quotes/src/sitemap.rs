@@ -0,0 +1,102 @@
+//! `sitemap.xml` listing the URL of every quote.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use http::header::{HeaderValue, CACHE_CONTROL};
+use lambda_runtime::Error;
+use tokio_postgres::Client;
+
+use crate::db::DbError;
+use crate::{feed, links, quotes, response};
+
+// The sitemaps protocol allows at most 50,000 URLs per file.
+const URLS_PER_SITEMAP: i64 = 50_000;
+
+/// Serves a single sitemap while the quotes fit in one, and a sitemap index pointing at
+/// `?page=N` sitemaps once they do not.
+pub async fn handle(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let page = match event.query_string_parameters.first("page") {
+        Some(page) => match page.parse::<i64>() {
+            Ok(page) => Some(page.max(1)),
+            Err(_) => {
+                return Ok(response::problem(
+                    400,
+                    "Bad Request",
+                    "page must be an integer.",
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let xml = match page {
+        Some(page) => urlset(event, client, page).await?,
+        None => {
+            let total = quotes::count_quotes(client, None, None, None).await?;
+            if total > URLS_PER_SITEMAP {
+                index(event, (total + URLS_PER_SITEMAP - 1) / URLS_PER_SITEMAP)
+            } else {
+                urlset(event, client, 1).await?
+            }
+        }
+    };
+
+    let mut resp = response::body(200, "application/xml; charset=utf-8", xml);
+    resp.headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=3600"),
+    );
+    Ok(resp)
+}
+
+fn index(event: &ApiGatewayProxyRequest, pages: i64) -> String {
+    let sitemap_url = links::request_url(event);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for page in 1..=pages {
+        xml.push_str(&format!(
+            "  <sitemap><loc>{}?page={}</loc></sitemap>\n",
+            feed::escape(&sitemap_url),
+            page
+        ));
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+async fn urlset(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    page: i64,
+) -> Result<String, DbError> {
+    let entries =
+        quotes::quote_timestamps(client, (page - 1) * URLS_PER_SITEMAP, URLS_PER_SITEMAP).await?;
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for (id, created_at) in entries {
+        let id = match id {
+            Some(id) => id,
+            None => continue,
+        };
+        xml.push_str(&format!(
+            "  <url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            feed::escape(&quote_url(event, &id)),
+            feed::timestamp(&created_at)
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    Ok(xml)
+}
+
+/// The page for a quote: `SITEMAP_QUOTE_URL` with the quote's public id substituted for `{id}`
+/// (or the older `{rowid}`), so a front-end can advertise its own pages, or the quote's
+/// canonical API URL.
+fn quote_url(event: &ApiGatewayProxyRequest, id: &str) -> String {
+    match std::env::var("SITEMAP_QUOTE_URL") {
+        Ok(template) => template.replace("{id}", id).replace("{rowid}", id),
+        Err(_) => links::for_quote(event, Some(id.to_string())).self_link,
+    }
+}
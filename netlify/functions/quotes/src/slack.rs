@@ -0,0 +1,132 @@
+//! The `/quote` Slack slash command.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use lambda_runtime::Error;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde_json::{json, Value};
+use tokio_postgres::Client;
+
+use crate::quotes::{self, Quote};
+use crate::{auth, response};
+
+// Slack recommends rejecting requests signed more than five minutes ago to prevent replays.
+const MAX_SKEW_SECS: u64 = 5 * 60;
+
+pub async fn handle(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let body = match raw_body(event) {
+        Some(body) => body,
+        None => return Ok(response::text(400, "Bad Request")),
+    };
+    if !verify(event, &body)? {
+        return Ok(response::text(401, "Unauthorized"));
+    }
+
+    let text = form_urlencoded::parse(&body)
+        .find(|(key, _)| key == "text")
+        .map(|(_, value)| value.trim().to_string())
+        .unwrap_or_default();
+
+    let quote = if text.is_empty() {
+        quotes::random_quote(client).await?
+    } else {
+        quotes::search_quotes(client, &text, 1, 1, None, None, None)
+            .await?
+            .quotes
+            .into_iter()
+            .next()
+    };
+
+    let message = match quote {
+        Some(quote) => json!({ "response_type": "in_channel", "blocks": blocks(&quote) }),
+        None => json!({
+            "response_type": "ephemeral",
+            "text": format!("No quote matches \"{}\".", text),
+        }),
+    };
+    Ok(response::json(200, message.to_string()))
+}
+
+/// The form body as Slack sent it, which is what the signature covers.
+fn raw_body(event: &ApiGatewayProxyRequest) -> Option<Vec<u8>> {
+    let body = event.body.as_deref()?;
+    if event.is_base64_encoded.unwrap_or(false) {
+        openssl::base64::decode_block(body).ok()
+    } else {
+        Some(body.as_bytes().to_vec())
+    }
+}
+
+/// Checks `X-Slack-Signature` against an HMAC-SHA256 of the request keyed by `SLACK_SIGNING_SECRET`.
+fn verify(event: &ApiGatewayProxyRequest, body: &[u8]) -> Result<bool, Error> {
+    let secret = match std::env::var("SLACK_SIGNING_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        _ => return Ok(false),
+    };
+    let header = |name: &str| {
+        event
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let (timestamp, signature) = match (
+        header("x-slack-request-timestamp"),
+        header("x-slack-signature"),
+    ) {
+        (Some(timestamp), Some(signature)) => (timestamp, signature),
+        _ => return Ok(false),
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    match timestamp.parse::<u64>() {
+        Ok(sent) if now.abs_diff(sent) <= MAX_SKEW_SECS => {}
+        _ => return Ok(false),
+    }
+
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(format!("v0:{}:", timestamp).as_bytes())?;
+    signer.update(body)?;
+    let expected: String = signer
+        .sign_to_vec()?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Ok(auth::constant_time_eq(
+        signature.as_bytes(),
+        format!("v0={}", expected).as_bytes(),
+    ))
+}
+
+fn blocks(quote: &Quote) -> Value {
+    let mut context = Vec::new();
+    if let Some(episode) = quote.episode {
+        context.push(format!("Episode {}", episode));
+    }
+    if let Some(stardate) = quote.stardate {
+        context.push(format!("Stardate {}", stardate));
+    }
+
+    json!([
+        {
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!(
+                    ">{}\n— *{}*",
+                    quote.quote.as_deref().unwrap_or_default(),
+                    quote.characters_text().unwrap_or_default()
+                ),
+            },
+        },
+        {
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": context.join(" · ") }],
+        },
+    ])
+}
@@ -0,0 +1,34 @@
+//! URL slugs generated from quote text, such as `make-it-so`.
+
+use uuid::Uuid;
+
+// Long quotes are cut at a word boundary so URLs stay readable.
+const MAX_LEN: usize = 60;
+
+/// Lowercases `text` and joins its words with `-`, keeping only ASCII letters and digits.
+///
+/// A slug that would be read as a rowid or a uuid, or that is empty, is prefixed with
+/// `quote-` so the router can always tell the kinds of id apart.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for word in text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        if !slug.is_empty() && slug.len() + 1 + word.len() > MAX_LEN {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug.truncate(MAX_LEN);
+
+    if slug.is_empty() || slug.parse::<i64>().is_ok() || slug.parse::<Uuid>().is_ok() {
+        slug.insert_str(0, "quote-");
+        slug.truncate(MAX_LEN);
+        return slug.trim_end_matches('-').to_string();
+    }
+    slug
+}
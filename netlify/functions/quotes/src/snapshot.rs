@@ -0,0 +1,212 @@
+//! Versioned JSON archives of every table the service owns, for cloning environments and
+//! disaster recovery drills.
+//!
+//! An archive looks like
+//! `{"format": "quotes-snapshot", "schema_version": 19, "as_of": "...", "tables": {"quotes": [...], ...}}`
+//! with one object per row, keyed by column name. Every table is read at the `as_of` cluster
+//! timestamp, so lines and the quote of the day never point at a quote missing from the
+//! archive. Generated columns and `crdb_region` are left out so an archive restores into a
+//! cluster with other regions.
+
+use std::collections::HashMap;
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use futures::{pin_mut, TryStreamExt};
+use lambda_runtime::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::db::{DbError, StatementContext};
+use crate::quotes::{self, AsOf};
+use crate::response;
+use crate::schema::SCHEMA_VERSION;
+
+pub const FORMAT: &str = "quotes-snapshot";
+
+// Restored in this order. `rate_limits`, `jobs` and `outbox` only hold operational state and
+// `schema_migrations` describes the target database, so none of them is archived.
+const TABLES: &[&str] = &["quotes", "quote_lines", "qotd"];
+
+// Rows per `UPSERT` when restoring.
+const CHUNK_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+struct Archive {
+    format: String,
+    schema_version: i64,
+    tables: HashMap<String, Vec<Value>>,
+}
+
+/// Writes the archive row by row as the database streams them, so the rows are never held
+/// both as database rows and as JSON values.
+pub async fn export(client: &Client) -> Result<ApiGatewayProxyResponse, Error> {
+    let as_of = quotes::snapshot_timestamp(client).await?;
+    let mut body = format!(
+        "{{\"format\":\"{}\",\"schema_version\":{},\"created_at\":{},\"as_of\":\"{}\",\"tables\":{{",
+        FORMAT,
+        SCHEMA_VERSION,
+        Value::from(chrono::Utc::now().to_rfc3339()),
+        as_of.as_str(),
+    );
+
+    for (i, table) in TABLES.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        body.push_str(&format!("\"{}\":[", table));
+
+        let fields: Vec<String> = columns(client, table)
+            .await?
+            .iter()
+            .map(|column| format!("'{}', {}", column.replace('\'', "''"), ident(column)))
+            .collect();
+        let sql = format!(
+            "SELECT jsonb_build_object({})::STRING FROM {}{};",
+            fields.join(", "),
+            ident(table),
+            AsOf::clause(Some(&as_of))
+        );
+        let rows = client
+            .query_raw(sql.as_str(), std::iter::empty::<i64>())
+            .await
+            .statement("snapshot_export")?;
+        pin_mut!(rows);
+
+        let mut first = true;
+        while let Some(row) = rows.try_next().await.statement("snapshot_export")? {
+            if !first {
+                body.push(',');
+            }
+            first = false;
+            body.push_str(row.get(0));
+        }
+        body.push(']');
+    }
+    body.push_str("}}");
+
+    let mut resp = response::json(200, body);
+    resp.headers.insert(
+        http::header::CONTENT_DISPOSITION,
+        http::HeaderValue::from_static("attachment; filename=\"quotes-snapshot.json\""),
+    );
+    Ok(resp)
+}
+
+/// Restores an archive made at the current schema version, upserting every row in one
+/// transaction. `nested` uses a savepoint instead, inside a transaction the caller opened.
+pub async fn import(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    nested: bool,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let archive: Archive = match event.body.as_deref().map(serde_json::from_str) {
+        Some(Ok(archive)) => archive,
+        _ => {
+            return Ok(response::problem(
+                400,
+                "Bad Request",
+                "The request body must be a snapshot archive.",
+            ))
+        }
+    };
+    if archive.format != FORMAT {
+        return Ok(response::problem(
+            400,
+            "Bad Request",
+            &format!("The archive format must be {}.", FORMAT),
+        ));
+    }
+    if archive.schema_version != SCHEMA_VERSION {
+        return Ok(response::problem_with(
+            409,
+            "Conflict",
+            "The archive was made at another schema version; migrate one side first.",
+            serde_json::json!({
+                "archive_version": archive.schema_version,
+                "schema_version": SCHEMA_VERSION,
+            }),
+        ));
+    }
+    if let Some(unknown) = archive
+        .tables
+        .keys()
+        .find(|table| !TABLES.contains(&table.as_str()))
+    {
+        return Ok(response::problem(
+            400,
+            "Bad Request",
+            &format!("The archive contains an unknown table {}.", unknown),
+        ));
+    }
+
+    let begin = match nested {
+        true => "SAVEPOINT snapshot;",
+        false => "BEGIN;",
+    };
+    client
+        .batch_execute(begin)
+        .await
+        .statement("snapshot_import")?;
+    let restored = restore(client, &archive).await;
+    let end = match (&restored, nested) {
+        (Ok(_), true) => "RELEASE SAVEPOINT snapshot;",
+        (Ok(_), false) => "COMMIT;",
+        (Err(_), true) => "ROLLBACK TO SAVEPOINT snapshot; RELEASE SAVEPOINT snapshot;",
+        (Err(_), false) => "ROLLBACK;",
+    };
+    client
+        .batch_execute(end)
+        .await
+        .statement("snapshot_import")?;
+
+    let body = serde_json::json!({ "schema_version": SCHEMA_VERSION, "restored": restored? });
+    Ok(response::json(200, body.to_string()))
+}
+
+async fn restore(
+    client: &Client,
+    archive: &Archive,
+) -> Result<HashMap<&'static str, usize>, DbError> {
+    let mut restored = HashMap::new();
+    for table in TABLES {
+        let rows = match archive.tables.get(*table) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let columns: Vec<String> = columns(client, table)
+            .await?
+            .iter()
+            .map(|column| ident(column))
+            .collect();
+        let sql = format!(
+            "UPSERT INTO {t} ({c}) SELECT {c} FROM json_populate_recordset(NULL::{t}, $1::JSONB);",
+            t = ident(table),
+            c = columns.join(", "),
+        );
+        for chunk in rows.chunks(CHUNK_SIZE) {
+            client
+                .execute(sql.as_str(), &[&Value::from(chunk.to_vec())])
+                .await
+                .statement("snapshot_import")?;
+        }
+        restored.insert(*table, rows.len());
+    }
+    Ok(restored)
+}
+
+/// The stored columns of `table` that an archive carries, in table order.
+async fn columns(client: &Client, table: &str) -> Result<Vec<String>, DbError> {
+    let rows = client
+        .query(
+            "SELECT column_name FROM information_schema.columns WHERE table_schema = 'public' AND table_name = $1 AND is_generated = 'NEVER' AND column_name <> 'crdb_region' ORDER BY ordinal_position;",
+            &[&table],
+        )
+        .await
+        .statement("snapshot_columns")?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,138 @@
+//! Spam checks for anonymous quote submissions, each enabled by its own setting.
+
+use std::time::Duration;
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use lambda_runtime::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::db::{DbError, StatementContext};
+use crate::{config, response, xray};
+
+const HCAPTCHA_VERIFY_URL: &str = "https://hcaptcha.com/siteverify";
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+#[derive(Deserialize)]
+struct CaptchaVerdict {
+    success: bool,
+}
+
+/// Runs the honeypot, throttle and captcha checks, returning the response to send instead
+/// of inserting when one fails. Throttling needs a database connection and is skipped
+/// without one.
+pub async fn check(
+    event: &ApiGatewayProxyRequest,
+    client: Option<&Client>,
+) -> Result<Option<ApiGatewayProxyResponse>, Error> {
+    if honeypot_filled(event) {
+        return Ok(Some(rejected()));
+    }
+
+    let interval = config::var_or("SUBMISSION_INTERVAL_SECS", 0i64);
+    if let (Some(client), Some(ip), true) = (client, client_ip(event), interval > 0) {
+        if !throttle(client, &format!("submit:{}", ip), interval).await? {
+            return Ok(Some(response::with_retry_after(
+                response::problem(
+                    429,
+                    "Too Many Requests",
+                    "Submissions from this address are limited; try again later.",
+                ),
+                Duration::from_secs(interval as u64),
+            )));
+        }
+    }
+
+    if let Ok(secret) = std::env::var("CAPTCHA_SECRET") {
+        let token = event
+            .headers
+            .get("captcha-token")
+            .and_then(|value| value.to_str().ok());
+        let verified = match token {
+            Some(token) => verify_captcha(&secret, token, client_ip(event)).await?,
+            None => false,
+        };
+        if !verified {
+            return Ok(Some(response::problem(
+                403,
+                "Forbidden",
+                "A valid captcha token is required in the captcha-token header.",
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+// Bots fill in every field; people never see the one named by `HONEYPOT_FIELD`.
+fn honeypot_filled(event: &ApiGatewayProxyRequest) -> bool {
+    let field = match std::env::var("HONEYPOT_FIELD") {
+        Ok(field) => field,
+        Err(_) => return false,
+    };
+    let body: Value = match event.body.as_deref().map(serde_json::from_str) {
+        Some(Ok(body)) => body,
+        _ => return false,
+    };
+    match body.get(&field) {
+        Some(Value::Null) | None => false,
+        Some(Value::String(value)) => !value.is_empty(),
+        Some(_) => true,
+    }
+}
+
+fn rejected() -> ApiGatewayProxyResponse {
+    response::problem(422, "Unprocessable Entity", "The submission was rejected.")
+}
+
+/// The client address Netlify reports, falling back to the API Gateway source IP.
+pub fn client_ip(event: &ApiGatewayProxyRequest) -> Option<String> {
+    event
+        .headers
+        .get("x-nf-client-connection-ip")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .or_else(|| event.request_context.identity.source_ip.clone())
+}
+
+/// Records a request for `key` and returns whether at least `interval_secs` passed since the
+/// last one. A single upsert decides, so concurrent requests cannot both get through.
+pub async fn throttle(client: &Client, key: &str, interval_secs: i64) -> Result<bool, DbError> {
+    let _subsegment = xray::sql("throttle");
+    let row = client
+        .query_opt(
+            "INSERT INTO rate_limits (key, last_at) VALUES ($1, now()) ON CONFLICT (key) DO UPDATE SET last_at = now() WHERE rate_limits.last_at < now() - $2 * INTERVAL '1 second' RETURNING key;",
+            &[&key, &interval_secs],
+        )
+        .await
+        .statement("throttle")?;
+    Ok(row.is_some())
+}
+
+/// Verifies a token with hCaptcha, or with Cloudflare Turnstile when
+/// `CAPTCHA_PROVIDER=turnstile`.
+async fn verify_captcha(secret: &str, token: &str, ip: Option<String>) -> Result<bool, Error> {
+    let _subsegment = xray::remote("captcha");
+    let url = match config::var_or("CAPTCHA_PROVIDER", String::from("hcaptcha")).as_str() {
+        "turnstile" => TURNSTILE_VERIFY_URL,
+        _ => HCAPTCHA_VERIFY_URL,
+    };
+    let mut form = vec![
+        ("secret", secret.to_string()),
+        ("response", token.to_string()),
+    ];
+    if let Some(ip) = ip {
+        form.push(("remoteip", ip));
+    }
+
+    let verdict: CaptchaVerdict = reqwest::Client::new()
+        .post(url)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(verdict.success)
+}
@@ -0,0 +1,99 @@
+//! Stardates read from JSON without going through text.
+//!
+//! `rust_decimal` deserializes a JSON number by formatting the parsed `f64` and parsing the
+//! string again, which shows up in bulk imports where every item carries a stardate. The
+//! value then reaches CockroachDB as binary NUMERIC, like every other parameter.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::{self, Deserializer, Visitor};
+
+// Stardates have one place in practice; a few more cover hand-entered precision.
+const MAX_SCALE: u32 = 6;
+
+// Integers below this are exact as f64.
+const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+
+/// The decimal with the fewest places, up to six, that parses to `value`. This is what
+/// formatting `value` and parsing the text would give. `None` when more places are needed,
+/// or the value is out of range.
+pub fn from_f64(value: f64) -> Option<Decimal> {
+    if !value.is_finite() {
+        return None;
+    }
+    let mut power = 1.0;
+    for scale in 0..=MAX_SCALE {
+        let mantissa = (value * power).round();
+        if mantissa.abs() >= MAX_EXACT {
+            return None;
+        }
+        if mantissa / power == value {
+            return Some(Decimal::new(mantissa as i64, scale));
+        }
+        power *= 10.0;
+    }
+    None
+}
+
+/// Deserializes an optional stardate given as a number or a string.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(OptionVisitor)
+}
+
+struct OptionVisitor;
+
+impl<'de> Visitor<'de> for OptionVisitor {
+    type Value = Option<Decimal>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a stardate")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(StardateVisitor).map(Some)
+    }
+}
+
+struct StardateVisitor;
+
+impl<'de> Visitor<'de> for StardateVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a stardate as a number or a string")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+        match from_f64(value) {
+            Some(stardate) => Ok(stardate),
+            None => self.visit_str(&value.to_string()),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+        Decimal::from_str(value)
+            .or_else(|_| Decimal::from_scientific(value))
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
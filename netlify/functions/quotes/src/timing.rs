@@ -0,0 +1,104 @@
+//! Per-request phase timings, logged as one structured line when the request finishes.
+//!
+//! Lambda hands a process one request at a time, so the current request's timings live in
+//! a static rather than being threaded through every handler.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+
+static CURRENT: Mutex<Option<Timings>> = Mutex::new(None);
+static COLD_START: AtomicBool = AtomicBool::new(true);
+
+// Status logged for requests abandoned at the deadline, after nginx's "client closed request".
+const ABORTED: i64 = 499;
+
+struct Timings {
+    started: Instant,
+    last_mark: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    aborted: bool,
+}
+
+impl Timings {
+    fn add(&mut self, phase: &'static str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
+        }
+    }
+}
+
+pub fn start() {
+    let now = Instant::now();
+    *CURRENT.lock().unwrap() = Some(Timings {
+        started: now,
+        last_mark: now,
+        phases: Vec::new(),
+        aborted: false,
+    });
+}
+
+/// Attributes the time since the previous mark to `phase`.
+pub fn mark(phase: &'static str) {
+    if let Some(timings) = CURRENT.lock().unwrap().as_mut() {
+        let now = Instant::now();
+        let elapsed = now - timings.last_mark;
+        timings.last_mark = now;
+        timings.add(phase, elapsed);
+    }
+}
+
+/// Times `f` as `phase`, leaving that time out of the next mark.
+pub fn measure<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+    if let Some(timings) = CURRENT.lock().unwrap().as_mut() {
+        timings.last_mark += elapsed;
+        timings.add(phase, elapsed);
+    }
+    result
+}
+
+/// Marks the request as abandoned at the deadline. API Gateway has already given up on
+/// it, so its line is logged with status 499 rather than the status sent back.
+pub fn abort() {
+    if let Some(timings) = CURRENT.lock().unwrap().as_mut() {
+        timings.aborted = true;
+    }
+}
+
+/// Logs the request line with every recorded phase in milliseconds.
+pub fn finish(request_id: &str, method: &str, path: &str, status: i64) {
+    let timings = match CURRENT.lock().unwrap().take() {
+        Some(timings) => timings,
+        None => return,
+    };
+
+    let phases: Map<String, Value> = timings
+        .phases
+        .iter()
+        .map(|(phase, elapsed)| (format!("{}_ms", phase), json!(millis(*elapsed))))
+        .collect();
+    let mut line = json!({
+        "request_id": request_id,
+        "method": method,
+        "path": path,
+        "status": status,
+        "cold_start": COLD_START.swap(false, Ordering::Relaxed),
+        "total_ms": millis(timings.started.elapsed()),
+        "phases": phases,
+    });
+    if timings.aborted {
+        line["status"] = json!(ABORTED);
+        line["aborted"] = json!(true);
+    }
+    log::info!("{}", line);
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,248 @@
+//! Atomic sequences of quote operations for `POST /quotes:transact`.
+//!
+//! Later operations may refer to the quote an earlier one touched with `"rowid": "$<index>"`,
+//! so a client can insert a quote and update another in one step. The whole sequence is
+//! retried when CockroachDB aborts it with a serialization failure.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::batch::ItemResult;
+use crate::config;
+use crate::db::{DbError, StatementContext};
+use crate::isolation::{self, Isolation};
+use crate::moderation::{self, Verdict};
+use crate::quotes::{self, Lock, Quote};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Insert { quote: Value },
+    Update { rowid: Reference, quote: Value },
+    Delete { rowid: Reference },
+}
+
+/// A quote id as in a URL, or `"$<index>"` for the quote an earlier operation inserted,
+/// updated or deleted.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Reference {
+    Rowid(i64),
+    Text(String),
+}
+
+pub enum Outcome {
+    /// Every operation succeeded and the transaction committed after `attempts` tries.
+    Committed {
+        results: Vec<ItemResult>,
+        attempts: u32,
+    },
+    /// The operation that failed; nothing was written.
+    RolledBack(ItemResult),
+}
+
+pub fn max_operations() -> usize {
+    config::var_or("TRANSACT_MAX_OPERATIONS", 25)
+}
+
+/// Applies `operations` in order in one transaction, retrying it up to `TRANSACT_RETRIES`
+/// times on serialization failures.
+///
+/// `nested` runs inside a transaction the caller already opened, such as a dry run, using
+/// a savepoint instead. CockroachDB can only restart a transaction from its first
+/// savepoint, so nested runs are not retried.
+///
+/// The `cockroach_restart` savepoint protocol only applies to `SERIALIZABLE` transactions.
+/// Under `READ COMMITTED` the server already retries conflicting statements, so the rare
+/// serialization failure that still reaches the client restarts the transaction from
+/// `BEGIN`.
+pub async fn run(
+    client: &Client,
+    principal: &Principal,
+    operations: &[Operation],
+    nested: bool,
+) -> Result<Outcome, DbError> {
+    let read_committed = isolation::current() == Isolation::ReadCommitted;
+    let (begin, release, rollback, restart) = match (nested, read_committed) {
+        (true, _) => (
+            "SAVEPOINT transact;",
+            "RELEASE SAVEPOINT transact;",
+            "ROLLBACK TO SAVEPOINT transact; RELEASE SAVEPOINT transact;",
+            "",
+        ),
+        (false, false) => (
+            "BEGIN; SAVEPOINT cockroach_restart;",
+            "RELEASE SAVEPOINT cockroach_restart; COMMIT;",
+            "ROLLBACK;",
+            "ROLLBACK TO SAVEPOINT cockroach_restart;",
+        ),
+        (false, true) => ("BEGIN;", "COMMIT;", "ROLLBACK;", "ROLLBACK; BEGIN;"),
+    };
+    let retries: u32 = match nested {
+        true => 0,
+        false => config::var_or("TRANSACT_RETRIES", 5),
+    };
+
+    client.batch_execute(begin).await.statement("transact")?;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = match apply_all(client, principal, operations).await {
+            Ok(Ok(results)) => client
+                .batch_execute(release)
+                .await
+                .statement("transact")
+                .map(|_| Ok(results)),
+            other => other,
+        };
+
+        match result {
+            Ok(Ok(results)) => return Ok(Outcome::Committed { results, attempts }),
+            Err(DbError::Serialization { .. }) if attempts <= retries => {
+                log::info!("retrying transaction after serialization failure");
+                client.batch_execute(restart).await.statement("transact")?;
+            }
+            Ok(Err(failed)) => {
+                client.batch_execute(rollback).await.statement("transact")?;
+                return Ok(Outcome::RolledBack(failed));
+            }
+            Err(e) => {
+                if let Err(rollback_error) = client.batch_execute(rollback).await {
+                    log::warn!("failed to roll back transaction: {}", rollback_error);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Runs every operation, stopping at the first one that fails. The outer error is for
+/// failures that abort the whole request; the inner one names the operation that failed.
+async fn apply_all(
+    client: &Client,
+    principal: &Principal,
+    operations: &[Operation],
+) -> Result<Result<Vec<ItemResult>, ItemResult>, DbError> {
+    let mut results: Vec<ItemResult> = Vec::with_capacity(operations.len());
+    for (index, operation) in operations.iter().enumerate() {
+        let failure = |status: u16, error: String| ItemResult {
+            index,
+            status,
+            rowid: None,
+            uuid: None,
+            error: Some(error),
+        };
+
+        let outcome = match operation {
+            Operation::Insert { quote } => {
+                let mut quote = match parse(quote) {
+                    Ok(quote) => quote,
+                    Err(reason) => return Ok(Err(failure(422, reason))),
+                };
+                quote.created_by = principal.owner();
+                quotes::insert_quote(client, quote)
+                    .await
+                    .map(|quote| Some((201, quote.rowid, quote.uuid)))
+            }
+            Operation::Update { rowid, quote } => {
+                let rowid = match resolve(client, rowid, &results).await? {
+                    Ok(Some(rowid)) => rowid,
+                    Ok(None) => return Ok(Err(failure(404, missing()))),
+                    Err(reason) => return Ok(Err(failure(422, reason))),
+                };
+                let quote = match parse(quote) {
+                    Ok(quote) => quote,
+                    Err(reason) => return Ok(Err(failure(422, reason))),
+                };
+                match owned(client, principal, rowid).await? {
+                    true => quotes::update_quote(client, rowid, quote)
+                        .await
+                        .map(|updated| updated.map(|quote| (200, Some(rowid), quote.uuid))),
+                    false => return Ok(Err(failure(403, not_owner()))),
+                }
+            }
+            Operation::Delete { rowid } => {
+                let rowid = match resolve(client, rowid, &results).await? {
+                    Ok(Some(rowid)) => rowid,
+                    Ok(None) => return Ok(Err(failure(404, missing()))),
+                    Err(reason) => return Ok(Err(failure(422, reason))),
+                };
+                match owned(client, principal, rowid).await? {
+                    true => {
+                        let uuid = quotes::quote_uuid(client, rowid).await?;
+                        quotes::delete_quote(client, rowid)
+                            .await
+                            .map(|deleted| (deleted > 0).then_some((200, Some(rowid), uuid)))
+                    }
+                    false => return Ok(Err(failure(403, not_owner()))),
+                }
+            }
+        };
+
+        match outcome {
+            Ok(Some((status, rowid, uuid))) => results.push(ItemResult {
+                index,
+                status,
+                rowid,
+                uuid,
+                error: None,
+            }),
+            Ok(None) => return Ok(Err(failure(404, missing()))),
+            Err(e @ DbError::Constraint { .. }) => return Ok(Err(failure(409, e.to_string()))),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Ok(results))
+}
+
+fn parse(quote: &Value) -> Result<Quote, String> {
+    let quote: Quote = serde_json::from_value(quote.clone()).map_err(|e| e.to_string())?;
+    match moderation::check(&quote) {
+        Verdict::Reject(reason) => Err(reason),
+        _ => Ok(quote),
+    }
+}
+
+/// The rowid `reference` stands for, given the results of the operations before it. Other
+/// ids are resolved as in a URL, so `UUID_IDS` keeps raw rowids out; `None` when no quote
+/// has the id.
+async fn resolve(
+    client: &Client,
+    reference: &Reference,
+    earlier: &[ItemResult],
+) -> Result<Result<Option<i64>, String>, DbError> {
+    let id = match reference {
+        Reference::Rowid(rowid) => rowid.to_string(),
+        Reference::Text(text) => match text.strip_prefix('$') {
+            Some(index) => {
+                return Ok(index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| earlier.get(index))
+                    .and_then(|result| result.rowid)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} does not refer to an earlier operation", text)))
+            }
+            None => text.clone(),
+        },
+    };
+    Ok(Ok(quotes::resolve_id(client, &id).await?))
+}
+
+/// Whether the caller may change quote `rowid`, locking it for the rest of the transaction
+/// so that the check and the write cannot be split by a concurrent change.
+async fn owned(client: &Client, principal: &Principal, rowid: i64) -> Result<bool, DbError> {
+    let owner = quotes::quote_owner(client, rowid, Lock::ForUpdate).await?;
+    Ok(principal.may_modify(owner.as_deref()))
+}
+
+fn missing() -> String {
+    String::from("quote does not exist")
+}
+
+fn not_owner() -> String {
+    String::from("quote belongs to another caller")
+}
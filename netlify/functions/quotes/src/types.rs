@@ -0,0 +1,37 @@
+//! JSON Schema for the bodies the handlers read and write, generated from the structs they
+//! (de)serialize, for `GET /admin/types`.
+//!
+//! The front-end generates its TypeScript types from this document, for example with
+//! `json-schema-to-typescript`, so they follow the Rust structs instead of drifting from them.
+
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Value};
+
+use crate::batch::{ChunkResult, ItemResult, Mode};
+use crate::jobs::Job;
+use crate::links::Links;
+use crate::quotes::{CharacterName, Line, Quote, TimelineBucket};
+use crate::serializer::Meta;
+use crate::transact::{Operation, Reference};
+
+/// A draft-07 document with every type under `definitions`.
+pub fn schema() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    generator.subschema_for::<Quote>();
+    generator.subschema_for::<Line>();
+    generator.subschema_for::<Links>();
+    generator.subschema_for::<Meta>();
+    generator.subschema_for::<CharacterName>();
+    generator.subschema_for::<TimelineBucket>();
+    generator.subschema_for::<Mode>();
+    generator.subschema_for::<ItemResult>();
+    generator.subschema_for::<ChunkResult>();
+    generator.subschema_for::<Job>();
+    generator.subschema_for::<Operation>();
+    generator.subschema_for::<Reference>();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "definitions": generator.take_definitions(),
+    })
+}
@@ -0,0 +1,284 @@
+//! Daily usage per caller: requests, quotes read and quotes written, stored in the
+//! `api_usage` table with `USAGE_TRACKING=true`.
+//!
+//! Like the audit log, the current request's counts live in a static. Finished requests add
+//! them to totals kept by the process, which are upserted in one statement at most every
+//! `USAGE_FLUSH_SECS`, so most requests pay no extra round trip.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use chrono::{Datelike, NaiveDate, Utc};
+use lambda_runtime::Error;
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::auth::Principal;
+use crate::db::{DbError, StatementContext};
+use crate::{config, response, xray};
+
+static CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Counts {
+    pub requests: i64,
+    pub rows_read: i64,
+    pub rows_written: i64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.requests += other.requests;
+        self.rows_read += other.rows_read;
+        self.rows_written += other.rows_written;
+    }
+}
+
+struct Current {
+    caller: String,
+    counts: Counts,
+}
+
+/// Totals not yet written, by caller and UTC day.
+struct Pending {
+    totals: HashMap<(String, NaiveDate), Counts>,
+    since: Instant,
+}
+
+pub fn enabled() -> bool {
+    config::var_or("USAGE_TRACKING", false)
+}
+
+/// Starts counting the current request for an identified caller.
+pub fn begin(principal: &Principal) {
+    *CURRENT.lock().unwrap() = match (&principal.caller, enabled()) {
+        (Some(caller), true) => Some(Current {
+            caller: caller.clone(),
+            counts: Counts {
+                requests: 1,
+                ..Counts::default()
+            },
+        }),
+        _ => None,
+    };
+}
+
+/// Counts quotes returned to the caller.
+pub fn read(rows: usize) {
+    if let Some(current) = CURRENT.lock().unwrap().as_mut() {
+        current.counts.rows_read += rows as i64;
+    }
+}
+
+/// Counts quotes the caller created, updated or deleted.
+pub fn wrote(rows: usize) {
+    if let Some(current) = CURRENT.lock().unwrap().as_mut() {
+        current.counts.rows_written += rows as i64;
+    }
+}
+
+/// Drops the writes of a request whose transaction was rolled back, such as a dry run.
+pub fn rolled_back() {
+    if let Some(current) = CURRENT.lock().unwrap().as_mut() {
+        current.counts.rows_written = 0;
+    }
+}
+
+/// Adds the current request to the pending totals and returns whether they are due to be
+/// written.
+pub fn finish() -> bool {
+    let current = match CURRENT.lock().unwrap().take() {
+        Some(current) => current,
+        None => return false,
+    };
+    let day = Utc::now().naive_utc().date();
+    let mut pending = PENDING.lock().unwrap();
+    let pending = pending.get_or_insert_with(|| Pending {
+        totals: HashMap::new(),
+        since: Instant::now(),
+    });
+    pending
+        .totals
+        .entry((current.caller, day))
+        .or_default()
+        .add(current.counts);
+    pending.since.elapsed().as_secs() >= config::var_or("USAGE_FLUSH_SECS", 60)
+}
+
+/// The counts of `caller` not written yet, for today and for this month.
+pub fn pending(caller: &str) -> (Counts, Counts) {
+    let today = Utc::now().naive_utc().date();
+    let (mut day, mut month) = (Counts::default(), Counts::default());
+    if let Some(pending) = PENDING.lock().unwrap().as_ref() {
+        for ((pending_caller, pending_day), counts) in &pending.totals {
+            if pending_caller != caller
+                || (pending_day.year(), pending_day.month()) != (today.year(), today.month())
+            {
+                continue;
+            }
+            if *pending_day == today {
+                day.add(*counts);
+            }
+            month.add(*counts);
+        }
+    }
+    (day, month)
+}
+
+/// Upserts the pending totals in one statement. On failure they are kept for the next flush.
+pub async fn flush(client: &Client) -> Result<(), DbError> {
+    let pending = match PENDING.lock().unwrap().take() {
+        Some(pending) => pending,
+        None => return Ok(()),
+    };
+    let _subsegment = xray::sql("usage_flush");
+    let mut callers = Vec::new();
+    let mut days = Vec::new();
+    let mut requests = Vec::new();
+    let mut rows_read = Vec::new();
+    let mut rows_written = Vec::new();
+    for ((caller, day), counts) in &pending.totals {
+        callers.push(caller.as_str());
+        days.push(*day);
+        requests.push(counts.requests);
+        rows_read.push(counts.rows_read);
+        rows_written.push(counts.rows_written);
+    }
+    let result = client
+        .execute(
+            "INSERT INTO api_usage (caller, day, requests, rows_read, rows_written) SELECT * FROM unnest($1::STRING[], $2::DATE[], $3::INT8[], $4::INT8[], $5::INT8[]) ON CONFLICT (caller, day) DO UPDATE SET requests = api_usage.requests + excluded.requests, rows_read = api_usage.rows_read + excluded.rows_read, rows_written = api_usage.rows_written + excluded.rows_written;",
+            &[&callers, &days, &requests, &rows_read, &rows_written],
+        )
+        .await
+        .statement("usage_flush");
+    if result.is_err() {
+        let mut current = PENDING.lock().unwrap();
+        let current = current.get_or_insert_with(|| Pending {
+            totals: HashMap::new(),
+            since: pending.since,
+        });
+        for (key, counts) in pending.totals {
+            current.totals.entry(key).or_default().add(counts);
+        }
+    }
+    result.map(|_| ())
+}
+
+#[derive(Serialize)]
+struct Day {
+    day: String,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+#[derive(Serialize)]
+struct CallerTotals {
+    caller: String,
+    days: i64,
+    #[serde(flatten)]
+    counts: Counts,
+}
+
+fn days_param(event: &ApiGatewayProxyRequest) -> Result<i64, ApiGatewayProxyResponse> {
+    match event.query_string_parameters.first("days") {
+        None => Ok(30),
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) if (1..=366).contains(&days) => Ok(days),
+            _ => Err(response::problem(
+                400,
+                "Bad Request",
+                "days must be between 1 and 366.",
+            )),
+        },
+    }
+}
+
+/// `GET /me/usage`: the caller's counts for each of the last `?days=` days, newest first.
+pub async fn mine(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+    principal: &Principal,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let caller = match principal.caller.as_deref() {
+        Some(caller) => caller,
+        None => {
+            return Ok(response::problem(
+                401,
+                "Unauthorized",
+                "Credentials are required to read your usage.",
+            ))
+        }
+    };
+    let days = match days_param(event) {
+        Ok(days) => days,
+        Err(resp) => return Ok(resp),
+    };
+    let _subsegment = xray::sql("usage_mine");
+    let rows = client
+        .query(
+            "SELECT day::STRING, requests, rows_read, rows_written FROM api_usage WHERE caller = $1 AND day > current_date() - $2::INT8 ORDER BY day DESC;",
+            &[&caller, &days],
+        )
+        .await
+        .statement("usage_mine")?;
+    let mut totals = Counts::default();
+    let days: Vec<Day> = rows
+        .iter()
+        .map(|row| {
+            let counts = Counts {
+                requests: row.get(1),
+                rows_read: row.get(2),
+                rows_written: row.get(3),
+            };
+            totals.add(counts);
+            Day {
+                day: row.get(0),
+                counts,
+            }
+        })
+        .collect();
+    let body = serde_json::json!({
+        "data": { "caller": caller, "days": days, "totals": totals },
+    });
+    Ok(response::json(200, body.to_string()))
+}
+
+/// `GET /admin/usage`: every caller's totals over the last `?days=` days, busiest first.
+/// `?caller=` narrows it to one caller.
+pub async fn rollup(
+    event: &ApiGatewayProxyRequest,
+    client: &Client,
+) -> Result<ApiGatewayProxyResponse, Error> {
+    let days = match days_param(event) {
+        Ok(days) => days,
+        Err(resp) => return Ok(resp),
+    };
+    let caller = event.query_string_parameters.first("caller");
+    let _subsegment = xray::sql("usage_rollup");
+    let rows = client
+        .query(
+            "SELECT caller, count(*), sum(requests)::INT8, sum(rows_read)::INT8, sum(rows_written)::INT8 FROM api_usage WHERE day > current_date() - $1::INT8 AND ($2::STRING IS NULL OR caller = $2) GROUP BY caller ORDER BY 3 DESC;",
+            &[&days, &caller],
+        )
+        .await
+        .statement("usage_rollup")?;
+    let callers: Vec<CallerTotals> = rows
+        .iter()
+        .map(|row| CallerTotals {
+            caller: row.get(0),
+            days: row.get(1),
+            counts: Counts {
+                requests: row.get(2),
+                rows_read: row.get(3),
+                rows_written: row.get(4),
+            },
+        })
+        .collect();
+    Ok(response::json(
+        200,
+        serde_json::json!({ "data": callers }).to_string(),
+    ))
+}
@@ -0,0 +1,122 @@
+//! Request body checks run before payloads reach the repository layer.
+
+use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
+use jsonschema::JSONSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::moderation::{self, Verdict};
+use crate::quotes::Quote;
+use crate::{config, response};
+
+const QUOTE_FIELDS: &[&str] = &[
+    "rowid",
+    "uuid",
+    "slug",
+    "quote",
+    "characters",
+    "stardate",
+    "episode",
+    "lang",
+    "lines",
+];
+const QUOTE_SCHEMA: &str = include_str!("../schemas/quote.schema.json");
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Parses a quote from the request body.
+///
+/// Unknown fields are rejected with `?strict=true` or `STRICT_PAYLOADS=true`, and
+/// `VALIDATE_SCHEMA=true` checks the body against `schemas/quote.schema.json`. The quote then
+/// goes through the content filter, which can reject it.
+pub fn parse_quote(event: &ApiGatewayProxyRequest) -> Result<Quote, ApiGatewayProxyResponse> {
+    let body = match event.body.as_deref() {
+        Some(body) => body,
+        None => {
+            return Err(response::problem(
+                400,
+                "Bad Request",
+                "A JSON request body is required.",
+            ))
+        }
+    };
+    let body: Value = serde_json::from_str(body)
+        .map_err(|e| response::problem(400, "Bad Request", &e.to_string()))?;
+
+    let strict = match event.query_string_parameters.first("strict") {
+        Some(strict) => strict == "true",
+        None => config::var_or("STRICT_PAYLOADS", false),
+    };
+
+    let mut errors = Vec::new();
+    if strict {
+        errors.extend(unknown_fields(&body));
+    }
+    if config::var_or("VALIDATE_SCHEMA", false) {
+        errors.extend(schema_errors(&body));
+    }
+    if !errors.is_empty() {
+        return Err(unprocessable(&errors));
+    }
+
+    let quote: Quote = serde_json::from_value(body).map_err(|e| {
+        unprocessable(&[FieldError {
+            field: String::new(),
+            message: e.to_string(),
+        }])
+    })?;
+    match moderation::check(&quote) {
+        Verdict::Reject(reason) => Err(unprocessable(&[FieldError {
+            field: String::new(),
+            message: reason,
+        }])),
+        Verdict::Allow | Verdict::Flag(_) => Ok(quote),
+    }
+}
+
+pub fn unprocessable(errors: &[FieldError]) -> ApiGatewayProxyResponse {
+    response::problem_with(
+        422,
+        "Unprocessable Entity",
+        "The request body contains unknown or invalid fields.",
+        json!({ "invalid_fields": errors }),
+    )
+}
+
+fn unknown_fields(body: &Value) -> Vec<FieldError> {
+    // The spam honeypot is part of the form even though it is not a quote field.
+    let honeypot = std::env::var("HONEYPOT_FIELD").ok();
+    match body.as_object() {
+        Some(object) => object
+            .keys()
+            .filter(|key| !QUOTE_FIELDS.contains(&key.as_str()))
+            .filter(|key| honeypot.as_deref() != Some(key.as_str()))
+            .map(|key| FieldError {
+                field: key.clone(),
+                message: format!(
+                    "unknown field, expected one of: {}",
+                    QUOTE_FIELDS.join(", ")
+                ),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn schema_errors(body: &Value) -> Vec<FieldError> {
+    let schema: Value = serde_json::from_str(QUOTE_SCHEMA).expect("quote schema is valid JSON");
+    let schema = JSONSchema::compile(&schema).expect("quote schema compiles");
+
+    let mut errors = Vec::new();
+    if let Err(validation_errors) = schema.validate(body) {
+        errors.extend(validation_errors.map(|error| FieldError {
+            field: error.instance_path.to_string(),
+            message: error.to_string(),
+        }));
+    }
+    errors
+}
@@ -0,0 +1,68 @@
+//! Scheduled warm-up pings that keep a container and its database connections alive, and
+//! the optional eager initialization that opens them before the first request.
+
+use std::time::Instant;
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use serde_json::{json, Value};
+
+use crate::{config, db, response, usage};
+
+/// Whether the invocation is a warm-up rather than an API request: a `{"warmup": true}`
+/// payload, or an EventBridge (CloudWatch Events) schedule.
+pub fn is_warmup(payload: &Value) -> bool {
+    payload["warmup"] == true
+        || (payload["source"] == "aws.events" && payload["detail-type"] == "Scheduled Event")
+}
+
+/// Opens or revalidates the cached connections with `SELECT 1`. Failures are only logged
+/// quietly; the next real request reports them properly.
+pub async fn ping() -> ApiGatewayProxyResponse {
+    let reachable = warm_connections().await;
+    if !reachable {
+        log::info!("warm-up could not reach the database");
+    }
+    // An idle container would otherwise hold its usage counts until the next request.
+    if let (true, Ok(client)) = (reachable, db::get_db_client().await) {
+        if let Err(e) = usage::flush(&client).await {
+            log::warn!("usage counts not written yet: {}", e);
+        }
+    }
+    response::json(
+        200,
+        json!({ "warmup": true, "database": reachable }).to_string(),
+    )
+}
+
+/// With `EAGER_INIT=true`, builds the TLS connector and opens the cached connections during
+/// the Lambda init phase, so the first request does not pay for them.
+pub async fn eager_init() {
+    if !config::var_or("EAGER_INIT", false) {
+        return;
+    }
+    let started = Instant::now();
+    match warm_connections().await {
+        true => log::info!(
+            "eager initialization took {} ms",
+            started.elapsed().as_millis()
+        ),
+        false => log::warn!("eager initialization could not reach the database"),
+    }
+}
+
+/// Whether the primary, read and public connections answer `SELECT 1`.
+async fn warm_connections() -> bool {
+    let primary = match db::get_db_client().await {
+        Ok(client) => client.simple_query("SELECT 1").await.is_ok(),
+        Err(_) => false,
+    };
+    let read = match db::get_read_client().await {
+        Ok(client) => client.simple_query("SELECT 1").await.is_ok(),
+        Err(_) => false,
+    };
+    let public = match db::get_public_client().await {
+        Ok(client) => client.simple_query("SELECT 1").await.is_ok(),
+        Err(_) => false,
+    };
+    primary && read && public
+}
@@ -0,0 +1,148 @@
+//! AWS X-Ray subsegments for database and serialization work.
+//!
+//! There is no X-Ray SDK for Rust, so subsegments are sent straight to the X-Ray daemon that
+//! Lambda runs when active tracing is enabled, parented to the function's segment taken from
+//! the invocation's trace header.
+
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::request_id;
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+const DAEMON_HEADER: &str = "{\"format\": \"json\", \"version\": 1}\n";
+
+#[derive(Clone)]
+struct Trace {
+    root: String,
+    parent: String,
+}
+
+/// Starts tracing the current invocation from its `Root=...;Parent=...;Sampled=...` header.
+/// Unsampled or untraced invocations record nothing.
+pub fn begin(trace_header: Option<&str>) {
+    let mut root = None;
+    let mut parent = None;
+    let mut sampled = false;
+    for part in trace_header.unwrap_or_default().split(';') {
+        match part.split_once('=') {
+            Some(("Root", value)) => root = Some(value.to_string()),
+            Some(("Parent", value)) => parent = Some(value.to_string()),
+            Some(("Sampled", value)) => sampled = value == "1",
+            _ => {}
+        }
+    }
+
+    *TRACE.lock().unwrap() = match (root, parent, sampled) {
+        (Some(root), Some(parent), true) => Some(Trace { root, parent }),
+        _ => None,
+    };
+}
+
+pub fn end() {
+    *TRACE.lock().unwrap() = None;
+}
+
+/// A subsegment that is sent to the daemon when dropped.
+pub struct Subsegment {
+    trace: Option<Trace>,
+    name: &'static str,
+    extra: Value,
+    start_time: f64,
+}
+
+/// Traces local work such as serialization.
+pub fn subsegment(name: &'static str) -> Subsegment {
+    start(name, json!({}))
+}
+
+/// Traces a SQL statement, shown as a CockroachDB node in the service map.
+pub fn sql(statement: &'static str) -> Subsegment {
+    start(
+        "CockroachDB",
+        json!({
+            "namespace": "remote",
+            "sql": { "database_type": "CockroachDB", "driver_version": "tokio-postgres" },
+            "annotations": { "statement": statement },
+        }),
+    )
+}
+
+/// Traces connection setup to CockroachDB, such as TLS configuration or the connect handshake.
+pub fn remote(name: &'static str) -> Subsegment {
+    start(name, json!({ "namespace": "remote" }))
+}
+
+fn start(name: &'static str, mut extra: Value) -> Subsegment {
+    if let Some(id) = request_id::current() {
+        extra["annotations"]["request_id"] = json!(id);
+    }
+    Subsegment {
+        trace: TRACE.lock().unwrap().clone(),
+        name,
+        extra,
+        start_time: now(),
+    }
+}
+
+impl Drop for Subsegment {
+    fn drop(&mut self) {
+        let trace = match &self.trace {
+            Some(trace) => trace,
+            None => return,
+        };
+
+        let mut document = json!({
+            "type": "subsegment",
+            "name": self.name,
+            "id": segment_id(),
+            "trace_id": trace.root,
+            "parent_id": trace.parent,
+            "start_time": self.start_time,
+            "end_time": now(),
+        });
+        if let (Some(document), Value::Object(extra)) =
+            (document.as_object_mut(), self.extra.take())
+        {
+            document.extend(extra);
+        }
+
+        if let Err(e) = send(&format!("{}{}", DAEMON_HEADER, document)) {
+            log::debug!("failed to send X-Ray subsegment: {}", e);
+        }
+    }
+}
+
+fn send(payload: &str) -> std::io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.send_to(payload.as_bytes(), daemon_address())?;
+    Ok(())
+}
+
+/// `AWS_XRAY_DAEMON_ADDRESS` is either `host:port` or `tcp:host:port udp:host:port`.
+fn daemon_address() -> String {
+    let address =
+        std::env::var("AWS_XRAY_DAEMON_ADDRESS").unwrap_or_else(|_| "127.0.0.1:2000".into());
+    address
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix("udp:"))
+        .unwrap_or(address.as_str())
+        .to_string()
+}
+
+fn segment_id() -> String {
+    let mut bytes = [0u8; 8];
+    let _ = openssl::rand::rand_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs_f64())
+        .unwrap_or_default()
+}
@@ -0,0 +1,54 @@
+//! Chunked batches that fail between savepoints must not leave their transaction open on the
+//! cached client.
+//!
+//! Runs only when `TEST_DATABASE_URL` points at a scratch cluster with the quotes schema.
+
+use quotes_api::auth::{Principal, Scope};
+use quotes_api::batch::{self, Operation};
+
+#[test]
+fn a_failed_chunk_does_not_leave_the_transaction_open() {
+    let url = match std::env::var("TEST_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    std::env::set_var("DATABASE_URL", url);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let client = quotes_api::db::get_db_client().await.unwrap();
+        // Every statement in an explicit transaction now fails with a retry error, which
+        // CockroachDB will not roll back to the chunk's savepoint.
+        client
+            .batch_execute("SET inject_retry_errors_enabled = true;")
+            .await
+            .unwrap();
+        let principal = Principal {
+            scopes: vec![Scope::QuotesWrite],
+            authenticated: true,
+            ..Principal::default()
+        };
+        let items = vec![serde_json::json!({ "quote": "Make it so.", "characters": "Picard" })];
+
+        let outcome =
+            batch::run_chunked(&client, &principal, Operation::Insert, items, 10, false).await;
+        assert!(outcome.is_err());
+
+        // The failed batch either rolled back or dropped the client, so the next request
+        // starts outside any transaction.
+        let client = quotes_api::db::get_db_client().await.unwrap();
+        client
+            .batch_execute("SET inject_retry_errors_enabled = false;")
+            .await
+            .unwrap();
+        let status: String = client
+            .query_one("SHOW transaction_status;", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(status, "NoTxn");
+    });
+}
@@ -0,0 +1,125 @@
+//! Replays the recorded events in `tests/fixtures/` through everything the handler does
+//! before it needs the database, by calling the same [`pipeline::prepare`] it does. Requests
+//! it ends early are snapshotted as the response the handler returns; the others as the
+//! request handed to dispatch. Each `<name>.event.json` is checked against
+//! `<name>.snap.json`; run with `UPDATE_SNAPSHOTS=1` to rewrite the snapshots after an
+//! intended change.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use quotes_api::{event, pipeline};
+use serde_json::{json, Value};
+
+const ADMIN_TOKEN: &str = "contract-admin-token";
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut events: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".event.json"))
+        .collect();
+    events.sort();
+    events
+}
+
+fn replay(payload: Value) -> Value {
+    let event = match event::request(payload) {
+        Ok(event) => event,
+        Err(e) => return json!({ "error": e.to_string() }),
+    };
+    let prepared = match pipeline::prepare(event) {
+        Ok(prepared) => prepared,
+        Err(resp) => return snapshot(resp),
+    };
+    let event = &prepared.event;
+    let principal = &prepared.principal;
+
+    let query: BTreeMap<&str, Vec<&str>> =
+        event
+            .query_string_parameters
+            .iter()
+            .fold(BTreeMap::new(), |mut query, (name, value)| {
+                query.entry(name).or_default().push(value);
+                query
+            });
+    json!({
+        "dispatch": {
+            "method": event.http_method.as_str(),
+            "endpoint": format!("{:?}", prepared.endpoint),
+            "rowid": prepared.params.get("rowid"),
+            "query": query,
+            "cookie": event.headers.get("cookie").and_then(|value| value.to_str().ok()),
+            "scopes": principal.scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>(),
+            "authenticated": principal.authenticated,
+            "body": event.body,
+            "is_base64_encoded": event.is_base64_encoded.unwrap_or(false),
+        }
+    })
+}
+
+fn snapshot(resp: ApiGatewayProxyResponse) -> Value {
+    let headers: BTreeMap<&str, &str> = resp
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    let body = match &resp.body {
+        Some(Body::Text(body)) => serde_json::from_str(body).unwrap_or_else(|_| json!(body)),
+        Some(Body::Binary(body)) => json!(body),
+        _ => Value::Null,
+    };
+    json!({
+        "response": {
+            "status": resp.status_code,
+            "headers": headers,
+            "body": body,
+        }
+    })
+}
+
+#[test]
+fn recorded_events_match_their_snapshots() {
+    std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+    for name in [
+        "JWT_SECRET",
+        "API_KEYS",
+        "SIGNING_SECRETS",
+        "ANONYMOUS_SCOPES",
+    ] {
+        std::env::remove_var(name);
+    }
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+    let mut mismatched = Vec::new();
+    for path in fixtures() {
+        let payload: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let actual = replay(payload);
+        let snap = PathBuf::from(path.to_string_lossy().replace(".event.json", ".snap.json"));
+
+        let expected = fs::read_to_string(&snap)
+            .ok()
+            .and_then(|snap| serde_json::from_str::<Value>(&snap).ok());
+        if expected.as_ref() == Some(&actual) {
+            continue;
+        }
+        if update {
+            fs::write(&snap, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        } else {
+            mismatched.push(format!(
+                "{}:\n{}",
+                snap.display(),
+                serde_json::to_string_pretty(&actual).unwrap()
+            ));
+        }
+    }
+    assert!(
+        mismatched.is_empty(),
+        "responses differ from their snapshots:\n{}",
+        mismatched.join("\n")
+    );
+}
@@ -0,0 +1,58 @@
+use aws_lambda_events::{encodings::Body, event::apigw::ApiGatewayProxyResponse};
+use quotes_api::db::DbError;
+use quotes_api::response;
+
+fn problem(resp: &ApiGatewayProxyResponse) -> serde_json::Value {
+    match &resp.body {
+        Some(Body::Text(body)) => serde_json::from_str(body).unwrap(),
+        body => panic!("expected a text body, got {:?}", body),
+    }
+}
+
+#[test]
+fn no_row_returned_names_the_statement() {
+    let error = DbError::NoRowReturned {
+        statement: "insert_quote",
+    };
+    assert_eq!(error.to_string(), "insert_quote returned no row");
+    assert!(error.code().is_none());
+}
+
+#[test]
+fn no_row_returned_maps_to_500_problem_with_context() {
+    let resp = response::db_error(&DbError::NoRowReturned {
+        statement: "insert_quote",
+    });
+
+    assert_eq!(resp.status_code, 500);
+    assert_eq!(
+        resp.headers.get("content-type").unwrap(),
+        "application/problem+json"
+    );
+    let body = problem(&resp);
+    assert_eq!(body["status"], 500);
+    assert_eq!(body["title"], "Internal Server Error");
+    assert_eq!(
+        body["detail"],
+        "The database returned no row for insert_quote."
+    );
+}
+
+#[test]
+fn connection_failures_map_to_503() {
+    let connect = DbError::Connect {
+        host: Some(String::from("db.example.com:26257")),
+        source: None,
+    };
+    assert_eq!(
+        connect.to_string(),
+        "could not connect to db.example.com:26257"
+    );
+    let resp = response::db_error(&connect);
+    assert_eq!(resp.status_code, 503);
+    assert!(resp.headers.contains_key("retry-after"));
+
+    let tls = DbError::Tls("missing certificate".into());
+    assert!(tls.statement().is_none());
+    assert_eq!(response::db_error(&tls).status_code, 503);
+}
@@ -0,0 +1,202 @@
+//! Property tests for the statements built from request data. Whatever a payload holds,
+//! its values must reach the database as parameters, never as SQL text.
+//!
+//! The round trips through a real database run only when `TEST_DATABASE_URL` points at a
+//! scratch cluster with the quotes schema; they insert, update and delete their own rows.
+
+use proptest::prelude::*;
+use quotes_api::quotes::{self, AsOf, NaturalKey, Quote};
+use quotes_api::{sanitize, stardate};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Text that tends to break hand-built SQL: quotes, backslashes, comment markers,
+/// placeholders and non-ASCII characters, mixed with anything else.
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z0-9 '\"\\\\;$%_-]{0,40}",
+        "[\\PC]{0,40}",
+        Just("'; DROP TABLE quotes; --".to_string()),
+        Just("It's $1 o'clock".to_string()),
+        Just("Qapla'! 🖖 Ÿ ß 日本".to_string()),
+    ]
+}
+
+fn stardate() -> impl Strategy<Value = Decimal> {
+    (any::<i64>(), 0u32..=10).prop_map(|(mantissa, scale)| Decimal::new(mantissa, scale))
+}
+
+prop_compose! {
+    fn quote()(
+        text in proptest::option::of(text()),
+        characters in proptest::option::of(proptest::collection::vec(text(), 0..4)),
+        stardate in proptest::option::of(stardate()),
+        episode in proptest::option::of(any::<i64>()),
+    ) -> Quote {
+        Quote {
+            rowid: None,
+            uuid: None,
+            slug: None,
+            quote: text,
+            characters,
+            stardate,
+            episode,
+            lang: None,
+            created_by: None,
+            lines: None,
+            highlight: None,
+        }
+    }
+}
+
+/// Every `$n` in `sql`, in order.
+fn placeholders(sql: &str) -> Vec<usize> {
+    sql.match_indices('$')
+        .filter_map(|(at, _)| {
+            let digits: String = sql[at + 1..]
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            digits.parse().ok()
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn update_sql_only_depends_on_which_fields_are_set(quote in quote(), rowid in any::<i64>()) {
+        let update = quotes::update_quote_sql(&rowid, &quote);
+
+        let mut set = Vec::new();
+        for (present, column, cast) in [
+            (quote.quote.is_some(), "quote", ""),
+            (quote.characters.is_some(), "characters", "::STRING[]"),
+            (quote.episode.is_some(), "episode", ""),
+            (quote.stardate.is_some(), "stardate", ""),
+        ] {
+            if present {
+                set.push(format!("{}=${}{}", column, set.len() + 1, cast));
+            }
+        }
+        match update {
+            // Nothing to set, so there is no statement to run.
+            None => prop_assert!(set.is_empty()),
+            Some((sql, params)) => prop_assert_eq!(
+                sql,
+                format!(
+                    "UPDATE quotes SET {} WHERE rowid=${} RETURNING {};",
+                    set.join(", "),
+                    params.len(),
+                    quotes::columns(None)
+                )
+            ),
+        }
+    }
+
+    #[test]
+    fn update_sql_numbers_every_parameter(quote in quote(), rowid in any::<i64>()) {
+        if let Some((sql, params)) = quotes::update_quote_sql(&rowid, &quote) {
+            prop_assert_eq!(placeholders(&sql), (1..=params.len()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn lookup_sql_numbers_every_parameter(
+        episode in proptest::option::of(any::<i64>()),
+        character in proptest::option::of(text()),
+        stardate in proptest::option::of(stardate()),
+    ) {
+        let key = NaturalKey { episode, character, stardate };
+        let (sql, params) = quotes::lookup_quotes_sql(&key);
+
+        prop_assert_eq!(placeholders(&sql), (1..=params.len()).collect::<Vec<_>>());
+        prop_assert!(!sql.contains('\''));
+    }
+
+    #[test]
+    fn stardate_numbers_read_as_they_print(
+        value in prop_oneof![-1e7..1e7f64, (any::<i32>(), 0u32..=6).prop_map(|(m, s)| m as f64 / 10f64.powi(s as i32))],
+    ) {
+        let expected = Decimal::from_str(&value.to_string()).unwrap();
+        if let Some(fast) = stardate::from_f64(value) {
+            prop_assert_eq!(fast.normalize(), expected.normalize());
+        }
+        let quote: Quote = serde_json::from_value(serde_json::json!({ "stardate": value })).unwrap();
+        prop_assert_eq!(quote.stardate.map(|s| s.normalize()), Some(expected.normalize()));
+    }
+
+    #[test]
+    fn as_of_only_accepts_timestamps(as_of in "[\\PC]{0,40}|[0-9]{1,19}(\\.[0-9]{1,10})?") {
+        if let Some(parsed) = AsOf::parse(&as_of) {
+            prop_assert!(parsed.as_str().bytes().all(|b| b.is_ascii_digit() || b == b'.'));
+            let sql = quotes::list_quotes_sql(Some(&parsed));
+            prop_assert_eq!(sql.matches('\'').count(), 2);
+        }
+    }
+}
+
+prop_compose! {
+    /// A quote that passes validation, which needs the text.
+    fn new_quote()(quote in quote(), text in text()) -> Quote {
+        Quote { quote: Some(text), ..quote }
+    }
+}
+
+/// The text fields of `quote` as they are stored, after normalization.
+fn stored(mut quote: Quote) -> (Option<String>, Option<Vec<String>>, Option<i64>) {
+    sanitize::quote(&mut quote);
+    (quote.quote, quote.characters, quote.episode)
+}
+
+#[test]
+fn quotes_round_trip_through_the_database() {
+    let url = match std::env::var("TEST_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    std::env::set_var("DATABASE_URL", url);
+    // One runtime for every case, since the cached client's connection runs on it.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    proptest!(ProptestConfig::with_cases(32), |(new in new_quote(), changes in quote())| {
+        runtime.block_on(async {
+            let client = quotes_api::db::get_db_client().await.unwrap();
+
+            let inserted = quotes::insert_quote(&client, new.clone()).await.unwrap();
+            let rowid = inserted.rowid.unwrap();
+            let read = quotes::get_quote(&client, rowid).await.unwrap().unwrap();
+            assert_eq!(
+                (read.quote.clone(), read.characters.clone(), read.episode),
+                stored(new.clone())
+            );
+            assert_eq!(read.stardate.map(|s| s.normalize()), new.stardate.map(|s| s.normalize()));
+
+            let mut expected = read;
+            expected.quote = changes.quote.clone().or(expected.quote);
+            expected.characters = changes.characters.clone().or(expected.characters);
+            expected.episode = changes.episode.or(expected.episode);
+            expected.stardate = changes.stardate.or(expected.stardate);
+            if changes.quote.is_some()
+                || changes.characters.is_some()
+                || changes.episode.is_some()
+                || changes.stardate.is_some()
+            {
+                quotes::update_quote(&client, rowid, changes).await.unwrap();
+            }
+            let read = quotes::get_quote(&client, rowid).await.unwrap().unwrap();
+            assert_eq!(
+                (read.quote.clone(), read.characters.clone(), read.episode),
+                stored(expected.clone())
+            );
+            assert_eq!(
+                read.stardate.map(|s| s.normalize()),
+                expected.stardate.map(|s| s.normalize())
+            );
+
+            quotes::delete_quote(&client, rowid).await.unwrap();
+        });
+    });
+}
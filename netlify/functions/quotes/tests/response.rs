@@ -0,0 +1,36 @@
+//! The problem responses written out by hand must match what `response::problem` builds.
+
+use aws_lambda_events::encodings::Body;
+use aws_lambda_events::event::apigw::ApiGatewayProxyResponse;
+use quotes_api::response;
+
+fn text(resp: &ApiGatewayProxyResponse) -> &str {
+    match &resp.body {
+        Some(Body::Text(body)) => body,
+        other => panic!("expected a text body, got {:?}", other),
+    }
+}
+
+#[test]
+fn route_not_found_matches_problem() {
+    let expected = response::problem(404, "Not Found", "No route matches the requested path.");
+    let actual = response::route_not_found();
+    assert_eq!(actual.status_code, expected.status_code);
+    assert_eq!(actual.headers, expected.headers);
+    assert_eq!(text(&actual), text(&expected));
+}
+
+#[test]
+fn method_not_allowed_matches_problem() {
+    let expected = response::problem(
+        405,
+        "Method Not Allowed",
+        "This resource only supports GET, POST.",
+    );
+    // The second call is served from the bodies built on the first.
+    for _ in 0..2 {
+        let actual = response::method_not_allowed(&["GET", "POST"]);
+        assert_eq!(text(&actual), text(&expected));
+        assert_eq!(actual.headers.get("allow").unwrap(), "GET, POST");
+    }
+}
@@ -0,0 +1,103 @@
+//! Signed requests are verified against everything that changes what they do: the method,
+//! path, query and body. A captured request replayed with other query parameters is refused.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use quotes_api::{auth, event};
+use serde_json::{json, Value};
+
+const KEY_ID: &str = "partner";
+const SECRET: &str = "contract-signing-secret";
+const BODY: &str = r#"[{"quote":"Make it so.","characters":"Picard"}]"#;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A `POST /quotes/batch` with `query`, signed as if its query were `signed_query`.
+fn request(query: &[(&str, &str)], signed_query: &str) -> ApiGatewayProxyRequest {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .to_string();
+    let path = "/.netlify/functions/quotes/quotes/batch";
+    let signed = format!(
+        "{}\nPOST\n{}\n{}\n{}",
+        timestamp,
+        path,
+        signed_query,
+        hex(&openssl::sha::sha256(BODY.as_bytes()))
+    );
+    let key = PKey::hmac(SECRET.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(signed.as_bytes()).unwrap();
+    let signature = format!("{}:{}", KEY_ID, hex(&signer.sign_to_vec().unwrap()));
+
+    let mut single = serde_json::Map::new();
+    let mut multi = serde_json::Map::new();
+    for (name, value) in query {
+        single.insert(name.to_string(), json!(value));
+        multi
+            .entry(name.to_string())
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .unwrap()
+            .push(json!(value));
+    }
+    let headers = json!({
+        "content-type": "application/json",
+        "x-signature": signature,
+        "x-signature-timestamp": timestamp,
+    });
+    let multi_headers: serde_json::Map<String, Value> = headers
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(name, value)| (name.clone(), json!([value])))
+        .collect();
+    event::request(json!({
+        "resource": "/{proxy+}",
+        "path": path,
+        "httpMethod": "POST",
+        "headers": headers,
+        "multiValueHeaders": multi_headers,
+        "queryStringParameters": single,
+        "multiValueQueryStringParameters": multi,
+        "requestContext": { "httpMethod": "POST", "path": path, "stage": "prod" },
+        "body": BODY,
+        "isBase64Encoded": false,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn the_query_is_part_of_the_signature() {
+    std::env::set_var(
+        "SIGNING_SECRETS",
+        json!({ KEY_ID: { "secret": SECRET, "scopes": ["quotes:write"] } }).to_string(),
+    );
+
+    // Pairs are signed sorted by name, then value, whatever order they were sent in.
+    let signed = request(
+        &[("mode", "transactional"), ("dry_run", "true")],
+        "dry_run=true&mode=transactional",
+    );
+    assert_eq!(
+        auth::canonical_query(&signed),
+        "dry_run=true&mode=transactional"
+    );
+    let principal = auth::principal(&signed).unwrap();
+    assert_eq!(principal.caller.as_deref(), Some("signing:partner"));
+
+    let replayed = request(
+        &[("mode", "transactional"), ("dry_run", "false")],
+        "dry_run=true&mode=transactional",
+    );
+    assert!(auth::principal(&replayed).is_err());
+
+    let unsigned_query = request(&[("dry_run", "true")], "");
+    assert!(auth::principal(&unsigned_query).is_err());
+}